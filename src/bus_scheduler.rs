@@ -0,0 +1,349 @@
+use crate::command_payload::{DaliCommand, DaliCommandRequest, DaliTarget};
+use log::debug;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Work waiting to be executed on the DALI buses
+#[derive(Debug)]
+pub enum ScheduledWork {
+    Command(DaliCommandRequest),
+    PollLight,
+}
+
+/// Interactive commands (e.g. from a dashboard) and configuration changes are executed in the order they were received
+/// (a command may depend on a previous command for the same target), before maintenance work (polling, finding
+/// lights etc.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CommandPriority {
+    Normal,
+    Maintenance,
+}
+
+/// Command queue between parsing the commands and executing them.
+///
+/// Commands are queued by priority. A brightness command replaces a queued brightness command for the same target
+/// (e.g. a slider sending many commands per second), so only the latest value is sent to the bus. While a long
+/// operation (e.g. finding lights) is running on a bus, the bus is busy and the commands sent to it are left in the
+/// queue until the operation is done (or cancelled by a Cancel command), commands for other buses are executed.
+/// A bus is also busy while a single command is executed on it, such a command cannot be cancelled.
+#[derive(Default)]
+pub struct BusScheduler {
+    busy: HashMap<usize, Option<Arc<AtomicBool>>>, // Busy buses and the flag that cancels the operation running on them
+    queues: [VecDeque<ScheduledWork>; 2],
+}
+
+impl ScheduledWork {
+    fn bus(&self) -> Option<usize> {
+        match self {
+            ScheduledWork::Command(request) => request.command.bus(),
+            ScheduledWork::PollLight => None,
+        }
+    }
+
+    fn priority(&self) -> CommandPriority {
+        match self {
+            ScheduledWork::Command(request) => BusScheduler::get_priority(&request.command),
+            ScheduledWork::PollLight => CommandPriority::Maintenance,
+        }
+    }
+
+    // Target of a command whose effect is replaced by a later command with the same key
+    fn coalescing_key(&self) -> Option<(usize, DaliTarget)> {
+        match self {
+            ScheduledWork::Command(DaliCommandRequest { command, .. }) => match *command {
+                DaliCommand::SetLightBrightness { bus, address, .. } => {
+                    Some((bus, DaliTarget::Light(address)))
+                }
+                DaliCommand::SetGroupBrightness { bus, group, .. } => {
+                    Some((bus, DaliTarget::Group(group)))
+                }
+                DaliCommand::SetBusBrightness { bus, .. } => Some((bus, DaliTarget::Bus)),
+                _ => None,
+            },
+            ScheduledWork::PollLight => None,
+        }
+    }
+}
+
+impl BusScheduler {
+    pub fn new() -> BusScheduler {
+        BusScheduler::default()
+    }
+
+    pub fn get_priority(command: &DaliCommand) -> CommandPriority {
+        match command {
+            DaliCommand::UpdateBusStatus
+            | DaliCommand::FindAllLights { .. }
+            | DaliCommand::FindNewLights { .. }
+            | DaliCommand::AuditBus { .. }
+            | DaliCommand::ReplaceLight { .. }
+            | DaliCommand::Reconcile { .. }
+            | DaliCommand::MatchGroup { .. } => CommandPriority::Maintenance,
+
+            _ => CommandPriority::Normal,
+        }
+    }
+
+    pub fn is_busy(&self, bus: usize) -> bool {
+        self.busy.contains_key(&bus)
+    }
+
+    // Mark the bus as busy, returns the flag that is set when the operation should be cancelled
+    pub fn set_busy(&mut self, bus: usize) -> Arc<AtomicBool> {
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        self.busy.insert(bus, Some(cancel.clone()));
+        cancel
+    }
+
+    // Mark the bus as busy while a command (that cannot be cancelled) is executed on it
+    pub fn set_executing(&mut self, bus: usize) {
+        self.busy.insert(bus, None);
+    }
+
+    // Mark the bus as no longer busy, returns true if the operation was cancelled
+    pub fn set_idle(&mut self, bus: usize) -> bool {
+        self.busy
+            .remove(&bus)
+            .flatten()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    // Cancel the operation running on the bus, returns false if no operation that can be cancelled is running on it
+    pub fn cancel(&mut self, bus: usize) -> bool {
+        match self.busy.get(&bus) {
+            Some(Some(cancel)) => {
+                cancel.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Queue a command, returns the queued command that was superseded by it (if any)
+    pub fn schedule(&mut self, request: DaliCommandRequest) -> Option<DaliCommandRequest> {
+        let work = ScheduledWork::Command(request);
+
+        if let Some(bus) = work.bus().filter(|bus| self.is_busy(*bus)) {
+            debug!("Bus {bus} is busy, command queued");
+        }
+
+        let queue = &mut self.queues[work.priority() as usize];
+        let key = work.coalescing_key();
+
+        // The superseded command is removed (and not replaced in place), so the new command is executed after commands
+        // that were received before it
+        let superseded = key
+            .and_then(|key| {
+                queue
+                    .iter()
+                    .position(|queued| queued.coalescing_key() == Some(key))
+            })
+            .and_then(|index| queue.remove(index));
+
+        queue.push_back(work);
+
+        match superseded {
+            Some(ScheduledWork::Command(request)) => Some(request),
+            _ => None,
+        }
+    }
+
+    // Queue polling of the next light (unless it is already queued)
+    pub fn schedule_poll(&mut self) {
+        let queue = &mut self.queues[CommandPriority::Maintenance as usize];
+
+        if !queue
+            .iter()
+            .any(|work| matches!(work, ScheduledWork::PollLight))
+        {
+            queue.push_back(ScheduledWork::PollLight);
+        }
+    }
+
+    // Cancel commands are executed while their bus is busy (this is what they are for)
+    fn is_ready(&self, work: &ScheduledWork) -> bool {
+        match work {
+            ScheduledWork::Command(DaliCommandRequest {
+                command: DaliCommand::Cancel { .. },
+                ..
+            }) => true,
+            _ => work.bus().is_none_or(|bus| !self.is_busy(bus)),
+        }
+    }
+
+    // Is there work that can be executed now (work for a busy bus has to wait)
+    pub fn has_ready_work(&self) -> bool {
+        self.queues
+            .iter()
+            .any(|queue| queue.iter().any(|work| self.is_ready(work)))
+    }
+
+    // Get the next work to execute: the first work with the highest priority that is not for a busy bus
+    pub fn next(&mut self) -> Option<ScheduledWork> {
+        for priority in 0..self.queues.len() {
+            if let Some(index) = self.queues[priority]
+                .iter()
+                .position(|work| self.is_ready(work))
+            {
+                return self.queues[priority].remove(index);
+            }
+        }
+
+        None
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.queues.iter().map(|queue| queue.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bus_scheduler::{BusScheduler, ScheduledWork};
+    use crate::command_payload::{DaliCommand, DaliCommandRequest};
+
+    fn request(command: DaliCommand) -> DaliCommandRequest {
+        DaliCommandRequest {
+            request_id: None,
+            command,
+        }
+    }
+
+    fn next_command(scheduler: &mut BusScheduler) -> Option<DaliCommand> {
+        match scheduler.next() {
+            Some(ScheduledWork::Command(request)) => Some(request.command),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_busy_bus_queue() {
+        let mut scheduler = BusScheduler::new();
+
+        scheduler.set_busy(0);
+        scheduler.schedule(request(DaliCommand::SetBusBrightness { bus: 0, value: 10 }));
+        scheduler.schedule(request(DaliCommand::NewGroup { bus: 0 }));
+        scheduler.schedule(request(DaliCommand::NewGroup { bus: 1 }));
+
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::NewGroup { bus: 1 })
+        ));
+        assert!(!scheduler.has_ready_work());
+        assert_eq!(scheduler.queue_depth(), 2);
+
+        scheduler.schedule(request(DaliCommand::Cancel { bus: 0 }));
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::Cancel { bus: 0 })
+        ));
+        assert!(scheduler.cancel(0));
+        assert!(!scheduler.cancel(1));
+
+        assert!(scheduler.set_idle(0));
+
+        // A command that is executed keeps the bus busy, but cannot be cancelled
+        scheduler.set_executing(1);
+        assert!(scheduler.is_busy(1));
+        assert!(!scheduler.cancel(1));
+        assert!(!scheduler.set_idle(1));
+        assert!(!scheduler.is_busy(1));
+
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::SetBusBrightness { bus: 0, value: 10 })
+        ));
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::NewGroup { bus: 0 })
+        ));
+        assert!(scheduler.next().is_none());
+    }
+
+    #[test]
+    fn test_coalescing_and_priority() {
+        let mut scheduler = BusScheduler::new();
+
+        scheduler.schedule_poll();
+        scheduler.schedule_poll();
+        scheduler.schedule(request(DaliCommand::RenameBus {
+            bus: 0,
+            name: "Kitchen".to_owned(),
+        }));
+        scheduler.schedule(request(DaliCommand::SetLightBrightness {
+            bus: 0,
+            address: 1,
+            value: 10,
+        }));
+        scheduler.schedule(request(DaliCommand::SetLightBrightness {
+            bus: 0,
+            address: 2,
+            value: 20,
+        }));
+        let superseded = scheduler.schedule(request(DaliCommand::SetLightBrightness {
+            bus: 0,
+            address: 1,
+            value: 30,
+        }));
+
+        assert!(matches!(
+            superseded.map(|request| request.command),
+            Some(DaliCommand::SetLightBrightness { value: 10, .. })
+        ));
+        assert_eq!(scheduler.queue_depth(), 4);
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::RenameBus { .. })
+        ));
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::SetLightBrightness {
+                address: 2,
+                value: 20,
+                ..
+            })
+        ));
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::SetLightBrightness {
+                address: 1,
+                value: 30,
+                ..
+            })
+        ));
+        assert!(matches!(scheduler.next(), Some(ScheduledWork::PollLight)));
+        assert!(scheduler.next().is_none());
+    }
+
+    #[test]
+    fn test_configuration_command_is_not_overtaken() {
+        let mut scheduler = BusScheduler::new();
+
+        scheduler.schedule(request(DaliCommand::FindNewLights { bus: 0 }));
+        scheduler.schedule(request(DaliCommand::AddToGroup {
+            bus: 0,
+            group: 1,
+            address: 2,
+        }));
+        scheduler.schedule(request(DaliCommand::SetGroupBrightness {
+            bus: 0,
+            group: 1,
+            value: 100,
+        }));
+
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::AddToGroup { .. })
+        ));
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::SetGroupBrightness { .. })
+        ));
+        assert!(matches!(
+            next_command(&mut scheduler),
+            Some(DaliCommand::FindNewLights { .. })
+        ));
+    }
+}
@@ -1,13 +1,39 @@
 
 use serde::{Deserialize, Serialize};
+use crate::config_payload::{GearIdentification, OperatingLevels};
+
+/// Addressing of a command: a single light, a group or all lights on the bus (broadcast)
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DaliTarget {
+    Light(u8),
+    Group(u8),
+    Bus,
+}
+
+/// Indirect arc power commands (level is changed relative to the current level or to a preset level)
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ArcPowerCommand {
+    Off,
+    Up,
+    Down,
+    StepUp,
+    StepDown,
+    StepDownAndOff,
+    OnAndStepUp,
+    RecallMaxLevel,
+    RecallMinLevel,
+}
 
 /// Payload  for controller command topic
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 #[serde(tag="command")]
 pub enum DaliCommand {
     SetLightBrightness{bus: usize, address: u8, value: u8 },    
     SetGroupBrightness{bus: usize, group: u8, value: u8 },
+    SetBusBrightness{bus: usize, value: u8 },
+    BroadcastCommand{bus: usize, dali_command: u16, #[serde(default)] repeat: bool },
+    ArcCommand { bus: usize, target: DaliTarget, #[serde(rename="arc_command")] command: ArcPowerCommand },
 
     UpdateBusStatus,
     RenameBus   { bus: usize, name: String },
@@ -20,10 +46,101 @@ pub enum DaliCommand {
     RemoveFromGroup { bus: usize, group: u8, address: u8 },
     FindAllLights   { bus: usize },
     FindNewLights   { bus: usize },
+    AuditBus        { bus: usize },                                             // Find lights sharing a short address and move them to unused addresses
+    ReplaceLight    { bus: usize, address: u8 },                                // A new light (without short address) takes over the address and settings of a failed light
+    Reconcile       { bus: usize, #[serde(default)] policy: ReconcilePolicy },  // Compare the configuration with the gear on the bus (and sync them by the policy)
     QueryLightStatus{ bus: usize, address: u8 },
     RemoveShortAddress { bus: usize, address: u8 },
     SetLightFadeTime { bus: usize, address: u8, fade_time: u8 },
     SetGroupFadeTime { bus: usize, group: u8, fade_time: u8 },
+    SetLightFadeDuration { bus: usize, address: u8, fade_duration: u32 },  // fade_duration is in milliseconds
+    SetGroupFadeDuration { bus: usize, group: u8, fade_duration: u32 },
+    SetLightFadeRate { bus: usize, address: u8, fade_rate: u8 },
+    SetGroupFadeRate { bus: usize, group: u8, fade_rate: u8 },
+    SetLightOperatingLevels { bus: usize, address: u8, levels: OperatingLevels },
+    SetGroupOperatingLevels { bus: usize, group: u8, levels: OperatingLevels },
+    GoToScene { bus: usize, target: DaliTarget, scene: u8 },
+    StoreScene { bus: usize, target: DaliTarget, scene: u8, level: u8 },
+    RemoveFromScene { bus: usize, target: DaliTarget, scene: u8 },
+    QuerySceneLevels { bus: usize, address: u8 },
+    QueryDeviceInfo { bus: usize, address: u8 },
+    ReadMemoryBank { bus: usize, address: u8, bank: u8 },
+    WriteMemoryLocation { bus: usize, address: u8, bank: u8, location: u8, value: u8 },
+    SetColorTemperature { bus: usize, target: DaliTarget, mirek: u16 },     // Colour temperature in mirek (1000000 / Kelvin)
+    SetColor { bus: usize, target: DaliTarget, color: RgbwafColor },
+    QueryColor { bus: usize, address: u8 },
+    SetDimmingCurve { bus: usize, target: DaliTarget, curve: DimmingCurve },
+    SetFastFadeTime { bus: usize, target: DaliTarget, fast_fade_time: u8 },     // Units of 25ms (0 disables fast fade)
+    QueryLedGear { bus: usize, address: u8 },
+    Identify { bus: usize, address: u8, #[serde(deserialize_with="deserialize_identify_seconds")] seconds: u32 },     // seconds is at most MAX_IDENTIFY_SECONDS
+    Reset { bus: usize, target: DaliTarget, confirm: String },                  // confirm must be RESET_CONFIRMATION
+    ResetMemoryBank { bus: usize, target: DaliTarget, bank: u8, confirm: String },
+    SavePersistentVariables { bus: usize, target: DaliTarget },
+    Cancel { bus: usize },                                                      // Cancel the long operation (FindAllLights, Reconcile etc.) running on the bus
+}
+
+impl DaliCommand {
+    /// The bus the command is sent to (None for commands that apply to all buses)
+    pub fn bus(&self) -> Option<usize> {
+        match self {
+            DaliCommand::UpdateBusStatus => None,
+
+            DaliCommand::SetLightBrightness { bus, .. } | DaliCommand::SetGroupBrightness { bus, .. } | DaliCommand::SetBusBrightness { bus, .. } |
+            DaliCommand::BroadcastCommand { bus, .. } | DaliCommand::ArcCommand { bus, .. } |
+            DaliCommand::RenameBus { bus, .. } | DaliCommand::RenameLight { bus, .. } | DaliCommand::RenameGroup { bus, .. } |
+            DaliCommand::NewGroup { bus } | DaliCommand::AddToGroup { bus, .. } | DaliCommand::MatchGroup { bus, .. } |
+            DaliCommand::RemoveGroup { bus, .. } | DaliCommand::RemoveFromGroup { bus, .. } |
+            DaliCommand::FindAllLights { bus } | DaliCommand::FindNewLights { bus } | DaliCommand::AuditBus { bus } |
+            DaliCommand::ReplaceLight { bus, .. } | DaliCommand::Reconcile { bus, .. } |
+            DaliCommand::QueryLightStatus { bus, .. } | DaliCommand::RemoveShortAddress { bus, .. } |
+            DaliCommand::SetLightFadeTime { bus, .. } | DaliCommand::SetGroupFadeTime { bus, .. } |
+            DaliCommand::SetLightFadeDuration { bus, .. } | DaliCommand::SetGroupFadeDuration { bus, .. } |
+            DaliCommand::SetLightFadeRate { bus, .. } | DaliCommand::SetGroupFadeRate { bus, .. } |
+            DaliCommand::SetLightOperatingLevels { bus, .. } | DaliCommand::SetGroupOperatingLevels { bus, .. } |
+            DaliCommand::GoToScene { bus, .. } | DaliCommand::StoreScene { bus, .. } | DaliCommand::RemoveFromScene { bus, .. } |
+            DaliCommand::QuerySceneLevels { bus, .. } | DaliCommand::QueryDeviceInfo { bus, .. } |
+            DaliCommand::ReadMemoryBank { bus, .. } | DaliCommand::WriteMemoryLocation { bus, .. } |
+            DaliCommand::SetColorTemperature { bus, .. } | DaliCommand::SetColor { bus, .. } | DaliCommand::QueryColor { bus, .. } |
+            DaliCommand::SetDimmingCurve { bus, .. } | DaliCommand::SetFastFadeTime { bus, .. } | DaliCommand::QueryLedGear { bus, .. } |
+            DaliCommand::Identify { bus, .. } | DaliCommand::Reset { bus, .. } | DaliCommand::ResetMemoryBank { bus, .. } |
+            DaliCommand::SavePersistentVariables { bus, .. } | DaliCommand::Cancel { bus } => Some(*bus),
+        }
+    }
+}
+
+/// Longest identification time (in seconds) that an Identify command may request
+pub const MAX_IDENTIFY_SECONDS: u32 = 300;
+
+fn deserialize_identify_seconds<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let seconds = u32::deserialize(deserializer)?;
+
+    if seconds > MAX_IDENTIFY_SECONDS {
+        Err(serde::de::Error::custom(format!("identify time of {seconds} seconds is longer than {MAX_IDENTIFY_SECONDS} seconds")))
+    } else {
+        Ok(seconds)
+    }
+}
+
+/// Confirmation token that must be given in reset commands
+pub const RESET_CONFIRMATION: &str = "RESET";
+
+/// Payload received on the controller command topic: a command with an optional request id that is returned in the command reply
+#[derive(Debug, Deserialize)]
+pub struct DaliCommandRequest {
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub command: DaliCommand,
+}
+
+impl std::fmt::Display for DaliTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DaliTarget::Light(short_address) => write!(f, "light {}", short_address),
+            DaliTarget::Group(group_address) => write!(f, "group {}", group_address),
+            DaliTarget::Bus => write!(f, "all lights"),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -41,6 +158,24 @@ impl From<LightStatus> for u8 {
     }
 }
 
+impl LightStatus {
+    pub fn is_lamp_failure(&self) -> bool {
+        (self.0 & 0x02) != 0
+    }
+
+    pub fn is_lamp_on(&self) -> bool {
+        (self.0 & 0x04) != 0
+    }
+
+    pub fn is_fade_running(&self) -> bool {
+        (self.0 & 0x10) != 0
+    }
+
+    pub fn is_power_failure(&self) -> bool {
+        (self.0 & 0x80) != 0
+    }
+}
+
 impl std::fmt::Display for LightStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut d = String::new();
@@ -108,9 +243,560 @@ impl QueryLightReply {
     }
 }
 
+/// Payload for the command reply topic (published after each command is executed)
+#[derive(Serialize)]
+pub struct CommandReply {
+    controller: String,
+    request_id: Option<String>,
+    ok: bool,
+    error: Option<String>,
+    result: Option<serde_json::Value>,
+}
+
+impl CommandReply {
+    pub fn new(controller: &str, request_id: Option<&str>, result: Option<serde_json::Value>) -> CommandReply {
+        CommandReply {
+            controller: controller.to_owned(),
+            request_id: request_id.map(|id| id.to_owned()),
+            ok: true,
+            error: None,
+            result,
+        }
+    }
+
+    pub fn new_failure(controller: &str, request_id: Option<&str>, error: &str) -> CommandReply {
+        CommandReply {
+            controller: controller.to_owned(),
+            request_id: request_id.map(|id| id.to_owned()),
+            ok: false,
+            error: Some(error.to_owned()),
+            result: None,
+        }
+    }
+}
+
+/// Payload for the (retained) light state topic
+#[derive(Serialize)]
+pub struct LightState {
+    controller: String,
+    bus: usize,
+    address: u8,
+    failure: bool,
+    level: u8,
+    on: bool,
+    lamp_failure: bool,
+    fade_running: bool,
+    description: String,
+}
+
+impl LightState {
+    pub fn new(controller: &str, bus: usize, address: u8, status: LightStatus, level: u8) -> LightState {
+        LightState {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: false,
+            level,
+            on: status.is_lamp_on(),
+            lamp_failure: status.is_lamp_failure(),
+            fade_running: status.is_fade_running(),
+            description: format!("{}", status),
+        }
+    }
+
+    pub fn new_failure(controller: &str, bus: usize, address: u8, error: &str) -> LightState {
+        LightState {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: true,
+            level: 0,
+            on: false,
+            lamp_failure: false,
+            fade_running: false,
+            description: error.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum LightEventKind {
+    Disappeared,
+    Reappeared,
+    LampFailure,
+    PowerFailure,
+    LevelChanged,
+}
+
+/// Payload for the controller events topic
+#[derive(Serialize)]
+pub struct LightEvent {
+    controller: String,
+    bus: usize,
+    address: u8,
+    event: LightEventKind,
+    level: Option<u8>,
+    description: String,
+}
+
+impl LightEvent {
+    pub fn new(controller: &str, bus: usize, address: u8, event: LightEventKind, level: Option<u8>, description: &str) -> LightEvent {
+        LightEvent {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            event,
+            level,
+            description: description.to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct QuerySceneLevelsReply {
+    controller: String,
+    bus: usize,
+    address: u8,
+    failure: bool,
+    levels: Vec<Option<u8>>,     // Level for each scene (null if light is not part of the scene)
+    description: String,
+}
+
+impl QuerySceneLevelsReply {
+    pub fn new(controller: &str, bus: usize, address: u8, levels: &[Option<u8>]) -> QuerySceneLevelsReply {
+        QuerySceneLevelsReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: false,
+            levels: levels.to_vec(),
+            description: String::new(),
+        }
+    }
+
+    pub fn new_failure(controller: &str, bus: usize, address: u8, error: &str) -> QuerySceneLevelsReply {
+        QuerySceneLevelsReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: true,
+            levels: Vec::new(),
+            description: error.to_string(),
+        }
+    }
+}
+
+/// Identification and configuration of a light's control gear
+#[derive(Debug, Serialize, Clone)]
+pub struct DeviceInfo {
+    pub version: u8,                        // IEC 62386-102 version (major version in bits 7-2, minor version in bits 1-0)
+    pub device_types: Vec<u8>,
+    pub physical_min_level: u8,
+    pub min_level: u8,
+    pub max_level: u8,
+    pub power_on_level: u8,
+    pub system_failure_level: u8,
+    pub fade_time: u8,
+    pub fade_rate: u8,
+    pub extended_fade_time: Option<u8>,     // DALI-2 only
+    pub fade_duration: u32,                 // Fade time in milliseconds (decoded from fade time and extended fade time)
+    pub random_address: u32,
+}
+
+/// Fade settings of a light
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct FadeSettings {
+    pub fade_time: u8,
+    pub fade_rate: u8,
+    pub extended_fade_time: Option<u8>,     // DALI-2 only
+    pub fade_duration: u32,                 // Milliseconds
+}
+
+impl std::fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "  Version: {}.{}, device types: {:?}, random address: {:#08x}", self.version >> 2, self.version & 0x03, self.device_types, self.random_address)?;
+        writeln!(f, "  Levels: physical min: {}, min: {}, max: {}, power on: {}, system failure: {}",
+            self.physical_min_level, self.min_level, self.max_level, self.power_on_level, self.system_failure_level)?;
+        write!(f, "  Fade time: {} ({}ms), fade rate: {}", self.fade_time, self.fade_duration, self.fade_rate)?;
+
+        if let Some(extended_fade_time) = self.extended_fade_time {
+            write!(f, ", extended fade time: {:#04x}", extended_fade_time)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct QueryDeviceInfoReply {
+    controller: String,
+    bus: usize,
+    address: u8,
+    failure: bool,
+    device_info: Option<DeviceInfo>,
+    description: String,
+}
+
+impl QueryDeviceInfoReply {
+    pub fn new(controller: &str, bus: usize, address: u8, device_info: &DeviceInfo) -> QueryDeviceInfoReply {
+        QueryDeviceInfoReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: false,
+            device_info: Some(device_info.clone()),
+            description: String::new(),
+        }
+    }
+
+    pub fn new_failure(controller: &str, bus: usize, address: u8, error: &str) -> QueryDeviceInfoReply {
+        QueryDeviceInfoReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: true,
+            device_info: None,
+            description: error.to_string(),
+        }
+    }
+}
+
+/// Content of a light's memory bank
+#[derive(Debug, Serialize, Clone)]
+pub struct MemoryBank {
+    pub bank: u8,
+    pub data: Vec<Option<u8>>,                          // Content of each location (null if location is not implemented)
+    pub identification: Option<GearIdentification>,     // Decoded content of memory bank 0
+}
+
+#[derive(Serialize)]
+pub struct ReadMemoryBankReply {
+    controller: String,
+    bus: usize,
+    address: u8,
+    failure: bool,
+    memory_bank: Option<MemoryBank>,
+    description: String,
+}
+
+impl ReadMemoryBankReply {
+    pub fn new(controller: &str, bus: usize, address: u8, memory_bank: &MemoryBank) -> ReadMemoryBankReply {
+        ReadMemoryBankReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: false,
+            memory_bank: Some(memory_bank.clone()),
+            description: String::new(),
+        }
+    }
+
+    pub fn new_failure(controller: &str, bus: usize, address: u8, error: &str) -> ReadMemoryBankReply {
+        ReadMemoryBankReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: true,
+            memory_bank: None,
+            description: error.to_string(),
+        }
+    }
+}
+
+/// RGBWAF colour (DT8) dim levels, channels which are not given (or are 255) are not changed
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RgbwafColor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub red: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub green: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blue: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub white: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amber: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub free_color: Option<u8>,
+}
+
+/// DT8 colour status (IEC 62386-209 QUERY COLOUR STATUS)
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct ColorStatus {
+    pub xy_out_of_range: bool,
+    pub color_temperature_out_of_range: bool,
+    pub auto_calibration_running: bool,
+    pub auto_calibration_successful: bool,
+    pub xy_active: bool,
+    pub color_temperature_active: bool,
+    pub primary_n_active: bool,
+    pub rgbwaf_active: bool,
+}
+
+impl From<u8> for ColorStatus {
+    fn from(v: u8) -> Self {
+        ColorStatus {
+            xy_out_of_range: (v & 0x01) != 0,
+            color_temperature_out_of_range: (v & 0x02) != 0,
+            auto_calibration_running: (v & 0x04) != 0,
+            auto_calibration_successful: (v & 0x08) != 0,
+            xy_active: (v & 0x10) != 0,
+            color_temperature_active: (v & 0x20) != 0,
+            primary_n_active: (v & 0x40) != 0,
+            rgbwaf_active: (v & 0x80) != 0,
+        }
+    }
+}
+
+/// DT8 colour type features (IEC 62386-209 QUERY COLOUR TYPE FEATURES)
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct ColorFeatures {
+    pub xy_capable: bool,
+    pub color_temperature_capable: bool,
+    pub primary_count: u8,
+    pub rgbwaf_channels: u8,
+}
+
+impl From<u8> for ColorFeatures {
+    fn from(v: u8) -> Self {
+        ColorFeatures {
+            xy_capable: (v & 0x01) != 0,
+            color_temperature_capable: (v & 0x02) != 0,
+            primary_count: (v >> 2) & 0x07,
+            rgbwaf_channels: (v >> 5) & 0x07,
+        }
+    }
+}
+
+/// Colour state of a DT8 light
+#[derive(Debug, Serialize, Clone)]
+pub struct ColorInfo {
+    pub status: ColorStatus,
+    pub features: ColorFeatures,
+    pub color_temperature: Option<u16>,             // mirek
+    pub coolest_color_temperature: Option<u16>,
+    pub warmest_color_temperature: Option<u16>,
+    pub color: Option<RgbwafColor>,                 // Only for RGBWAF capable lights
+}
+
+#[derive(Serialize)]
+pub struct QueryColorReply {
+    controller: String,
+    bus: usize,
+    address: u8,
+    failure: bool,
+    color_info: Option<ColorInfo>,
+    description: String,
+}
+
+impl QueryColorReply {
+    pub fn new(controller: &str, bus: usize, address: u8, color_info: &ColorInfo) -> QueryColorReply {
+        QueryColorReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: false,
+            color_info: Some(color_info.clone()),
+            description: String::new(),
+        }
+    }
+
+    pub fn new_failure(controller: &str, bus: usize, address: u8, error: &str) -> QueryColorReply {
+        QueryColorReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: true,
+            color_info: None,
+            description: error.to_string(),
+        }
+    }
+}
+
+/// Dimming curve of DT6 (LED) gear
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DimmingCurve {
+    Logarithmic,
+    Linear,
+}
+
+/// DT6 failure status (IEC 62386-207 QUERY FAILURE STATUS)
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct LedFailureStatus {
+    pub short_circuit: bool,
+    pub open_circuit: bool,
+    pub load_decrease: bool,
+    pub load_increase: bool,
+    pub current_protector_active: bool,
+    pub thermal_shutdown: bool,
+    pub thermal_overload: bool,                     // Light level is reduced because of thermal overload
+    pub reference_measurement_failed: bool,
+}
+
+impl From<u8> for LedFailureStatus {
+    fn from(v: u8) -> Self {
+        LedFailureStatus {
+            short_circuit: (v & 0x01) != 0,
+            open_circuit: (v & 0x02) != 0,
+            load_decrease: (v & 0x04) != 0,
+            load_increase: (v & 0x08) != 0,
+            current_protector_active: (v & 0x10) != 0,
+            thermal_shutdown: (v & 0x20) != 0,
+            thermal_overload: (v & 0x40) != 0,
+            reference_measurement_failed: (v & 0x80) != 0,
+        }
+    }
+}
+
+impl LedFailureStatus {
+    pub fn is_failure(&self) -> bool {
+        self.short_circuit || self.open_circuit || self.load_decrease || self.load_increase || self.current_protector_active ||
+            self.thermal_shutdown || self.thermal_overload || self.reference_measurement_failed
+    }
+}
+
+/// DT6 (LED) gear information
+#[derive(Debug, Serialize, Clone)]
+pub struct LedGearInfo {
+    pub gear_type: u8,
+    pub possible_operating_modes: u8,
+    pub features: u8,
+    pub operating_mode: u8,
+    pub failure_status: LedFailureStatus,
+    pub dimming_curve: DimmingCurve,
+    pub fast_fade_time: u8,
+    pub min_fast_fade_time: u8,
+    pub extended_version: u8,
+}
+
+#[derive(Serialize)]
+pub struct QueryLedGearReply {
+    controller: String,
+    bus: usize,
+    address: u8,
+    failure: bool,
+    led_gear_info: Option<LedGearInfo>,
+    description: String,
+}
+
+impl QueryLedGearReply {
+    pub fn new(controller: &str, bus: usize, address: u8, led_gear_info: &LedGearInfo) -> QueryLedGearReply {
+        QueryLedGearReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: false,
+            led_gear_info: Some(led_gear_info.clone()),
+            description: String::new(),
+        }
+    }
+
+    pub fn new_failure(controller: &str, bus: usize, address: u8, error: &str) -> QueryLedGearReply {
+        QueryLedGearReply {
+            controller: controller.to_owned(),
+            bus,
+            address,
+            failure: true,
+            led_gear_info: None,
+            description: error.to_string(),
+        }
+    }
+}
+
+/// Progress of a long operation (reported on the progress topic)
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag="event")]
+pub enum ProgressKind {
+    SearchStep { step: u8, found: usize, short_address: Option<u8> },   // short_address is the address the next light found will get
+    LightFound { short_address: u8, found: usize },
+    DeviceFound { random_address: u32, short_address: Option<u8>, found: usize },   // short_address is the light's address before it is re-commissioned
+    GroupMemberAdded { group: u8, light: String },
+    GroupMemberRemoved { group: u8, light: String },
+    AddressChecked { short_address: u8 },
+    AddressConflict { short_address: u8, moved_to: Vec<u8> },                  // Lights sharing short_address were moved to the moved_to addresses
+    Finished { ok: bool, cancelled: bool },
+}
+
+/// Payload for the progress topic (published while a long operation such as FindAllLights is running on a bus)
+#[derive(Serialize)]
+pub struct OperationProgress {
+    controller: String,
+    bus: usize,
+    operation: String,
+    request_id: Option<String>,
+    #[serde(flatten)]
+    progress: ProgressKind,
+}
+
+impl OperationProgress {
+    pub fn new(controller: &str, bus: usize, operation: &str, request_id: Option<&str>, progress: ProgressKind) -> OperationProgress {
+        OperationProgress {
+            controller: controller.to_owned(),
+            bus,
+            operation: operation.to_owned(),
+            request_id: request_id.map(|id| id.to_owned()),
+            progress,
+        }
+    }
+}
+
+/// How differences between the configuration and the gear on the bus are resolved
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ReconcilePolicy {
+    #[default]
+    ReportOnly,             // Nothing is changed
+    ConfigWins,             // Gear is programmed according to the configuration
+    GearWins,               // Configuration is updated according to the gear
+}
+
+/// Light parameter that is stored both in the configuration and in the gear
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum LightParameter {
+    MaxLevel,
+    MinLevel,
+    PowerOnLevel,
+    SystemFailureLevel,
+    FadeDuration,           // Milliseconds
+    FadeRate,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct GroupMismatch {
+    pub short_address: u8,
+    pub config_groups: Vec<u8>,     // Groups the light is member of in the configuration
+    pub gear_groups: Vec<u8>,       // Groups the light's gear is member of
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct ParameterMismatch {
+    pub short_address: u8,
+    pub parameter: LightParameter,
+    pub config: u32,
+    pub gear: u32,
+}
+
+/// Differences between a bus configuration and the gear on the bus (reply of the Reconcile command)
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct ReconcileReport {
+    pub policy: ReconcilePolicy,                        // Policy that was applied
+    pub missing_lights: Vec<u8>,                        // Configured lights that do not answer
+    pub unknown_lights: Vec<u8>,                        // Lights that answer but are not configured
+    pub address_conflicts: Vec<u8>,                     // Addresses used by more than one light (resolved by AuditBus)
+    pub group_mismatches: Vec<GroupMismatch>,
+    pub parameter_mismatches: Vec<ParameterMismatch>,
+}
+
+impl ReconcileReport {
+    pub fn is_empty(&self) -> bool {
+        self.missing_lights.is_empty() && self.unknown_lights.is_empty() && self.address_conflicts.is_empty() &&
+            self.group_mismatches.is_empty() && self.parameter_mismatches.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::command_payload::DaliCommand;
+    use crate::command_payload::{ArcPowerCommand, DaliCommand, DaliCommandRequest, DaliTarget, DimmingCurve, LedFailureStatus, OperationProgress, ProgressKind, ReconcilePolicy, RgbwafColor, RESET_CONFIRMATION};
+    use crate::config_payload::OperatingLevels;
 
     #[test]
     fn test_set_light_brightness() {
@@ -141,5 +827,158 @@ mod tests {
         let c: DaliCommand = serde_json::from_str(json).unwrap();
         assert!(matches!(c, DaliCommand::SetGroupBrightness { bus: 1, group: 5, value: 48 }));
     }
-}
 
+    #[test]
+    fn test_go_to_scene() {
+        let json = r#"
+            {
+                "command": "GoToScene",
+                "bus": 0,
+                "target": { "Group": 3 },
+                "scene": 2
+            }
+        "#;
+
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(c, DaliCommand::GoToScene { bus: 0, target: DaliTarget::Group(3), scene: 2 }));
+
+        let json = r#"{ "command": "GoToScene", "bus": 1, "target": "Bus", "scene": 15 }"#;
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(c, DaliCommand::GoToScene { bus: 1, target: DaliTarget::Bus, scene: 15 }));
+    }
+
+    #[test]
+    fn test_set_color() {
+        let json = r#"{ "command": "SetColorTemperature", "bus": 0, "target": { "Light": 4 }, "mirek": 250 }"#;
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(c, DaliCommand::SetColorTemperature { bus: 0, target: DaliTarget::Light(4), mirek: 250 }));
+
+        let json = r#"{ "command": "SetColor", "bus": 1, "target": { "Group": 2 }, "color": { "red": 254, "blue": 10 } }"#;
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+
+        if let DaliCommand::SetColor { bus: 1, target: DaliTarget::Group(2), color } = c {
+            assert_eq!(color, RgbwafColor { red: Some(254), blue: Some(10), ..Default::default() });
+        } else {
+            panic!("Unexpected command {:?}", c);
+        }
+    }
+
+    #[test]
+    fn test_led_gear() {
+        let json = r#"{ "command": "SetDimmingCurve", "bus": 0, "target": "Bus", "curve": "Linear" }"#;
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(c, DaliCommand::SetDimmingCurve { bus: 0, target: DaliTarget::Bus, curve: DimmingCurve::Linear }));
+
+        let failure_status = LedFailureStatus::from(0x22);
+        assert!(failure_status.open_circuit && failure_status.thermal_shutdown && !failure_status.short_circuit);
+        assert!(failure_status.is_failure());
+        assert!(!LedFailureStatus::from(0).is_failure());
+    }
+
+    #[test]
+    fn test_identify() {
+        let json = r#"{ "command": "Identify", "bus": 0, "address": 17, "seconds": 30 }"#;
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(c, DaliCommand::Identify { bus: 0, address: 17, seconds: 30 }));
+
+        let json = r#"{ "command": "Identify", "bus": 0, "address": 17, "seconds": 300 }"#;
+        assert!(serde_json::from_str::<DaliCommand>(json).is_ok());
+
+        // Identification time is limited
+        let json = r#"{ "command": "Identify", "bus": 0, "address": 17, "seconds": 301 }"#;
+        let e = serde_json::from_str::<DaliCommand>(json).unwrap_err();
+        assert!(e.to_string().contains("longer than 300 seconds"));
+    }
+
+    #[test]
+    fn test_reset() {
+        let json = r#"{ "command": "Reset", "bus": 0, "target": { "Group": 1 }, "confirm": "RESET" }"#;
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(c, DaliCommand::Reset { bus: 0, target: DaliTarget::Group(1), ref confirm } if confirm == RESET_CONFIRMATION));
+
+        // Confirmation token is required
+        let json = r#"{ "command": "Reset", "bus": 0, "target": "Bus" }"#;
+        assert!(serde_json::from_str::<DaliCommand>(json).is_err());
+    }
+
+    #[test]
+    fn test_broadcast_command() {
+        let json = r#"{ "command": "BroadcastCommand", "bus": 0, "dali_command": 0 }"#;
+
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(c, DaliCommand::BroadcastCommand { bus: 0, dali_command: 0, repeat: false }));
+    }
+
+    #[test]
+    fn test_arc_command() {
+        let json = r#"{ "command": "ArcCommand", "bus": 0, "target": { "Light": 7 }, "arc_command": "OnAndStepUp" }"#;
+
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(c, DaliCommand::ArcCommand { bus: 0, target: DaliTarget::Light(7), command: ArcPowerCommand::OnAndStepUp }));
+    }
+
+    #[test]
+    fn test_command_request() {
+        let json = r#"
+            {
+                "request_id": "kitchen-42",
+                "command": "SetLightBrightness",
+                "bus": 1,
+                "address": 5,
+                "value": 48
+            }
+        "#;
+
+        let r: DaliCommandRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(r.request_id.as_deref(), Some("kitchen-42"));
+        assert!(matches!(r.command, DaliCommand::SetLightBrightness { bus: 1, address: 5, value: 48 }));
+
+        let r: DaliCommandRequest = serde_json::from_str(r#"{ "command": "UpdateBusStatus" }"#).unwrap();
+        assert!(r.request_id.is_none());
+        assert!(matches!(r.command, DaliCommand::UpdateBusStatus));
+    }
+
+    #[test]
+    fn test_set_operating_levels() {
+        let json = r#"
+            {
+                "command": "SetGroupOperatingLevels",
+                "bus": 0,
+                "group": 2,
+                "levels": { "max_level": 200, "power_on_level": 255 }
+            }
+        "#;
+
+        let c: DaliCommand = serde_json::from_str(json).unwrap();
+        assert!(matches!(c, DaliCommand::SetGroupOperatingLevels { bus: 0, group: 2,
+            levels: OperatingLevels { max_level: Some(200), min_level: None, power_on_level: Some(255), system_failure_level: None } }));
+    }
+
+    #[test]
+    fn test_cancel_and_progress() {
+        let c: DaliCommand = serde_json::from_str(r#"{ "command": "Cancel", "bus": 1 }"#).unwrap();
+        assert!(matches!(c, DaliCommand::Cancel { bus: 1 }));
+        assert_eq!(c.bus(), Some(1));
+
+        let c: DaliCommand = serde_json::from_str(r#"{ "command": "AuditBus", "bus": 2 }"#).unwrap();
+        assert_eq!(c.bus(), Some(2));
+
+        let c: DaliCommand = serde_json::from_str(r#"{ "command": "ReplaceLight", "bus": 0, "address": 12 }"#).unwrap();
+        assert!(matches!(c, DaliCommand::ReplaceLight { bus: 0, address: 12 }));
+
+        let c: DaliCommand = serde_json::from_str(r#"{ "command": "Reconcile", "bus": 1 }"#).unwrap();
+        assert!(matches!(c, DaliCommand::Reconcile { bus: 1, policy: ReconcilePolicy::ReportOnly }));
+        let c: DaliCommand = serde_json::from_str(r#"{ "command": "Reconcile", "bus": 1, "policy": "GearWins" }"#).unwrap();
+        assert!(matches!(c, DaliCommand::Reconcile { bus: 1, policy: ReconcilePolicy::GearWins }));
+
+        let progress = OperationProgress::new("Kitchen", 1, "FindAllLights", Some("find-1"),
+            ProgressKind::SearchStep { step: 3, found: 2, short_address: Some(5) });
+        let json: serde_json::Value = serde_json::to_value(&progress).unwrap();
+
+        assert_eq!(json["event"], "SearchStep");
+        assert_eq!(json["operation"], "FindAllLights");
+        assert_eq!(json["request_id"], "find-1");
+        assert_eq!(json["found"], 2);
+        assert_eq!(json["short_address"], 5);
+    }
+}
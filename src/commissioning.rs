@@ -0,0 +1,312 @@
+use crate::config_payload::{BusConfig, Channel, GearIdentification, OperatingLevels};
+use log::{error, info};
+
+/// A light that was found by searching the bus
+#[derive(Debug, Clone)]
+pub struct FoundDevice {
+    pub random_address: u32,
+    pub short_address: Option<u8>, // Short address the light had when it was found (None if it had no address)
+    pub identification: Option<GearIdentification>,
+}
+
+/// Short address assigned to a light that was found, and the configured channel it was matched to
+#[derive(Debug, Clone)]
+pub struct AddressAssignment {
+    pub device: FoundDevice,
+    pub short_address: u8,
+    pub channel: Option<usize>, // Index of the matching channel in the bus configuration (None for a new light)
+}
+
+impl AddressAssignment {
+    // Is the light's short address to be programmed
+    pub fn is_changed(&self) -> bool {
+        self.device.short_address != Some(self.short_address)
+    }
+}
+
+// Check if a light that was found is the light configured by a channel
+type MatchPass = dyn Fn(&FoundDevice, &Channel) -> bool;
+
+fn is_same_serial(identification: &GearIdentification, other: &GearIdentification) -> bool {
+    identification.identification_number != 0
+        && identification.gtin == other.gtin
+        && identification.identification_number == other.identification_number
+}
+
+// Lights with different serial numbers are different lights, even if they have the same random or short address
+fn is_other_light(device: &FoundDevice, channel: &Channel) -> bool {
+    match (&device.identification, &channel.identification) {
+        (Some(identification), Some(other)) => !is_same_serial(identification, other),
+        _ => false,
+    }
+}
+
+/// Match the lights that were found to the configured channels and assign short addresses to them.
+///
+/// A light is matched to a channel by its serial number (from memory bank 0), then by its random address and last by
+/// its short address (re-commissioning does not randomize the lights, so a light keeps the random address it had when
+/// it was found before). A matched light gets the channel's short address (so its description, groups and scenes are
+/// kept). A new light keeps its short address if it is not used by another light, otherwise it gets the lowest
+/// unused address.
+pub fn assign_addresses(
+    bus_config: &BusConfig,
+    devices: Vec<FoundDevice>,
+) -> Vec<AddressAssignment> {
+    let mut channel_matches: Vec<Option<usize>> = vec![None; devices.len()];
+    let mut matched_channels = vec![false; bus_config.channels.len()];
+
+    let passes: [&MatchPass; 3] = [
+        &|device, channel| match (&device.identification, &channel.identification) {
+            (Some(identification), Some(other)) => is_same_serial(identification, other),
+            _ => false,
+        },
+        &|device, channel| {
+            channel.random_address == Some(device.random_address)
+                && !is_other_light(device, channel)
+        },
+        &|device, channel| {
+            device.short_address == Some(channel.short_address) && !is_other_light(device, channel)
+        },
+    ];
+
+    for is_match in passes {
+        for (device_index, device) in devices.iter().enumerate() {
+            if channel_matches[device_index].is_some() {
+                continue;
+            }
+
+            if let Some(channel_index) = bus_config
+                .channels
+                .iter()
+                .enumerate()
+                .position(|(index, channel)| !matched_channels[index] && is_match(device, channel))
+            {
+                matched_channels[channel_index] = true;
+                channel_matches[device_index] = Some(channel_index);
+            }
+        }
+    }
+
+    let mut used_addresses: Vec<u8> = channel_matches
+        .iter()
+        .flatten()
+        .map(|channel_index| bus_config.channels[*channel_index].short_address)
+        .collect();
+    let mut assignments = Vec::new();
+
+    for (device, channel) in devices.into_iter().zip(channel_matches) {
+        let short_address = match channel {
+            Some(channel_index) => Some(bus_config.channels[channel_index].short_address),
+            None => device
+                .short_address
+                .filter(|short_address| {
+                    *short_address < 64 && !used_addresses.contains(short_address)
+                })
+                .or_else(|| {
+                    (0..64u8).find(|short_address| !used_addresses.contains(short_address))
+                }),
+        };
+
+        match short_address {
+            Some(short_address) => {
+                used_addresses.push(short_address);
+                assignments.push(AddressAssignment {
+                    device,
+                    short_address,
+                    channel,
+                })
+            }
+            None => error!(
+                "No unused short address for light with random address {:#08x} on bus {}",
+                device.random_address, bus_config.bus
+            ),
+        }
+    }
+
+    assignments
+}
+
+/// Build the bus configuration after the lights were assigned their short addresses.
+///
+/// Channels of matched lights are kept (with updated identification and random address), new lights are added and
+/// channels of lights that were not found are removed (together with their group memberships and scene levels).
+pub fn rebuild_bus_config(bus_config: &BusConfig, assignments: &[AddressAssignment]) -> BusConfig {
+    let mut channels: Vec<Channel> = assignments
+        .iter()
+        .map(|assignment| match assignment.channel {
+            Some(channel_index) => {
+                let channel = &bus_config.channels[channel_index];
+
+                Channel {
+                    identification: assignment
+                        .device
+                        .identification
+                        .clone()
+                        .or_else(|| channel.identification.clone()),
+                    random_address: Some(assignment.device.random_address),
+                    ..channel.clone()
+                }
+            }
+            None => Channel {
+                short_address: assignment.short_address,
+                description: format!("Light {}", assignment.short_address),
+                identification: assignment.device.identification.clone(),
+                random_address: Some(assignment.device.random_address),
+                levels: OperatingLevels::default(),
+                fade_duration: None,
+                fade_rate: None,
+            },
+        })
+        .collect();
+
+    channels.sort_by_key(|channel| channel.short_address);
+
+    // Only addresses of lights that were matched are kept in groups and scenes (a new light may get the address of a
+    // light that was not found)
+    let kept_addresses: Vec<u8> = assignments
+        .iter()
+        .filter(|assignment| assignment.channel.is_some())
+        .map(|assignment| assignment.short_address)
+        .collect();
+
+    for channel in bus_config.channels.iter() {
+        if !kept_addresses.contains(&channel.short_address) {
+            info!(
+                "Light {} ({}) was not found on bus {}, removed from configuration",
+                channel.short_address, channel.description, bus_config.bus
+            );
+        }
+    }
+
+    let mut groups = bus_config.groups.clone();
+
+    for group in groups.iter_mut() {
+        group
+            .members
+            .retain(|member| kept_addresses.contains(member));
+    }
+
+    let mut scenes = bus_config.scenes.clone();
+
+    for scene in scenes.iter_mut() {
+        scene
+            .levels
+            .retain(|level| kept_addresses.contains(&level.short_address));
+    }
+    scenes.retain(|scene| !scene.levels.is_empty());
+
+    BusConfig {
+        channels,
+        groups,
+        scenes,
+        ..bus_config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commissioning::{assign_addresses, rebuild_bus_config, FoundDevice};
+    use crate::config_payload::{BusConfig, Channel};
+    use crate::test_builders::{self, bus_config, group, identification, scene};
+
+    fn channel(short_address: u8, description: &str, serial: Option<u64>) -> Channel {
+        Channel {
+            description: description.to_owned(),
+            identification: serial.map(identification),
+            ..test_builders::channel(short_address)
+        }
+    }
+
+    fn device(random_address: u32, short_address: Option<u8>, serial: Option<u64>) -> FoundDevice {
+        FoundDevice {
+            random_address,
+            short_address,
+            identification: serial.map(identification),
+        }
+    }
+
+    #[test]
+    fn test_recommission_keeps_names_and_groups() {
+        let bus_config = BusConfig {
+            channels: vec![
+                channel(0, "Sink", Some(100)),
+                channel(1, "Table", Some(101)),
+                channel(2, "Door", None),
+                channel(3, "Broken", Some(103)),
+            ],
+            groups: vec![group(0, &[0, 1, 2, 3])],
+            scenes: vec![scene(0, &[(1, 200), (3, 100)]), scene(1, &[(3, 50)])],
+            ..bus_config(&[])
+        };
+
+        let devices = vec![
+            device(0x100, Some(1), Some(100)), // Sink, its address was changed
+            device(0x101, Some(0), Some(101)), // Table, its address was changed
+            device(0x102, Some(2), None),      // Door, matched by short address
+            device(0x103, Some(3), Some(999)), // Replaced light (different serial)
+            device(0x104, None, None),         // New light without short address
+        ];
+
+        let assignments = assign_addresses(&bus_config, devices);
+        let addresses: Vec<(u32, u8, bool)> = assignments
+            .iter()
+            .map(|a| (a.device.random_address, a.short_address, a.is_changed()))
+            .collect();
+
+        assert_eq!(
+            addresses,
+            vec![
+                (0x100, 0, true),
+                (0x101, 1, true),
+                (0x102, 2, false),
+                (0x103, 3, false),
+                (0x104, 4, true)
+            ]
+        );
+
+        let new_config = rebuild_bus_config(&bus_config, &assignments);
+        let channels: Vec<(u8, &str)> = new_config
+            .channels
+            .iter()
+            .map(|channel| (channel.short_address, channel.description.as_str()))
+            .collect();
+
+        assert_eq!(
+            channels,
+            vec![
+                (0, "Sink"),
+                (1, "Table"),
+                (2, "Door"),
+                (3, "Light 3"),
+                (4, "Light 4")
+            ]
+        );
+        assert_eq!(new_config.channels[0].random_address, Some(0x100));
+        assert_eq!(new_config.groups[0].members, vec![0, 1, 2]);
+        assert_eq!(new_config.scenes.len(), 1);
+        assert_eq!(new_config.scenes[0].levels.len(), 1);
+    }
+
+    #[test]
+    fn test_match_by_random_address() {
+        let mut moved = channel(5, "Hall", None);
+
+        moved.random_address = Some(0x200);
+
+        let bus_config = BusConfig {
+            channels: vec![moved, channel(7, "Stairs", None)],
+            ..bus_config(&[])
+        };
+
+        // The light lost its short address, and a new light took the address of the stairs light (which is missing)
+        let assignments = assign_addresses(
+            &bus_config,
+            vec![device(0x200, None, None), device(0x300, Some(5), Some(1))],
+        );
+
+        assert_eq!(assignments[0].short_address, 5);
+        assert_eq!(assignments[0].channel, Some(0));
+        assert_eq!(assignments[1].short_address, 0);
+        assert_eq!(assignments[1].channel, None);
+    }
+}
@@ -1,27 +1,74 @@
 
 use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BusStatus {
     Active,
     NoPower,
     Overloaded,
     Unknown,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Channel {
     pub short_address: u8,
     pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identification: Option<GearIdentification>,    // From memory bank 0 (if the light's memory bank 0 was read)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub random_address: Option<u32>,                    // Random address the light had when it was found
+    #[serde(default, skip_serializing_if = "OperatingLevels::is_empty")]
+    pub levels: OperatingLevels,                        // Operating levels that were programmed into the light
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_duration: Option<u32>,                     // Fade time (milliseconds) that was programmed into the light
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_rate: Option<u8>,                          // Fade rate (1-15) that was programmed into the light
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Light operating levels (a level that is not specified is not changed)
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OperatingLevels {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_level: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_level: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_on_level: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_failure_level: Option<u8>,
+}
+
+/// Identification of a light's control gear (decoded from memory bank 0)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GearIdentification {
+    pub gtin: u64,
+    pub firmware_version: String,
+    pub identification_number: u64,       // Serial number
+    pub hardware_version: Option<String>,
+    pub version_101: Option<u8>,
+    pub version_102: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Group {
     pub group_address: u8,     // Group number
     pub description: String,
     pub members: Vec<u8>,      // Members list (short addresses of lights in this group)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneLevel {
+    pub short_address: u8,
+    pub level: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    pub scene: u8,             // Scene number (0-15)
+    pub description: String,
+    pub levels: Vec<SceneLevel>,   // Level of each light which is part of this scene
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BusConfig {
     pub description: String,
     pub status: BusStatus,
@@ -29,6 +76,8 @@ pub struct BusConfig {
     pub channels: Vec<Channel>,
     #[serde(default)]
     pub groups: Vec<Group>,
+    #[serde(default)]
+    pub scenes: Vec<Scene>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1,9 +1,12 @@
 use error_stack::{Report, ResultExt};
-use log::{debug, info, log_enabled, trace, Level::Trace};
+use log::{debug, error, info, log_enabled, trace, Level::Trace};
 use rppal::{uart, uart::Uart};
 use std::ascii::escape_default;
+use std::collections::VecDeque;
 use std::str;
-use std::time::Duration;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 use crate::config_payload::{BusConfig, BusStatus, DaliConfig};
@@ -37,6 +40,9 @@ pub enum DaliAtxError {
     #[error("Configured for {0} while hardware reports {1}")]
     MismatchBusCount(usize, usize),
 
+    #[error("DALI HAT I/O thread is not running")]
+    InterfaceClosed,
+
     #[error("In context of '{0}'")]
     Context(String),
 }
@@ -56,9 +62,34 @@ pub enum DaliAtxError {
 
 pub type Result<T> = std::result::Result<T, Report<DaliAtxError>>;
 
-pub struct DaliAtx {
+// A frame (command line) to send to the DALI HAT, the reply line is sent back on the reply channel
+struct AtxFrame {
+    bus: usize,
+    buffer: Vec<u8>,
+    reply: mpsc::Sender<Vec<u8>>,
+}
+
+// A frame that was sent and is waiting for its reply. After the reply timeout the frame is answered with a no reply
+// line, but the bus is kept waiting (with no reply channel) until the late reply window ends, so a late reply is
+// discarded and not passed to the next frame sent to the bus.
+struct InFlightFrame {
+    reply: Option<mpsc::Sender<Vec<u8>>>,
+    deadline: Instant,
+}
+
+// The UART is owned by an I/O thread. Frames for different buses are interleaved on the UART, the replies are
+// routed back to the sender using the reply bus prefix. Each bus has at most one frame waiting for a reply.
+struct AtxPort {
     uart: Uart,
     debug_write_buffer: Vec<u8>,
+    pending: Vec<VecDeque<AtxFrame>>,
+    in_flight: Vec<Option<InFlightFrame>>,
+    line: Vec<u8>,
+}
+
+pub struct DaliAtx {
+    frames: mpsc::Sender<AtxFrame>,
+    frame_buffer: Vec<u8>,
 }
 
 impl DaliController for DaliAtx {
@@ -69,13 +100,12 @@ impl DaliController for DaliAtx {
             ))
         };
 
-        self.wait_for_idle(Duration::from_millis(DaliAtx::IDLE_TIME_MILLISECONDS));
-        self.send_command(bus, 'h')
-            .change_context_lazy(into_context)?;
-        self.send_byte_value(b1).change_context_lazy(into_context)?;
-        self.send_byte_value(b2).change_context_lazy(into_context)?;
-        self.send_nl().change_context_lazy(into_context)?;
-        self.receive_reply(bus).change_context_lazy(into_context)
+        self.send_command(bus, 'h');
+        self.send_byte_value(b1);
+        self.send_byte_value(b2);
+        let reply = self.send_nl(bus).change_context_lazy(into_context)?;
+        self.receive_reply(bus, reply)
+            .change_context_lazy(into_context)
     }
 
     fn send_2_bytes_repeat(
@@ -90,24 +120,22 @@ impl DaliController for DaliAtx {
             ))
         };
 
-        self.wait_for_idle(Duration::from_millis(DaliAtx::IDLE_TIME_MILLISECONDS));
-        self.send_command(bus, 't')
-            .change_context_lazy(into_context)?;
-        self.send_byte_value(b1).change_context_lazy(into_context)?;
-        self.send_byte_value(b2).change_context_lazy(into_context)?;
-        self.send_nl().change_context_lazy(into_context)?;
-        self.receive_reply(bus).change_context_lazy(into_context)
+        self.send_command(bus, 't');
+        self.send_byte_value(b1);
+        self.send_byte_value(b2);
+        let reply = self.send_nl(bus).change_context_lazy(into_context)?;
+        self.receive_reply(bus, reply)
+            .change_context_lazy(into_context)
     }
 
     fn get_bus_status(&mut self, bus: usize) -> dali_manager::Result<BusStatus> {
         let into_context = || DaliManagerError::Context(format!("Getting status from bus {bus}"));
 
-        self.wait_for_idle(Duration::from_millis(DaliAtx::IDLE_TIME_MILLISECONDS));
-        self.send_command(bus, 'd')
+        self.send_command(bus, 'd');
+        let reply = self.send_nl(bus).change_context_lazy(into_context)?;
+        let bus_result = self
+            .receive_reply(bus, reply)
             .change_context_lazy(into_context)?;
-        self.send_nl().change_context_lazy(into_context)?;
-
-        let bus_result = self.receive_reply(bus).change_context_lazy(into_context)?;
 
         if let DaliBusResult::Value8(v) = bus_result {
             match v >> 4 {
@@ -120,10 +148,246 @@ impl DaliController for DaliAtx {
             Err(DaliAtxError::UnexpectedBusResult(bus_result)).change_context_lazy(into_context)
         }
     }
+
+    fn clone_controller(&self) -> Box<dyn DaliController> {
+        Box::new(DaliAtx::new_handle(self.frames.clone()))
+    }
 }
 
-impl DaliAtx {
+impl AtxPort {
     const IDLE_TIME_MILLISECONDS: u64 = 10;
+    const REPLY_TIMEOUT_MILLISECONDS: u64 = 100;
+    const LATE_REPLY_MILLISECONDS: u64 = 100;
+    const READ_TIMEOUT_MILLISECONDS: u64 = 5;
+    const MAX_BUSES: usize = 4;
+
+    fn new(uart: Uart) -> AtxPort {
+        AtxPort {
+            uart,
+            debug_write_buffer: Vec::new(),
+            pending: (0..AtxPort::MAX_BUSES).map(|_| VecDeque::new()).collect(),
+            in_flight: (0..AtxPort::MAX_BUSES).map(|_| None).collect(),
+            line: Vec::new(),
+        }
+    }
+
+    fn run(mut self, frames: mpsc::Receiver<AtxFrame>) {
+        loop {
+            if self.is_idle() {
+                // Nothing is pending, wait for the next frame
+                match frames.recv() {
+                    Ok(frame) => self.queue_frame(frame),
+                    Err(_) => break,
+                }
+            }
+
+            while let Ok(frame) = frames.try_recv() {
+                self.queue_frame(frame);
+            }
+
+            self.send_pending_frames();
+
+            if let Err(e) = self.receive_lines() {
+                error!("DALI HAT I/O thread: {e:?}");
+                break;
+            }
+
+            self.expire_frames();
+        }
+
+        debug!("DALI HAT I/O thread terminated");
+    }
+
+    fn is_idle(&self) -> bool {
+        self.in_flight.iter().all(Option::is_none) && self.pending.iter().all(VecDeque::is_empty)
+    }
+
+    fn queue_frame(&mut self, frame: AtxFrame) {
+        match self.pending.get_mut(frame.bus) {
+            Some(pending) => pending.push_back(frame),
+            None => {
+                // No such bus, reply as if there was no reply from the bus
+                let _ = frame.reply.send(AtxPort::no_reply_line(frame.bus));
+            }
+        }
+    }
+
+    fn send_pending_frames(&mut self) {
+        for bus in 0..AtxPort::MAX_BUSES {
+            if self.in_flight[bus].is_some() {
+                continue;
+            }
+
+            if let Some(frame) = self.pending[bus].pop_front() {
+                // If no reply is expected from any bus, drain any stray characters before sending
+                if self.in_flight.iter().all(Option::is_none) {
+                    self.line.clear();
+                    self.wait_for_idle(Duration::from_millis(AtxPort::IDLE_TIME_MILLISECONDS));
+                }
+
+                match self.do_write(&frame.buffer) {
+                    Ok(_) => {
+                        self.in_flight[bus] = Some(InFlightFrame {
+                            reply: Some(frame.reply),
+                            deadline: Instant::now()
+                                + Duration::from_millis(AtxPort::REPLY_TIMEOUT_MILLISECONDS),
+                        })
+                    }
+                    Err(e) => {
+                        error!("Sending frame to DALI bus {bus}: {e}");
+                        let _ = frame.reply.send(AtxPort::no_reply_line(bus));
+                    }
+                }
+            }
+        }
+    }
+
+    fn receive_lines(&mut self) -> Result<()> {
+        let into_context = || DaliAtxError::Context("Getting reply lines from DALI HAT".into());
+        let mut buffer = [0u8; 32];
+
+        self.uart
+            .set_read_mode(0, Duration::from_millis(AtxPort::READ_TIMEOUT_MILLISECONDS))
+            .change_context_lazy(into_context)?;
+
+        let bytes_read = self
+            .uart
+            .read(&mut buffer)
+            .change_context_lazy(into_context)?;
+
+        for &b in &buffer[..bytes_read] {
+            self.line.push(b);
+
+            if b == b'\n' {
+                let line = std::mem::take(&mut self.line);
+                self.dispatch_line(line);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Route a reply line to the frame waiting for a reply from the bus in the line's bus prefix
+    fn dispatch_line(&mut self, line: Vec<u8>) {
+        trace!("Got reply {}", AtxPort::to_nice_string(line.as_slice()));
+
+        let bus = match line.first() {
+            Some(&b) if (b'1'..=b'3').contains(&b) => (b - b'0') as usize,
+            _ => 0,
+        };
+
+        match self.in_flight[bus].take() {
+            Some(InFlightFrame {
+                reply: Some(reply), ..
+            }) => {
+                let _ = reply.send(line);
+            }
+            Some(InFlightFrame { reply: None, .. }) => debug!(
+                "Ignoring late reply {}",
+                AtxPort::to_nice_string(line.as_slice())
+            ),
+            None => debug!(
+                "Ignoring unexpected reply {}",
+                AtxPort::to_nice_string(line.as_slice())
+            ),
+        }
+    }
+
+    fn expire_frames(&mut self) {
+        let now = Instant::now();
+
+        for bus in 0..AtxPort::MAX_BUSES {
+            if self.in_flight[bus]
+                .as_ref()
+                .is_none_or(|in_flight| in_flight.deadline > now)
+            {
+                continue;
+            }
+
+            match self.in_flight[bus].take() {
+                Some(InFlightFrame {
+                    reply: Some(reply), ..
+                }) => {
+                    trace!("Wait for reply from bus {bus} timeout - assuming no reply");
+                    let _ = reply.send(AtxPort::no_reply_line(bus));
+
+                    self.in_flight[bus] = Some(InFlightFrame {
+                        reply: None,
+                        deadline: now + Duration::from_millis(AtxPort::LATE_REPLY_MILLISECONDS),
+                    });
+                }
+                _ => trace!("No late reply from bus {bus}"),
+            }
+        }
+    }
+
+    fn no_reply_line(bus: usize) -> Vec<u8> {
+        let mut line = Vec::new();
+
+        if bus > 0 {
+            line.push(bus as u8 + b'0');
+        }
+        line.push(b'N');
+        line.push(b'\n');
+        line
+    }
+
+    fn wait_for_idle(&mut self, wait_period: Duration) {
+        debug!("Start Waiting for idle");
+        loop {
+            self.uart.set_read_mode(0, wait_period).unwrap();
+            let mut buffer = [0u8; 1];
+            if self.uart.read(&mut buffer).unwrap() == 0 {
+                // If timeout, we're idle
+                debug!("bus is idle");
+                break;
+            } else {
+                debug!("Not idle, Got byte {}", buffer[0]);
+            }
+        }
+    }
+
+    fn to_nice_string(bs: &[u8]) -> String {
+        let mut visible = String::new();
+        for &b in bs {
+            let part: Vec<u8> = escape_default(b).collect();
+            visible.push_str(str::from_utf8(&part).unwrap());
+        }
+        visible
+    }
+
+    fn flush_debug_write(&mut self) {
+        trace!(
+            "UART sent: {}",
+            AtxPort::to_nice_string(self.debug_write_buffer.as_slice())
+        );
+        self.debug_write_buffer.clear();
+    }
+
+    fn do_write(&mut self, buffer: &[u8]) -> rppal::uart::Result<usize> {
+        if log_enabled!(Trace) {
+            for b in buffer {
+                self.debug_write_buffer.push(*b);
+                if *b == b'\n' {
+                    self.flush_debug_write();
+                }
+            }
+        }
+
+        for c in buffer {
+            self.uart.write(&[*c])?;
+        }
+        Ok(buffer.len())
+    }
+}
+
+impl DaliAtx {
+    fn new_handle(frames: mpsc::Sender<AtxFrame>) -> DaliAtx {
+        DaliAtx {
+            frames,
+            frame_buffer: Vec::new(),
+        }
+    }
 
     pub fn try_new(dali_config: &mut DaliConfig) -> dali_manager::Result<Box<dyn DaliController>> {
         let into_context = || DaliManagerError::Context("Creating ATX controller".into());
@@ -184,58 +448,15 @@ impl DaliAtx {
             .change_context_lazy(into_context);
         }
 
-        Ok(Box::new(DaliAtx {
-            uart,
-            debug_write_buffer: Vec::new(),
-        }))
-    }
+        let (frames, frames_receiver) = mpsc::channel();
+        let port = AtxPort::new(uart);
 
-    fn wait_for_idle(&mut self, wait_period: Duration) {
-        debug!("Start Waiting for idle");
-        loop {
-            self.uart.set_read_mode(0, wait_period).unwrap();
-            let mut buffer = [0u8; 1];
-            if self.uart.read(&mut buffer).unwrap() == 0 {
-                // If timeout, we're idle
-                debug!("bus is idle");
-                break;
-            } else {
-                debug!("Not idle, Got byte {}", buffer[0]);
-            }
-        }
-    }
-
-    fn to_nice_string(bs: &[u8]) -> String {
-        let mut visible = String::new();
-        for &b in bs {
-            let part: Vec<u8> = escape_default(b).collect();
-            visible.push_str(str::from_utf8(&part).unwrap());
-        }
-        visible
-    }
-
-    fn flush_debug_write(&mut self) {
-        trace!(
-            "UART sent: {}",
-            DaliAtx::to_nice_string(self.debug_write_buffer.as_slice())
-        );
-        self.debug_write_buffer.clear();
-    }
-
-    fn do_write(&mut self, buffer: &[u8]) -> rppal::uart::Result<usize> {
-        if log_enabled!(Trace) {
-            for b in buffer {
-                self.debug_write_buffer.push(*b);
-                if *b == b'\n' {
-                    self.flush_debug_write();
-                }
-            }
-        }
+        thread::Builder::new()
+            .name("dali-hat".into())
+            .spawn(move || port.run(frames_receiver))
+            .change_context_lazy(into_context)?;
 
-        for c in buffer {
-            self.uart.write(&[*c])?;
-        }
-        Ok(buffer.len())
+        Ok(Box::new(DaliAtx::new_handle(frames)))
     }
 
     fn to_bus_count_string(n: usize) -> String {
@@ -259,43 +480,44 @@ impl DaliAtx {
         Ok(DaliAtx::get_digit(buffer[0])? * 16 + DaliAtx::get_digit(buffer[1])?)
     }
 
-    fn send_command(&mut self, bus: usize, command: char) -> Result<usize> {
-        let into_context =
-            || DaliAtxError::Context(format!("send_command({}, {})", bus, command));
-
-        if bus == 0 {
-            let command_buffer = [command as u8];
-            Ok(self
-                .do_write(&command_buffer)
-                .map_err(DaliAtxError::from)
-                .change_context_lazy(into_context)?)
-        } else {
-            let command_buffer = [('0' as usize + bus) as u8, command as u8];
-            Ok(self
-                .do_write(&command_buffer)
-                .map_err(DaliAtxError::from)
-                .change_context_lazy(into_context)?)
+    // Start a new frame (prefixed by the bus number for buses other than 0)
+    fn send_command(&mut self, bus: usize, command: char) {
+        self.frame_buffer.clear();
+
+        if bus != 0 {
+            self.frame_buffer.push(('0' as usize + bus) as u8);
         }
+        self.frame_buffer.push(command as u8);
     }
 
     const HEX_DIGITS: &'static [u8; 16] = b"0123456789ABCDEF";
 
-    #[allow(dead_code)]
-    fn send_byte_value(&mut self, value: u8) -> Result<usize> {
-        let into_context =
-            || DaliAtxError::Context(format!("Sending byte to DALI interface ({})", value));
-        let buffer = [
+    fn send_byte_value(&mut self, value: u8) {
+        self.frame_buffer.extend_from_slice(&[
             DaliAtx::HEX_DIGITS[(value >> 4) as usize],
             DaliAtx::HEX_DIGITS[(value & 0xf) as usize],
-        ];
-
-        self.do_write(&buffer).change_context_lazy(into_context)
+        ]);
     }
 
-    fn send_nl(&mut self) -> Result<usize> {
-        let into_context = || DaliAtxError::Context("Sending newline to DALI interface".to_string());
-        let buffer = [b'\n'];
-        self.do_write(&buffer).change_context_lazy(into_context)
+    // Terminate the frame and pass it to the I/O thread for sending. Returns the channel on which the reply line is
+    // received. The only sender of this channel is passed with the frame, so if the I/O thread terminates the channel
+    // is closed.
+    fn send_nl(&mut self, bus: usize) -> Result<mpsc::Receiver<Vec<u8>>> {
+        let into_context =
+            || DaliAtxError::Context("Sending newline to DALI interface".to_string());
+        let (reply, replies) = mpsc::channel();
+
+        self.frame_buffer.push(b'\n');
+        self.frames
+            .send(AtxFrame {
+                bus,
+                buffer: std::mem::take(&mut self.frame_buffer),
+                reply,
+            })
+            .map_err(|_| DaliAtxError::InterfaceClosed)
+            .change_context_lazy(into_context)?;
+
+        Ok(replies)
     }
 
     fn receive_value8(&self, buffer: &[u8]) -> Result<u8> {
@@ -313,51 +535,27 @@ impl DaliAtx {
             | DaliAtx::get_byte_value(&buffer[4..=5])? as u32)
     }
 
-    fn get_line(&mut self, expected_bus: usize) -> Result<Vec<u8>> {
+    // Wait for the I/O thread to pass the reply line (a synthesized no reply line on timeout)
+    fn get_line(
+        &mut self,
+        expected_bus: usize,
+        replies: mpsc::Receiver<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
         let into_context =
             || DaliAtxError::Context(format!("Getting reply line from DALI bus {expected_bus}"));
-        let mut line = Vec::new();
-
-        self.uart
-            .set_read_mode(0, Duration::from_millis(100))
-            .change_context_lazy(into_context)?;
 
-        Ok({
-            let received_line = loop {
-                let mut byte_buffer = [0u8];
-
-                let bytes_read = self
-                    .uart
-                    .read(&mut byte_buffer)
-                    .change_context_lazy(into_context)?;
-
-                if bytes_read == 0 {
-                    trace!("Wait for reply timeout - assuming no reply");
-                    if expected_bus > 0 {
-                        line.push(expected_bus as u8 + b'0');
-                    }
-                    line.push(b'N');
-                    line.push(b'\n');
-                    break line;
-                } else {
-                    line.push(byte_buffer[0]);
-
-                    if byte_buffer[0] == b'\n' {
-                        break line;
-                    }
-                }
-            };
-
-            trace!(
-                "Got reply {}",
-                DaliAtx::to_nice_string(received_line.as_slice())
-            );
-            received_line
-        })
+        replies
+            .recv()
+            .map_err(|_| DaliAtxError::InterfaceClosed)
+            .change_context_lazy(into_context)
     }
 
-    fn receive_reply(&mut self, expected_bus: usize) -> Result<DaliBusResult> {
-        let line = self.get_line(expected_bus)?;
+    fn receive_reply(
+        &mut self,
+        expected_bus: usize,
+        replies: mpsc::Receiver<Vec<u8>>,
+    ) -> Result<DaliBusResult> {
+        let line = self.get_line(expected_bus, replies)?;
         let mut i = 0;
 
         let (bus, reply_type) = {
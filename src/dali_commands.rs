@@ -302,3 +302,34 @@ pub const  DALI_RESERVED299:u16 = 0x01F7; //299  - [Reserved]
 pub const  DALI_RESERVED300:u16 = 0x01F9; //300  - [Reserved]
 pub const  DALI_RESERVED301:u16 = 0x01FB; //301  - [Reserved]
 pub const  DALI_RESERVED302:u16 = 0x01FD; //302  - [Reserved]
+
+// IEC62386-209 (device type 8 - colour control) application extended commands. These share command numbers 224-255 with IEC62386-207,
+// each one must be immediately preceded by DALI_ENABLE_DEVICE_TYPE_X with device type 8
+pub const  DALI_DT8_SET_TEMPORARY_X_COORDINATE:u16 = 224; //224 IEC62386-209 - Stores DTR1:DTR0 as temporary x-coordinate.
+pub const  DALI_DT8_SET_TEMPORARY_Y_COORDINATE:u16 = 225; //225 IEC62386-209 - Stores DTR1:DTR0 as temporary y-coordinate.
+pub const  DALI_DT8_ACTIVATE:u16 = 226; //226 IEC62386-209 - Transfers the temporary colour value to the actual colour value.
+pub const  DALI_DT8_X_COORDINATE_STEP_UP:u16 = 227; //227 IEC62386-209 - Increments the x-coordinate (without fade).
+pub const  DALI_DT8_X_COORDINATE_STEP_DOWN:u16 = 228; //228 IEC62386-209 - Decrements the x-coordinate (without fade).
+pub const  DALI_DT8_Y_COORDINATE_STEP_UP:u16 = 229; //229 IEC62386-209 - Increments the y-coordinate (without fade).
+pub const  DALI_DT8_Y_COORDINATE_STEP_DOWN:u16 = 230; //230 IEC62386-209 - Decrements the y-coordinate (without fade).
+pub const  DALI_DT8_SET_TEMPORARY_COLOUR_TEMPERATURE:u16 = 231; //231 IEC62386-209 - Stores DTR1:DTR0 as temporary colour temperature Tc (mirek).
+pub const  DALI_DT8_COLOUR_TEMPERATURE_STEP_COOLER:u16 = 232; //232 IEC62386-209 - Decrements the colour temperature Tc by one mirek (without fade).
+pub const  DALI_DT8_COLOUR_TEMPERATURE_STEP_WARMER:u16 = 233; //233 IEC62386-209 - Increments the colour temperature Tc by one mirek (without fade).
+pub const  DALI_DT8_SET_TEMPORARY_PRIMARY_N_DIMLEVEL:u16 = 234; //234 IEC62386-209 - Stores DTR1:DTR0 as temporary dim level of primary N (DTR2).
+pub const  DALI_DT8_SET_TEMPORARY_RGB_DIMLEVEL:u16 = 235; //235 IEC62386-209 - Stores DTR0, DTR1, DTR2 as temporary red, green and blue dim levels.
+pub const  DALI_DT8_SET_TEMPORARY_WAF_DIMLEVEL:u16 = 236; //236 IEC62386-209 - Stores DTR0, DTR1, DTR2 as temporary white, amber and freecolour dim levels.
+pub const  DALI_DT8_SET_TEMPORARY_RGBWAF_CONTROL:u16 = 237; //237 IEC62386-209 - Stores DTR0 as temporary RGBWAF control.
+pub const  DALI_DT8_COPY_REPORT_TO_TEMPORARY:u16 = 238; //238 IEC62386-209 - Copies the actual colour value to the temporary colour value.
+pub const  DALI_DT8_STORE_TY_PRIMARY_N:u16 = 240; //240 IEC62386-209 - Stores DTR1:DTR0 as TY value of primary N (DTR2). (Send twice)
+pub const  DALI_DT8_STORE_XY_COORDINATE_PRIMARY_N:u16 = 241; //241 IEC62386-209 - Stores the temporary xy-coordinate as primary N (DTR2). (Send twice)
+pub const  DALI_DT8_STORE_COLOUR_TEMPERATURE_LIMIT:u16 = 242; //242 IEC62386-209 - Stores DTR1:DTR0 as the colour temperature limit selected by DTR2. (Send twice)
+pub const  DALI_DT8_STORE_GEAR_FEATURES_STATUS:u16 = 243; //243 IEC62386-209 - Stores DTR0 as gear features/status. (Send twice)
+pub const  DALI_DT8_ASSIGN_COLOUR_TO_LINKED_CHANNEL:u16 = 245; //245 IEC62386-209 - Assigns the colour in DTR0 to the linked output channel. (Send twice)
+pub const  DALI_DT8_START_AUTO_CALIBRATION:u16 = 246; //246 IEC62386-209 - Starts automatic calibration of the primaries. (Send twice)
+pub const  DALI_DT8_QUERY_GEAR_FEATURES_STATUS:u16 = 247; //247 IEC62386-209 - Returns 'GEAR FEATURES/STATUS'
+pub const  DALI_DT8_QUERY_COLOUR_STATUS:u16 = 248; //248 IEC62386-209 - Returns 'COLOUR STATUS'
+pub const  DALI_DT8_QUERY_COLOUR_TYPE_FEATURES:u16 = 249; //249 IEC62386-209 - Returns 'COLOUR TYPE FEATURES'
+pub const  DALI_DT8_QUERY_COLOUR_VALUE:u16 = 250; //250 IEC62386-209 - Returns the MSB of the colour value selected by DTR0, the LSB is stored in DTR0
+pub const  DALI_DT8_QUERY_RGBWAF_CONTROL:u16 = 251; //251 IEC62386-209 - Returns 'RGBWAF CONTROL'
+pub const  DALI_DT8_QUERY_ASSIGNED_COLOUR:u16 = 252; //252 IEC62386-209 - Returns the colour assigned to the linked channel selected by DTR0
+pub const  DALI_DT8_QUERY_EXTENDED_VERSION_NUMBER:u16 = 255; //255 IEC62386-209 - Returns 2
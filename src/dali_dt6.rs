@@ -0,0 +1,149 @@
+use crate::command_payload::{DaliTarget, DimmingCurve, LedFailureStatus, LedGearInfo};
+use crate::dali_commands;
+use crate::dali_manager::{DaliBusResult, DaliManager, DaliManagerError, Result};
+use error_stack::ResultExt;
+use log::info;
+
+/// LED gear (device type 6, IEC 62386-207)
+///
+/// All commands are sent as application extended commands of device type 6, configuration commands are sent twice
+impl DaliManager<'_> {
+    const DEVICE_TYPE_LED: u8 = 6;
+    const MAX_FAST_FADE_TIME: u8 = 27;
+
+    fn query_led_byte(&mut self, bus: usize, command: u16, short_address: u8) -> Result<u8> {
+        self.query_application_extended_byte(
+            bus,
+            DaliManager::DEVICE_TYPE_LED,
+            command,
+            short_address,
+        )?
+        .ok_or(DaliManagerError::NoResult.into())
+    }
+
+    pub fn set_dimming_curve(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        curve: DimmingCurve,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set dimming curve of {target} on bus {bus} to {curve:?}"
+            ))
+        };
+
+        info!("Set dimming curve of {target} on bus {bus} to {curve:?}");
+
+        self.set_dtr(
+            bus,
+            match curve {
+                DimmingCurve::Logarithmic => 0,
+                DimmingCurve::Linear => 1,
+            },
+        )
+        .change_context_lazy(into_context)?;
+        self.send_application_extended_command(
+            bus,
+            DaliManager::DEVICE_TYPE_LED,
+            dali_commands::DALI_SELECT_DIMMING_CURVE,
+            target,
+            true,
+        )
+        .change_context_lazy(into_context)
+    }
+
+    // Fast fade time is in units of 25ms (up to 27), 0 disables fast fade. Gear may round it up to its minimum fast fade time
+    pub fn set_fast_fade_time(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        fast_fade_time: u8,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set fast fade time of {target} on bus {bus} to {fast_fade_time}"
+            ))
+        };
+
+        if fast_fade_time > DaliManager::MAX_FAST_FADE_TIME {
+            return Err(DaliManagerError::FastFadeTime(fast_fade_time))
+                .change_context_lazy(into_context);
+        }
+
+        info!("Set fast fade time of {target} on bus {bus} to {fast_fade_time}");
+
+        self.set_dtr(bus, fast_fade_time)
+            .change_context_lazy(into_context)?;
+        self.send_application_extended_command(
+            bus,
+            DaliManager::DEVICE_TYPE_LED,
+            dali_commands::DALI_STORE_DTR_AS_FAST_FADE_TIME,
+            target,
+            true,
+        )
+        .change_context_lazy(into_context)
+    }
+
+    pub fn query_led_failure_status(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+    ) -> Result<LedFailureStatus> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query LED failure status of light {short_address} on bus {bus}"
+            ))
+        };
+
+        let failure_status: LedFailureStatus = self
+            .query_led_byte(bus, dali_commands::DALI_QUERY_FAILURE_STATUS, short_address)
+            .change_context_lazy(into_context)?
+            .into();
+
+        if failure_status.is_failure() {
+            info!("Light {short_address} on bus {bus} reports failure: {failure_status:?}");
+        }
+
+        Ok(failure_status)
+    }
+
+    pub fn query_led_gear_info(&mut self, bus: usize, short_address: u8) -> Result<LedGearInfo> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query LED gear information of light {short_address} on bus {bus}"
+            ))
+        };
+        let mut query = |command| {
+            self.query_led_byte(bus, command, short_address)
+                .change_context_lazy(into_context)
+        };
+
+        let gear_type = query(dali_commands::DALI_QUERY_GEAR_TYPE)?;
+        let possible_operating_modes = query(dali_commands::DALI_QUERY_POSSIBLE_OPERATING_MODE)?;
+        let features = query(dali_commands::DALI_QUERY_FEATURES)?;
+        let operating_mode = query(dali_commands::DALI_QUERY_OPERATING_MODE)?;
+        let dimming_curve = match query(dali_commands::DALI_QUERY_DIMMING_CURVE)? {
+            1 => DimmingCurve::Linear,
+            _ => DimmingCurve::Logarithmic,
+        };
+        let fast_fade_time = query(dali_commands::DALI_QUERY_FAST_FADE_TIME)?;
+        let min_fast_fade_time = query(dali_commands::DALI_QUERY_MIN_FAST_FADE_TIME)?;
+        let extended_version = query(dali_commands::DALI_QUERY_EXTENDED_VERSION_NUMBER)?;
+        let failure_status = self
+            .query_led_failure_status(bus, short_address)
+            .change_context_lazy(into_context)?;
+
+        Ok(LedGearInfo {
+            gear_type,
+            possible_operating_modes,
+            features,
+            operating_mode,
+            failure_status,
+            dimming_curve,
+            fast_fade_time,
+            min_fast_fade_time,
+            extended_version,
+        })
+    }
+}
@@ -0,0 +1,339 @@
+use crate::command_payload::{ColorFeatures, ColorInfo, ColorStatus, DaliTarget, RgbwafColor};
+use crate::dali_commands;
+use crate::dali_manager::{DaliBusResult, DaliManager, DaliManagerError, Result};
+use error_stack::ResultExt;
+use log::info;
+
+/// Colour control (device type 8, IEC 62386-209)
+///
+/// Colour is changed by setting a temporary colour value and then activating it, all commands are sent
+/// as application extended commands of device type 8
+impl DaliManager<'_> {
+    const DEVICE_TYPE_COLOR: u8 = 8;
+
+    // QUERY COLOUR VALUE selectors (set in DTR0)
+    const COLOR_VALUE_COLOR_TEMPERATURE: u8 = 2;
+    const COLOR_VALUE_RED_DIMLEVEL: u8 = 9;
+    const COLOR_VALUE_COOLEST_COLOR_TEMPERATURE: u8 = 128;
+    const COLOR_VALUE_WARMEST_COLOR_TEMPERATURE: u8 = 130;
+
+    fn send_color_command(
+        &mut self,
+        bus: usize,
+        command: u16,
+        target: DaliTarget,
+    ) -> Result<DaliBusResult> {
+        self.send_application_extended_command(
+            bus,
+            DaliManager::DEVICE_TYPE_COLOR,
+            command,
+            target,
+            false,
+        )
+    }
+
+    fn query_color_byte(
+        &mut self,
+        bus: usize,
+        command: u16,
+        short_address: u8,
+    ) -> Result<Option<u8>> {
+        self.query_application_extended_byte(
+            bus,
+            DaliManager::DEVICE_TYPE_COLOR,
+            command,
+            short_address,
+        )
+    }
+
+    pub fn set_temporary_color_temperature(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        mirek: u16,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set temporary colour temperature {mirek} mirek for {target} on bus {bus}"
+            ))
+        };
+
+        // 0 and 0xffff (MASK) are not valid colour temperatures
+        if mirek == 0 || mirek == 0xffff {
+            return Err(DaliManagerError::ColorTemperature(mirek))
+                .change_context_lazy(into_context);
+        }
+
+        self.set_dtr(bus, mirek as u8)
+            .change_context_lazy(into_context)?;
+        self.set_dtr1(bus, (mirek >> 8) as u8)
+            .change_context_lazy(into_context)?;
+        self.send_color_command(
+            bus,
+            dali_commands::DALI_DT8_SET_TEMPORARY_COLOUR_TEMPERATURE,
+            target,
+        )
+        .change_context_lazy(into_context)
+    }
+
+    // Set three temporary dim levels (DTR0, DTR1, DTR2), 255 (MASK) leaves the level unchanged
+    fn set_temporary_dimlevels(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        command: u16,
+        levels: [Option<u8>; 3],
+    ) -> Result<DaliBusResult> {
+        self.set_dtr(bus, levels[0].unwrap_or(0xff))?;
+        self.set_dtr1(bus, levels[1].unwrap_or(0xff))?;
+        self.set_dtr2(bus, levels[2].unwrap_or(0xff))?;
+        self.send_color_command(bus, command, target)
+    }
+
+    pub fn set_temporary_rgbwaf(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        color: &RgbwafColor,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set temporary RGBWAF colour for {target} on bus {bus}"
+            ))
+        };
+
+        self.set_temporary_dimlevels(
+            bus,
+            target,
+            dali_commands::DALI_DT8_SET_TEMPORARY_RGB_DIMLEVEL,
+            [color.red, color.green, color.blue],
+        )
+        .change_context_lazy(into_context)?;
+
+        if color.white.is_some() || color.amber.is_some() || color.free_color.is_some() {
+            self.set_temporary_dimlevels(
+                bus,
+                target,
+                dali_commands::DALI_DT8_SET_TEMPORARY_WAF_DIMLEVEL,
+                [color.white, color.amber, color.free_color],
+            )
+            .change_context_lazy(into_context)?;
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    // Transfer the temporary colour value to the light (fading according to the fade time if the light is on)
+    pub fn activate_color(&mut self, bus: usize, target: DaliTarget) -> Result<DaliBusResult> {
+        let into_context =
+            || DaliManagerError::Context(format!("Activate colour of {target} on bus {bus}"));
+
+        self.send_color_command(bus, dali_commands::DALI_DT8_ACTIVATE, target)
+            .change_context_lazy(into_context)
+    }
+
+    pub fn set_color_temperature(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        mirek: u16,
+    ) -> Result<DaliBusResult> {
+        info!("Set colour temperature of {target} on bus {bus} to {mirek} mirek");
+
+        self.set_temporary_color_temperature(bus, target, mirek)?;
+        self.activate_color(bus, target)
+    }
+
+    pub fn set_color(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        color: &RgbwafColor,
+    ) -> Result<DaliBusResult> {
+        info!("Set colour of {target} on bus {bus} to {color:?}");
+
+        self.set_temporary_rgbwaf(bus, target, color)?;
+        self.activate_color(bus, target)
+    }
+
+    pub fn query_color_status(&mut self, bus: usize, short_address: u8) -> Result<ColorStatus> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query colour status of light {short_address} on bus {bus}"
+            ))
+        };
+
+        self.query_color_byte(
+            bus,
+            dali_commands::DALI_DT8_QUERY_COLOUR_STATUS,
+            short_address,
+        )
+        .change_context_lazy(into_context)?
+        .map(ColorStatus::from)
+        .ok_or(DaliManagerError::NoResult)
+        .change_context_lazy(into_context)
+    }
+
+    pub fn query_color_features(&mut self, bus: usize, short_address: u8) -> Result<ColorFeatures> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query colour type features of light {short_address} on bus {bus}"
+            ))
+        };
+
+        self.query_color_byte(
+            bus,
+            dali_commands::DALI_DT8_QUERY_COLOUR_TYPE_FEATURES,
+            short_address,
+        )
+        .change_context_lazy(into_context)?
+        .map(ColorFeatures::from)
+        .ok_or(DaliManagerError::NoResult)
+        .change_context_lazy(into_context)
+    }
+
+    // Query a 16 bit colour value, the light replies with the MSB and places the LSB in DTR0. MASK (0xffff) means
+    // that the value is not known
+    fn query_color_value(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+        selector: u8,
+    ) -> Result<Option<u16>> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query colour value {selector} of light {short_address} on bus {bus}"
+            ))
+        };
+
+        self.set_dtr(bus, selector)
+            .change_context_lazy(into_context)?;
+
+        let Some(msb) = self
+            .query_color_byte(
+                bus,
+                dali_commands::DALI_DT8_QUERY_COLOUR_VALUE,
+                short_address,
+            )
+            .change_context_lazy(into_context)?
+        else {
+            return Ok(None);
+        };
+        let lsb = self
+            .send_command_to_address_and_get_byte(
+                bus,
+                dali_commands::DALI_QUERY_CONTENT_DTR0,
+                short_address,
+                false,
+            )
+            .change_context_lazy(into_context)?;
+        let value = ((msb as u16) << 8) | lsb as u16;
+
+        Ok(if value == 0xffff { None } else { Some(value) })
+    }
+
+    // Query an 8 bit colour value (dim level), 255 (MASK) means that the value is not known
+    fn query_color_level(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+        selector: u8,
+    ) -> Result<Option<u8>> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query colour level {selector} of light {short_address} on bus {bus}"
+            ))
+        };
+
+        self.set_dtr(bus, selector)
+            .change_context_lazy(into_context)?;
+
+        Ok(self
+            .query_color_byte(
+                bus,
+                dali_commands::DALI_DT8_QUERY_COLOUR_VALUE,
+                short_address,
+            )
+            .change_context_lazy(into_context)?
+            .filter(|level| *level != 0xff))
+    }
+
+    pub fn query_color(&mut self, bus: usize, short_address: u8) -> Result<ColorInfo> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query colour of light {short_address} on bus {bus}"
+            ))
+        };
+
+        let status = self
+            .query_color_status(bus, short_address)
+            .change_context_lazy(into_context)?;
+        let features = self
+            .query_color_features(bus, short_address)
+            .change_context_lazy(into_context)?;
+
+        let (color_temperature, coolest_color_temperature, warmest_color_temperature) =
+            if features.color_temperature_capable {
+                (
+                    self.query_color_value(
+                        bus,
+                        short_address,
+                        DaliManager::COLOR_VALUE_COLOR_TEMPERATURE,
+                    )
+                    .change_context_lazy(into_context)?,
+                    self.query_color_value(
+                        bus,
+                        short_address,
+                        DaliManager::COLOR_VALUE_COOLEST_COLOR_TEMPERATURE,
+                    )
+                    .change_context_lazy(into_context)?,
+                    self.query_color_value(
+                        bus,
+                        short_address,
+                        DaliManager::COLOR_VALUE_WARMEST_COLOR_TEMPERATURE,
+                    )
+                    .change_context_lazy(into_context)?,
+                )
+            } else {
+                (None, None, None)
+            };
+
+        let color = if features.rgbwaf_channels > 0 {
+            let mut levels = [None; 6];
+
+            for (channel, level) in levels
+                .iter_mut()
+                .enumerate()
+                .take(features.rgbwaf_channels as usize)
+            {
+                *level = self
+                    .query_color_level(
+                        bus,
+                        short_address,
+                        DaliManager::COLOR_VALUE_RED_DIMLEVEL + channel as u8,
+                    )
+                    .change_context_lazy(into_context)?;
+            }
+
+            Some(RgbwafColor {
+                red: levels[0],
+                green: levels[1],
+                blue: levels[2],
+                white: levels[3],
+                amber: levels[4],
+                free_color: levels[5],
+            })
+        } else {
+            None
+        };
+
+        Ok(ColorInfo {
+            status,
+            features,
+            color_temperature,
+            coolest_color_temperature,
+            warmest_color_temperature,
+            color,
+        })
+    }
+}
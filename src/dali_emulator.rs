@@ -1,5 +1,5 @@
 use rand::random_range;
-use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
 use log::{info, trace, error, log_enabled, Level::Trace};
 use crate::dali_commands::{self};
 use crate::dali_manager;
@@ -10,65 +10,202 @@ use crate::setup::Setup;
 #[derive(Debug)]
 struct DaliLightEmulator {
     light_number: usize,
+    version: u8,
     initialize_mode: bool,
     brightness: u8,
     short_address: u8,
     random_address: u32,
     search_address: u32,
     enable_compare: bool,
-    selected: bool,
     group_mask: u16,
     dtr: [u8; 3],
+    scenes: [u8; 16],
+    min_level: u8,
+    max_level: u8,
+    power_on_level: u8,
+    system_failure_level: u8,
+    fade_time_rate: u8,
+    extended_fade_time: u8,
+    write_enabled: bool,
+    memory_banks: [Vec<u8>; 2],
+    enabled_device_type: Option<u8>,        // Device type enabled (for the next command only) by ENABLE DEVICE TYPE
+    next_device_type: usize,
+    color_temperature: u16,                 // DT8 colour state (temporary values are MASK if not set)
+    temporary_color_temperature: u16,
+    rgbwaf: [u8; 6],
+    temporary_rgbwaf: [u8; 6],
+    color_status: u8,
+    dimming_curve: u8,                      // DT6 state
+    fast_fade_time: u8,
+    failure_status: u8,
 }
 
 #[derive(Debug)]
 pub struct DaliBusEmulator {
     bus_number: usize,
-    lights: RefCell<Vec<DaliLightEmulator>>,
+    lights: Mutex<Vec<DaliLightEmulator>>,
+    #[cfg(test)]
+    sent_frames: Mutex<Vec<(u8, u8)>>,     // Frames sent on the bus (checked by tests)
 }
 
 pub struct DaliControllerEmulator {
-    buses: Vec<DaliBusEmulator>,
+    buses: Arc<Vec<DaliBusEmulator>>,       // Shared by all handles to the emulated controller
 }
 
 impl DaliLightEmulator {
+    const VERSION: u8 = 0x08;       // DALI-2 (IEC 62386-102 version 2.0)
+    const DEVICE_TYPES: [u8; 2] = [6, 8];       // LED modules (IEC 62386-207) with colour control (IEC 62386-209)
+    const PHYSICAL_MIN_LEVEL: u8 = 1;
+    const COLOR_TYPE_FEATURES: u8 = 0x62;       // Colour temperature capable, 3 RGBWAF channels (RGB)
+    const COOLEST_COLOR_TEMPERATURE: u16 = 153; // 6500K
+    const WARMEST_COLOR_TEMPERATURE: u16 = 370; // 2700K
+    const GEAR_TYPE: u8 = 0x05;                 // Integrated LED power supply, AC supply
+    const POSSIBLE_OPERATING_MODES: u8 = 0x01;  // PWM
+    const LED_FEATURES: u8 = 0x63;              // Short circuit, open circuit, thermal shutdown and thermal overload detection
+    const MIN_FAST_FADE_TIME: u8 = 4;
+
     fn new(light_number: usize) -> DaliLightEmulator {
         DaliLightEmulator {
              light_number,
+             version: DaliLightEmulator::VERSION,
              initialize_mode: false,
              brightness: 0,
              short_address: 0xff,
              search_address: 0,
              random_address: 0x0fff,
              enable_compare: false,
-             selected: false,
              group_mask: 0,
-             dtr: [0, 0, 0]
+             dtr: [0, 0, 0],
+             scenes: [0xff; 16],
+             min_level: DaliLightEmulator::PHYSICAL_MIN_LEVEL,
+             max_level: 254,
+             power_on_level: 254,
+             system_failure_level: 254,
+             fade_time_rate: 0x07,
+             extended_fade_time: 0,
+             write_enabled: false,
+             memory_banks: DaliLightEmulator::new_memory_banks(),
+             enabled_device_type: None,
+             next_device_type: 0,
+             color_temperature: DaliLightEmulator::WARMEST_COLOR_TEMPERATURE,
+             temporary_color_temperature: 0xffff,
+             rgbwaf: [254, 254, 254, 0xff, 0xff, 0xff],
+             temporary_rgbwaf: [0xff; 6],
+             color_status: 0x20,
+             dimming_curve: 0,
+             fast_fade_time: 0,
+             failure_status: 0,
         }
     }
 
     fn new_with_config(light_number: usize, short_address: u8, group_mask: u16) -> DaliLightEmulator {
         DaliLightEmulator {
             light_number,
+            version: DaliLightEmulator::VERSION,
             initialize_mode: false,
             brightness: 0,
             short_address,
             search_address: 0,
             random_address: 0x0fff,
             enable_compare: false,
-            selected: false,
             group_mask,
-            dtr: [0, 0, 0]
+            dtr: [0, 0, 0],
+            scenes: [0xff; 16],
+            min_level: DaliLightEmulator::PHYSICAL_MIN_LEVEL,
+            max_level: 254,
+            power_on_level: 254,
+            system_failure_level: 254,
+            fade_time_rate: 0x07,
+            extended_fade_time: 0,
+            write_enabled: false,
+            memory_banks: DaliLightEmulator::new_memory_banks(),
+            enabled_device_type: None,
+            next_device_type: 0,
+            color_temperature: DaliLightEmulator::WARMEST_COLOR_TEMPERATURE,
+            temporary_color_temperature: 0xffff,
+            rgbwaf: [254, 254, 254, 0xff, 0xff, 0xff],
+            temporary_rgbwaf: [0xff; 6],
+            color_status: 0x20,
+            dimming_curve: 0,
+            fast_fade_time: 0,
+            failure_status: 0,
        }
     }
 
+    // Memory bank 0 (identification) and memory bank 1 (writable)
+    fn new_memory_banks() -> [Vec<u8>; 2] {
+        let mut bank0 = vec![0x1a, 0xff, 0x01];
+
+        bank0.extend_from_slice(&[0x00, 0x00, 0x12, 0x34, 0x56, 0x78]);     // GTIN
+        bank0.extend_from_slice(&[0x01, 0x00]);                             // Firmware version
+        bank0.extend_from_slice(&random_range(0..=u64::MAX).to_be_bytes()); // Identification (serial) number
+        bank0.extend_from_slice(&[0x01, 0x00]);                             // Hardware version
+        bank0.extend_from_slice(&[0xff, DaliLightEmulator::VERSION, 0xff, 0x00, 0x01, 0x00]);
+
+        let mut bank1 = vec![0x0f, 0x00, 0xff];
+        bank1.resize(0x10, 0x00);
+
+        [bank0, bank1]
+    }
+
     fn command(&mut self, command: u16, parameter: u8) -> Option<u8> {
+        if !matches!(command, dali_commands::DALI_ENABLE_WRITE_MEMORY | dali_commands::DALI_WRITE_MEMORY_LOCATION |
+            dali_commands::DALI_DATA_TRANSFER_REGISTER0 | dali_commands::DALI_DATA_TRANSFER_REGISTER1 | dali_commands::DALI_DATA_TRANSFER_REGISTER2) {
+            self.write_enabled = false;
+        }
+
         match command {
+            dali_commands::DALI_OFF => self.set_brightness(0),
+            dali_commands::DALI_UP | dali_commands::DALI_STEP_UP => if self.brightness != 0 { self.set_brightness(self.brightness.saturating_add(1).min(254)) },
+            dali_commands::DALI_DOWN | dali_commands::DALI_STEP_DOWN => if self.brightness != 0 { self.set_brightness(self.brightness.saturating_sub(1).max(1)) },
+            dali_commands::DALI_STEP_DOWN_AND_OFF => self.set_brightness(self.brightness.saturating_sub(1)),
+            dali_commands::DALI_ON_AND_STEP_UP => self.set_brightness(self.brightness.saturating_add(1).min(254)),
+            dali_commands::DALI_RECALL_MAX_LEVEL => self.set_brightness(254),
+            dali_commands::DALI_RECALL_MIN_LEVEL => self.set_brightness(1),
+            dali_commands::DALI_RESET => self.reset(),
+            dali_commands::DALI_RESET_MEMORY_BANK => self.reset_memory_bank(),
+            dali_commands::DALI_SAVE_PERSISTENT_VARIABLES => info!("DALI light {}:{} save persistent variables", self.light_number, self.short_address),
+            dali_commands::DALI_IDENTIFY_DEVICE => self.identify_device(),
             dali_commands::DALI_ADD_TO_GROUP0..=dali_commands::DALI_ADD_TO_GROUP15 => self.add_to_group(command-dali_commands::DALI_ADD_TO_GROUP0),
             dali_commands::DALI_REMOVE_FROM_GROUP0..=dali_commands::DALI_REMOVE_FROM_GROUP15 => self.remove_from_group(command-dali_commands::DALI_REMOVE_FROM_GROUP0),
+            dali_commands::DALI_GO_TO_SCENE0..=dali_commands::DALI_GO_TO_SCENE15 => self.go_to_scene(command-dali_commands::DALI_GO_TO_SCENE0),
+            dali_commands::DALI_SET_SCENE0..=dali_commands::DALI_SET_SCENE15 => self.set_scene(command-dali_commands::DALI_SET_SCENE0),
+            dali_commands::DALI_REMOVE_FROM_SCENE0..=dali_commands::DALI_REMOVE_FROM_SCENE15 => self.remove_from_scene(command-dali_commands::DALI_REMOVE_FROM_SCENE0),
+            dali_commands::DALI_QUERY_STATUS => return Some(self.query_status()),
+            dali_commands::DALI_QUERY_ACTUAL_LEVEL => return Some(self.brightness),
+            dali_commands::DALI_QUERY_GROUPS_0_7 => return Some(self.group_mask as u8),
+            dali_commands::DALI_QUERY_GROUPS_8_15 => return Some((self.group_mask >> 8) as u8),
+            dali_commands::DALI_QUERY_SCENE0_LEVEL..=dali_commands::DALI_QUERY_SCENE15_LEVEL => return Some(self.scenes[(command-dali_commands::DALI_QUERY_SCENE0_LEVEL) as usize]),
+            dali_commands::DALI_QUERY_VERSION_NUMBER => return Some(self.version),
+            dali_commands::DALI_QUERY_DEVICE_TYPE => { self.next_device_type = 0; return Some(0xff) },    // MASK - more than one device type
+            dali_commands::DALI_QUERY_NEXT_DEVICE_TYPE => return Some(self.query_next_device_type()),
+            dali_commands::DALI_QUERY_CONTENT_DTR0 => return Some(self.dtr[0]),
+            dali_commands::DALI_QUERY_PHYSICAL_MINIMUM_LEVEL => return Some(DaliLightEmulator::PHYSICAL_MIN_LEVEL),
+            dali_commands::DALI_QUERY_MIN_LEVEL => return Some(self.min_level),
+            dali_commands::DALI_QUERY_MAX_LEVEL => return Some(self.max_level),
+            dali_commands::DALI_QUERY_POWER_ON_LEVEL => return Some(self.power_on_level),
+            dali_commands::DALI_QUERY_SYSTEM_FAILURE_LEVEL => return Some(self.system_failure_level),
+            dali_commands::DALI_QUERY_FADE_TIME_FADE_RATE => return Some(self.fade_time_rate),
+            dali_commands::DALI_QUERY_EXTENDED_FADE_TIME => return Some(self.extended_fade_time),
+            dali_commands::DALI_QUERY_RANDOM_ADDRESS_H => return Some((self.random_address >> 16) as u8),
+            dali_commands::DALI_QUERY_RANDOM_ADDRESS_M => return Some((self.random_address >> 8) as u8),
+            dali_commands::DALI_QUERY_RANDOM_ADDRESS_L => return Some(self.random_address as u8),
+            dali_commands::DALI_SET_FADE_TIME => self.fade_time_rate = (self.dtr[0].min(15) << 4) | (self.fade_time_rate & 0x0f),
+            dali_commands::DALI_SET_FADE_RATE => self.fade_time_rate = (self.fade_time_rate & 0xf0) | self.dtr[0].clamp(1, 15),
+            dali_commands::DALI_SET_EXTENDED_FADE_TIME => self.extended_fade_time = if self.dtr[0] > 0x4f { 0 } else { self.dtr[0] },
+            dali_commands::DALI_SET_MAX_LEVEL => self.max_level = self.dtr[0].clamp(self.min_level, 254),
+            dali_commands::DALI_SET_MIN_LEVEL => self.min_level = self.dtr[0].clamp(DaliLightEmulator::PHYSICAL_MIN_LEVEL, self.max_level),
+            dali_commands::DALI_SET_POWER_ON_LEVEL => self.power_on_level = self.dtr[0],
+            dali_commands::DALI_SET_SYSTEM_FAILURE_LEVEL => self.system_failure_level = self.dtr[0],
             dali_commands::DALI_SET_SHORT_ADDRESS => self.set_short_address(),
             dali_commands::DALI_TERMINATE => self.terminate_initialize_mode(),
             dali_commands::DALI_DATA_TRANSFER_REGISTER0 => self.set_dtr(0, parameter),
+            dali_commands::DALI_DATA_TRANSFER_REGISTER1 => self.set_dtr(1, parameter),
+            dali_commands::DALI_DATA_TRANSFER_REGISTER2 => self.set_dtr(2, parameter),
+            dali_commands::DALI_ENABLE_DEVICE_TYPE_X => self.enabled_device_type = Some(parameter),
+            dali_commands::DALI_READ_MEMORY_LOCATION => return self.read_memory_location(),
+            dali_commands::DALI_ENABLE_WRITE_MEMORY => self.write_enabled = true,
+            dali_commands::DALI_WRITE_MEMORY_LOCATION => return self.write_memory_location(parameter),
             dali_commands::DALI_INITIALISE => self.start_initialize_mode(parameter),
             dali_commands::DALI_RANDOMISE => self.randomize(),
             dali_commands::DALI_COMPARE => return self.compare(),
@@ -77,6 +214,8 @@ impl DaliLightEmulator {
             dali_commands::DALI_SEARCHADDRM => self.set_search_address_middle(parameter),
             dali_commands::DALI_SEARCHADDRL => self.set_search_address_low(parameter),
             dali_commands::DALI_PROGRAM_SHORT_ADDRESS => self.program_short_address(parameter),
+            dali_commands::DALI_QUERY_SHORT_ADDRESS => return self.query_short_address(),
+            dali_commands::DALI_VERIFY_SHORT_ADDRESS => return self.verify_short_address(parameter),
 
             _ => error!("DALI Light {} - Unsupported command {} ({:#03x})", self.light_number, command, command),
         }
@@ -87,8 +226,60 @@ impl DaliLightEmulator {
         (0b10100000..=0b11001011).contains(&b1) || (0b11001100..=0b11111011).contains(&b1)
     }
 
+    // Application extended commands (224-255) are interpreted according to the device type enabled by the previous command
+    fn application_extended_command(&mut self, device_type: Option<u8>, command: u16) -> Option<u8> {
+        match device_type {
+            Some(6) => self.led_command(command),
+            Some(8) => self.color_command(command),
+            _ => { error!("DALI Light {} - Unsupported application extended command {} (device type {:?})", self.light_number, command, device_type); None },
+        }
+    }
+
+    fn led_command(&mut self, command: u16) -> Option<u8> {
+        match command {
+            dali_commands::DALI_SELECT_DIMMING_CURVE => if self.dtr[0] < 2 { self.dimming_curve = self.dtr[0] },
+            dali_commands::DALI_STORE_DTR_AS_FAST_FADE_TIME => self.store_fast_fade_time(),
+            dali_commands::DALI_QUERY_GEAR_TYPE => return Some(DaliLightEmulator::GEAR_TYPE),
+            dali_commands::DALI_QUERY_DIMMING_CURVE => return Some(self.dimming_curve),
+            dali_commands::DALI_QUERY_POSSIBLE_OPERATING_MODE => return Some(DaliLightEmulator::POSSIBLE_OPERATING_MODES),
+            dali_commands::DALI_QUERY_FEATURES => return Some(DaliLightEmulator::LED_FEATURES),
+            dali_commands::DALI_QUERY_FAILURE_STATUS => return Some(self.failure_status),
+            dali_commands::DALI_QUERY_SHORT_CIRCUIT..=dali_commands::DALI_QUERY_THERMAL_OVERLOAD => {
+                let bit = command - dali_commands::DALI_QUERY_SHORT_CIRCUIT;
+                return if (self.failure_status & (1 << bit)) != 0 { Some(0xff) } else { None }
+            },
+            dali_commands::DALI_QUERY_CURRENT_PROTECTOR_ENABLE => return None,
+            dali_commands::DALI_QUERY_OPERATING_MODE => return Some(0x01),
+            dali_commands::DALI_QUERY_FAST_FADE_TIME => return Some(self.fast_fade_time),
+            dali_commands::DALI_QUERY_MIN_FAST_FADE_TIME => return Some(DaliLightEmulator::MIN_FAST_FADE_TIME),
+            dali_commands::DALI_QUERY_EXTENDED_VERSION_NUMBER => return Some(1),
+
+            _ => error!("DALI Light {} - Unsupported LED command {} ({:#03x})", self.light_number, command, command),
+        }
+        None
+    }
+
+    fn color_command(&mut self, command: u16) -> Option<u8> {
+        match command {
+            dali_commands::DALI_DT8_SET_TEMPORARY_COLOUR_TEMPERATURE => self.temporary_color_temperature = ((self.dtr[1] as u16) << 8) | self.dtr[0] as u16,
+            dali_commands::DALI_DT8_SET_TEMPORARY_RGB_DIMLEVEL => self.temporary_rgbwaf[0..3].copy_from_slice(&self.dtr),
+            dali_commands::DALI_DT8_SET_TEMPORARY_WAF_DIMLEVEL => self.temporary_rgbwaf[3..6].copy_from_slice(&self.dtr),
+            dali_commands::DALI_DT8_ACTIVATE => self.activate_color(),
+            dali_commands::DALI_DT8_QUERY_GEAR_FEATURES_STATUS => return Some(0),
+            dali_commands::DALI_DT8_QUERY_COLOUR_STATUS => return Some(self.color_status),
+            dali_commands::DALI_DT8_QUERY_COLOUR_TYPE_FEATURES => return Some(DaliLightEmulator::COLOR_TYPE_FEATURES),
+            dali_commands::DALI_DT8_QUERY_COLOUR_VALUE => return Some(self.query_color_value()),
+            dali_commands::DALI_DT8_QUERY_EXTENDED_VERSION_NUMBER => return Some(2),
+
+            _ => error!("DALI Light {} - Unsupported colour command {} ({:#03x})", self.light_number, command, command),
+        }
+        None
+    }
+
     // Receive 2 bytes DALI command
     pub fn receive_2_bytes(&mut self, b1: u8, b2: u8) -> Option<u8> {
+        let enabled_device_type = self.enabled_device_type.take();
+
         if (b1  & 0x01) == 0 && !DaliLightEmulator::is_special_command(b1) { // b2 is light level
             let mut set_my_brightness = false;
 
@@ -123,7 +314,13 @@ impl DaliLightEmulator {
                 my_command = true;    // broadcast
             }
 
-            if my_command { self.command(command, b2) } else { None }
+            if !my_command {
+                None
+            } else if command >= 224 && !DaliLightEmulator::is_special_command(b1) {
+                self.application_extended_command(enabled_device_type, command)
+            } else {
+                self.command(command, b2)
+            }
         }
     }
 
@@ -155,13 +352,39 @@ impl DaliLightEmulator {
         self.group_mask &= !(1 << group_number);
     }
 
+    fn query_status(&self) -> u8 {
+        let mut status = 0u8;
+
+        if self.brightness != 0 { status |= 0x04 }          // Lamp on
+        if self.short_address == 0xff { status |= 0x40 }    // Missing short address
+        status
+    }
+
+    fn go_to_scene(&mut self, scene: u16) {
+        let level = self.scenes[scene as usize];
+
+        if level != 0xff {
+            info!("DALI light {}:{} go to scene {}", self.light_number, self.short_address, scene);
+            self.set_brightness(level);
+        }
+    }
+
+    fn set_scene(&mut self, scene: u16) {
+        info!("DALI light {}:{} scene {} level set to {}", self.light_number, self.short_address, scene, self.dtr[0]);
+        self.scenes[scene as usize] = self.dtr[0];
+    }
+
+    fn remove_from_scene(&mut self, scene: u16) {
+        info!("DALI light {}:{} removed from scene {}", self.light_number, self.short_address, scene);
+        self.scenes[scene as usize] = 0xff;
+    }
+
     fn start_initialize_mode(&mut self, parameter: u8) {
         
         if (parameter == 0xff && self.short_address == 0xff) || parameter == 0 || ((parameter & 0x01) != 0 && (parameter >> 1) == self.short_address) {
             info!("DALI light {} start initialization mode", self.light_number);
             self.initialize_mode = true;
             self.enable_compare = true;
-            self.selected = false;
         }
     }
 
@@ -181,15 +404,124 @@ impl DaliLightEmulator {
         }
     }
 
+    fn read_memory_location(&mut self) -> Option<u8> {
+        let value = *self.memory_banks.get(self.dtr[1] as usize)?.get(self.dtr[0] as usize)?;
+
+        self.dtr[0] = self.dtr[0].saturating_add(1);
+        Some(value)
+    }
+
+    fn write_memory_location(&mut self, value: u8) -> Option<u8> {
+        if !self.write_enabled || self.dtr[1] == 0 || self.dtr[0] < 2 {
+            return None;
+        }
+
+        let location = self.memory_banks.get_mut(self.dtr[1] as usize)?.get_mut(self.dtr[0] as usize)?;
+
+        info!("DALI light {}:{} memory bank {} location {} set to {}", self.light_number, self.short_address, self.dtr[1], self.dtr[0], value);
+        *location = value;
+        self.dtr[0] = self.dtr[0].saturating_add(1);
+        Some(value)
+    }
+
+    fn store_fast_fade_time(&mut self) {
+        let fast_fade_time = self.dtr[0];
+
+        if fast_fade_time <= 27 {
+            self.fast_fade_time = if fast_fade_time == 0 { 0 } else { fast_fade_time.max(DaliLightEmulator::MIN_FAST_FADE_TIME) };
+            info!("DALI light {}:{} fast fade time set to {}", self.light_number, self.short_address, self.fast_fade_time);
+        }
+    }
+
+    // Reset variables to their default values (short address, random address and memory bank 0 are not changed)
+    fn reset(&mut self) {
+        info!("DALI light {}:{} reset", self.light_number, self.short_address);
+
+        self.brightness = 254;
+        self.group_mask = 0;
+        self.scenes = [0xff; 16];
+        self.min_level = DaliLightEmulator::PHYSICAL_MIN_LEVEL;
+        self.max_level = 254;
+        self.power_on_level = 254;
+        self.system_failure_level = 254;
+        self.fade_time_rate = 0x07;
+        self.extended_fade_time = 0;
+        self.color_temperature = DaliLightEmulator::WARMEST_COLOR_TEMPERATURE;
+        self.rgbwaf = [254, 254, 254, 0xff, 0xff, 0xff];
+        self.color_status = 0x20;
+        self.dimming_curve = 0;
+        self.fast_fade_time = 0;
+    }
+
+    fn identify_device(&mut self) {
+        if self.version >= DaliLightEmulator::VERSION {
+            info!("DALI light {}:{} identifying", self.light_number, self.short_address);
+        } else {
+            error!("DALI Light {} - IDENTIFY DEVICE is not supported by DALI-1 gear", self.light_number);
+        }
+    }
+
+    fn reset_memory_bank(&mut self) {
+        if self.dtr[0] == 0 || self.dtr[0] == 1 {
+            info!("DALI light {}:{} reset memory bank 1", self.light_number, self.short_address);
+            self.memory_banks[1] = DaliLightEmulator::new_memory_banks()[1].clone();
+        }
+    }
+
+    fn query_next_device_type(&mut self) -> u8 {
+        let device_type = DaliLightEmulator::DEVICE_TYPES.get(self.next_device_type).copied().unwrap_or(0xfe);
+
+        self.next_device_type += 1;
+        device_type
+    }
+
+    fn activate_color(&mut self) {
+        if self.temporary_color_temperature != 0xffff {
+            let color_temperature = self.temporary_color_temperature.clamp(DaliLightEmulator::COOLEST_COLOR_TEMPERATURE, DaliLightEmulator::WARMEST_COLOR_TEMPERATURE);
+
+            info!("DALI light {}:{} colour temperature set to {} mirek", self.light_number, self.short_address, color_temperature);
+            self.color_status = if color_temperature != self.temporary_color_temperature { 0x22 } else { 0x20 };
+            self.color_temperature = color_temperature;
+        } else if self.temporary_rgbwaf.iter().any(|level| *level != 0xff) {
+            // Only RGB channels are implemented
+            for (level, temporary_level) in self.rgbwaf.iter_mut().zip(self.temporary_rgbwaf.iter()).take(3) {
+                if *temporary_level != 0xff {
+                    *level = *temporary_level;
+                }
+            }
+
+            info!("DALI light {}:{} colour set to {:?}", self.light_number, self.short_address, &self.rgbwaf[0..3]);
+            self.color_status = 0x80;
+        }
+
+        self.temporary_color_temperature = 0xffff;
+        self.temporary_rgbwaf = [0xff; 6];
+    }
+
+    // Reply with the MSB of 16 bit values (the LSB is placed in DTR0) or with 8 bit values (dim levels)
+    fn query_color_value(&mut self) -> u8 {
+        let value = match self.dtr[0] {
+            2 => self.color_temperature,
+            128 => DaliLightEmulator::COOLEST_COLOR_TEMPERATURE,
+            130 => DaliLightEmulator::WARMEST_COLOR_TEMPERATURE,
+            selector @ 9..=14 => return self.rgbwaf[(selector - 9) as usize],
+            _ => 0xffff,
+        };
+
+        self.dtr[0] = value as u8;
+        (value >> 8) as u8
+    }
+
     fn randomize(&mut self) {
-        self.random_address = random_range(0..=0x0fff_u32);
-        info!("DALI light {} randomized address set to {}", self.light_number, self.random_address);
+        if self.initialize_mode {
+            self.random_address = random_range(0..=0x0fff_u32);
+            info!("DALI light {} randomized address set to {}", self.light_number, self.random_address);
+        }
     }
 
     fn compare(&mut self) -> Option<u8> {
         if self.enable_compare {
             info!("DALI light {} check if random {} <= search {} ", self.light_number, self.random_address, self.search_address);
-            self.selected = self.random_address == self.search_address;
             if self.random_address <= self.search_address { Some(0xff) } else { None }
         } else {
             info!("DALI light {} not participating in compare", self.light_number);
@@ -197,8 +529,13 @@ impl DaliLightEmulator {
         }
     }
 
+    // A light is selected when it is in initialize mode and its random address is equal to the search address
+    fn is_selected(&self) -> bool {
+        self.initialize_mode && self.random_address == self.search_address
+    }
+
     fn withdraw(&mut self) {
-        if self.selected {
+        if self.is_selected() {
             info!("DALI light {} withdrawing from compare process", self.light_number);
             self.enable_compare = false;
         } else{
@@ -225,13 +562,25 @@ impl DaliLightEmulator {
     }
 
     fn program_short_address(&mut self, parameter: u8) {
-        if self.selected {
-            let short_address = parameter >> 1;
+        if self.is_selected() {
+            let short_address = if parameter == 0xff { 0xff } else { parameter >> 1 };
             info!("DALI light {} is selected, set short address to {}", self.light_number, short_address);
             self.short_address = short_address;
         }
     }
 
+    fn query_short_address(&self) -> Option<u8> {
+        if self.is_selected() {
+            Some(if self.short_address == 0xff { 0xff } else { (self.short_address << 1) | 0x01 })
+        } else {
+            None
+        }
+    }
+
+    fn verify_short_address(&self, parameter: u8) -> Option<u8> {
+        if self.initialize_mode && self.short_address != 0xff && (parameter >> 1) == self.short_address { Some(0xff) } else { None }
+    }
+
 }
 
 impl DaliBusEmulator {
@@ -242,7 +591,7 @@ impl DaliBusEmulator {
             lights.push(DaliLightEmulator::new(light_number));
         }
 
-        DaliBusEmulator { bus_number, lights: RefCell::new(lights) }
+        DaliBusEmulator { bus_number, lights: Mutex::new(lights), #[cfg(test)] sent_frames: Mutex::new(Vec::new()) }
     }
 
     pub fn new_with_config(bus_config: &BusConfig) -> DaliBusEmulator {
@@ -260,7 +609,15 @@ impl DaliBusEmulator {
             lights.push(DaliLightEmulator::new_with_config(light_number, channel.short_address, group_mask));
         }
 
-        DaliBusEmulator { bus_number: bus_config.bus, lights: RefCell::new(lights) }
+        DaliBusEmulator { bus_number: bus_config.bus, lights: Mutex::new(lights), #[cfg(test)] sent_frames: Mutex::new(Vec::new()) }
+    }
+
+    // Emulate DALI-1 gear (version 1.0)
+    #[cfg(test)]
+    pub fn set_dali1(&self) {
+        for dali_light in self.lights.lock().unwrap().iter_mut() {
+            dali_light.version = 0x01;
+        }
     }
 
     pub fn send_2_bytes(&self, b1: u8, b2: u8) -> DaliBusResult {
@@ -268,7 +625,10 @@ impl DaliBusEmulator {
 
         let mut result = DaliBusResult::None;
 
-        for dali_light in self.lights.borrow_mut().iter_mut() {
+        #[cfg(test)]
+        self.sent_frames.lock().unwrap().push((b1, b2));
+
+        for dali_light in self.lights.lock().unwrap().iter_mut() {
             result = match dali_light.receive_2_bytes(b1, b2) {
                 Some(x) => match result {
                     DaliBusResult::None => DaliBusResult::Value8(x),
@@ -280,9 +640,9 @@ impl DaliBusEmulator {
             }
         }
 
-        if !log_enabled!(Trace) { 
+        if !log_enabled!(Trace) && !cfg!(test) { 
             // Emulate real time - bus speed is 1200bps, transaction is (2 bytes message + 1 byte reply = 30 bits (inc stop bits)) total of 1200/30 = 40 messages per second, so
-            // each message is 1000/40 = 25 milliseconds 
+            // each message is 1000/40 = 25 milliseconds (tests run without delay)
             std::thread::sleep(std::time::Duration::from_millis(25));
         }
 
@@ -291,6 +651,15 @@ impl DaliBusEmulator {
 }
 
 impl DaliControllerEmulator {
+    pub fn new(buses: Vec<DaliBusEmulator>) -> DaliControllerEmulator {
+        DaliControllerEmulator { buses: Arc::new(buses) }
+    }
+
+    #[cfg(test)]
+    pub fn sent_frames(&self, bus: usize) -> Vec<(u8, u8)> {
+        self.buses[bus].sent_frames.lock().unwrap().clone()
+    }
+
     pub fn try_new(dali_config: &mut DaliConfig) -> dali_manager::Result<Box<dyn DaliController>> {
         let mut buses: Vec<DaliBusEmulator> = Vec::new();
 
@@ -309,7 +678,7 @@ impl DaliControllerEmulator {
             }
         }
 
-        Ok(Box::new(DaliControllerEmulator{ buses }))
+        Ok(Box::new(DaliControllerEmulator::new(buses)))
     }
 }
 
@@ -329,4 +698,8 @@ impl DaliController for DaliControllerEmulator {
     fn get_bus_status(&mut self, _bus: usize) -> dali_manager::Result<BusStatus> {
         Ok(BusStatus::Active)
     }
+
+    fn clone_controller(&self) -> Box<dyn DaliController> {
+        Box::new(DaliControllerEmulator { buses: self.buses.clone() })
+    }
 }
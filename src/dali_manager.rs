@@ -1,8 +1,14 @@
-use crate::command_payload::LightStatus;
-use crate::config_payload::{BusConfig, BusStatus, Channel, Group};
+use crate::command_payload::{
+    ArcPowerCommand, DaliTarget, DeviceInfo, FadeSettings, LightStatus, MemoryBank,
+};
+use crate::config_payload::{
+    BusConfig, BusStatus, Channel, GearIdentification, Group, OperatingLevels,
+};
 use crate::dali_commands;
+use crate::reconcile;
 use error_stack::{Report, ResultExt};
-use log::{debug, info};
+use log::{debug, error, info};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{thread::sleep, time::Duration};
 use thiserror::Error;
 
@@ -31,6 +37,24 @@ pub enum DaliManagerError {
     #[error("Invalid fade time: {0}")]
     FadeTime(u8),
 
+    #[error("Invalid fade rate: {0}")]
+    FadeRate(u8),
+
+    #[error("Invalid scene number: {0}")]
+    Scene(u8),
+
+    #[error("Invalid colour temperature: {0} mirek")]
+    ColorTemperature(u16),
+
+    #[error("Invalid fast fade time: {0}")]
+    FastFadeTime(u8),
+
+    #[error("Memory bank {0} location {1} is read only")]
+    MemoryReadOnly(u8, u8),
+
+    #[error("Write to memory bank {0} location {1} failed")]
+    MemoryWriteFailed(u8, u8),
+
     #[error("Unexpected light status {0:?}")]
     UnexpectedStatus(DaliBusResult),
 
@@ -52,6 +76,21 @@ pub enum DaliManagerError {
     #[error("No value was returned from the DALI bus")]
     NoResult,
 
+    #[error("Programming short address {0} failed (light with random address {1:#08x})")]
+    ProgramShortAddressFailed(u8, u32),
+
+    #[error("Light did not confirm programming of short address {0}")]
+    ShortAddressNotVerified(u8),
+
+    #[error("Search did not converge on bus {0} (lights did not answer consistently)")]
+    SearchFailed(usize),
+
+    #[error("Light {0} still answers, it does not need to be replaced")]
+    LightStillAnswers(u8),
+
+    #[error("Expected one light without short address, found {0}")]
+    ReplacementLightCount(usize),
+
     #[error("In context of '{0}'")]
     Context(String),
 }
@@ -59,11 +98,32 @@ pub enum DaliManagerError {
 pub type Result<T> = std::result::Result<T, Report<DaliManagerError>>;
 pub type FindDeviceProgress = Box<dyn Fn(u8, u8)>;
 pub type MatchGroupProgress = Box<dyn Fn(MatchGroupAction, &str)>;
+pub type AuditBusProgress = Box<dyn Fn(AuditBusAction)>;
 
-pub trait DaliController {
+pub trait DaliController: Send {
     fn send_2_bytes(&mut self, bus: usize, b1: u8, b2: u8) -> Result<DaliBusResult>;
     fn send_2_bytes_repeat(&mut self, bus: usize, b1: u8, b2: u8) -> Result<DaliBusResult>;
     fn get_bus_status(&mut self, bus: usize) -> Result<BusStatus>;
+
+    // Get another handle to the same controller, so a long operation on one bus can run (on another thread)
+    // while commands are sent to the other buses
+    fn clone_controller(&self) -> Box<dyn DaliController>;
+}
+
+impl From<ArcPowerCommand> for u16 {
+    fn from(command: ArcPowerCommand) -> Self {
+        match command {
+            ArcPowerCommand::Off => dali_commands::DALI_OFF,
+            ArcPowerCommand::Up => dali_commands::DALI_UP,
+            ArcPowerCommand::Down => dali_commands::DALI_DOWN,
+            ArcPowerCommand::StepUp => dali_commands::DALI_STEP_UP,
+            ArcPowerCommand::StepDown => dali_commands::DALI_STEP_DOWN,
+            ArcPowerCommand::StepDownAndOff => dali_commands::DALI_STEP_DOWN_AND_OFF,
+            ArcPowerCommand::OnAndStepUp => dali_commands::DALI_ON_AND_STEP_UP,
+            ArcPowerCommand::RecallMaxLevel => dali_commands::DALI_RECALL_MAX_LEVEL,
+            ArcPowerCommand::RecallMinLevel => dali_commands::DALI_RECALL_MIN_LEVEL,
+        }
+    }
 }
 
 pub struct DaliManager<'a> {
@@ -77,6 +137,10 @@ pub struct DaliBusIterator {
     previous_mid_byte: Option<u8>,
     previous_high_byte: Option<u8>,
     short_address: u8,
+    random_address: Option<u32>, // Random address of the last device that was found
+    current_short_address: Option<u8>, // Short address the last device that was found had
+    search_low: u32,             // Lowest random address of the devices that were not found yet
+    randomise_count: usize,
     terminate: bool,
 }
 
@@ -91,7 +155,24 @@ pub enum MatchGroupAction<'a> {
     RemoveMember(&'a str),
 }
 
+/// Short address that was used by more than one light, and the addresses the other lights were moved to
+#[derive(Debug, Clone)]
+pub struct AddressConflict {
+    pub short_address: u8,
+    pub moved_to: Vec<u8>,
+}
+
+pub enum AuditBusAction<'a> {
+    CheckAddress(u8),
+    ConflictResolved(&'a AddressConflict),
+}
+
 impl<'manager> DaliManager<'manager> {
+    const BROADCAST_LIGHT_ADDRESS: u8 = 0xfe;
+    const BROADCAST_COMMAND_ADDRESS: u8 = 0xff;
+    const IDENTIFY_SECONDS: u32 = 10; // Identification time of DALI-2 gear
+    const STANDARD_FADE_TIME_TOLERANCE: u32 = 4; // Standard fade time is used if within 1/4 of the requested duration
+
     pub fn new(controller: &'manager mut dyn DaliController) -> DaliManager<'manager> {
         DaliManager { controller }
     }
@@ -122,20 +203,6 @@ impl<'manager> DaliManager<'manager> {
         }
     }
 
-    pub async fn set_light_brightness_async(
-        &mut self,
-        bus: usize,
-        short_address: u8,
-        value: u8,
-    ) -> Result<DaliBusResult> {
-        info!("Set light {short_address} on bus {bus} to {value}");
-        self.controller.send_2_bytes(
-            bus,
-            DaliManager::to_light_short_address(short_address),
-            value,
-        )
-    }
-
     pub fn set_light_brightness(
         &mut self,
         bus: usize,
@@ -150,17 +217,6 @@ impl<'manager> DaliManager<'manager> {
         )
     }
 
-    pub async fn set_group_brightness_async(
-        &mut self,
-        bus: usize,
-        group: u8,
-        value: u8,
-    ) -> Result<DaliBusResult> {
-        info!("Set group {group} on bus {bus} to {value}");
-        self.controller
-            .send_2_bytes(bus, DaliManager::to_light_group_address(group), value)
-    }
-
     pub fn set_group_brightness(
         &mut self,
         bus: usize,
@@ -175,6 +231,13 @@ impl<'manager> DaliManager<'manager> {
         )
     }
 
+    // Set the level of all lights on the bus using a single (broadcast) frame
+    pub fn set_bus_brightness(&mut self, bus: usize, level: u8) -> Result<DaliBusResult> {
+        info!("Set all lights on bus {bus} to {level}");
+        self.controller
+            .send_2_bytes(bus, DaliManager::BROADCAST_LIGHT_ADDRESS, level)
+    }
+
     pub fn send_command_to_address(
         &mut self,
         bus: usize,
@@ -239,7 +302,6 @@ impl<'manager> DaliManager<'manager> {
         }
     }
 
-    #[allow(dead_code)]
     pub fn send_command_to_group(
         &mut self,
         bus: usize,
@@ -275,6 +337,56 @@ impl<'manager> DaliManager<'manager> {
         }
     }
 
+    pub fn send_command_to_target(
+        &mut self,
+        bus: usize,
+        command: u16,
+        target: DaliTarget,
+        repeat: bool,
+    ) -> Result<DaliBusResult> {
+        let into_context =
+            || DaliManagerError::Context(format!("Sending command {command:04x} to {target}"));
+
+        match target {
+            DaliTarget::Light(short_address) => {
+                self.send_command_to_address(bus, command, short_address, repeat)
+            }
+            DaliTarget::Group(group_address) => {
+                self.send_command_to_group(bus, command, group_address, repeat)
+            }
+            DaliTarget::Bus => self.send_command_to_bus(bus, command, repeat),
+        }
+        .change_context_lazy(into_context)
+    }
+
+    // Send a (non special) command to all lights on the bus using a single (broadcast) frame
+    pub fn send_command_to_bus(
+        &mut self,
+        bus: usize,
+        command: u16,
+        repeat: bool,
+    ) -> Result<DaliBusResult> {
+        let into_context =
+            || DaliManagerError::Context(format!("Sending command {command:04x} to bus {bus}"));
+
+        if command > 0xff {
+            return Err(DaliManagerError::Command(command)).change_context_lazy(into_context);
+        }
+
+        let b1 = DaliManager::BROADCAST_COMMAND_ADDRESS;
+        let b2 = (command & 0xff) as u8;
+
+        if repeat {
+            self.controller
+                .send_2_bytes_repeat(bus, b1, b2)
+                .change_context_lazy(into_context)
+        } else {
+            self.controller
+                .send_2_bytes(bus, b1, b2)
+                .change_context_lazy(into_context)
+        }
+    }
+
     fn is_collision(result: &DaliBusResult) -> bool {
         matches!(
             result,
@@ -390,16 +502,32 @@ impl<'manager> DaliManager<'manager> {
         )
         .change_context_lazy(into_context)?;
 
-        // loop {
-        //     self.broadcast_command(bus, dali_commands::DALI_PROGRAM_SHORT_ADDRESS, (short_address << 1) | 0x01, false, &format!("Program short address {}", short_address))?;
+        // All lights in initialize state that have this short address answer, so a collision means that another light
+        // already had this address
+        match self
+            .broadcast_command_allow_collision(
+                bus,
+                dali_commands::DALI_VERIFY_SHORT_ADDRESS,
+                (short_address << 1) | 0x01,
+                false,
+                "Verify short address",
+            )
+            .change_context_lazy(into_context)?
+        {
+            DaliBusResult::Value8(_) => {}
+            DaliBusResult::ReceiveCollision => error!("Short address {short_address} on bus {bus} is used by more than one light (use AuditBus to resolve)"),
+            _ => {
+                return Err(DaliManagerError::ShortAddressNotVerified(short_address))
+                    .change_context_lazy(into_context)
+            }
+        }
 
-        //     let actual_short_address = self.query_short_address(bus)? >> 1;
-        //     debug!("Actual short address {actual_short_address}");
+        self.withdraw(bus).change_context_lazy(into_context)
+    }
 
-        //     if actual_short_address == short_address {
-        //         break;
-        //     }
-        // }
+    // Exclude the selected device (random address is equal to the search address) from the rest of the search
+    pub fn withdraw(&mut self, bus: usize) -> Result<()> {
+        let into_context = || DaliManagerError::Context(format!("Withdraw device on bus {bus}"));
 
         loop {
             let status = self
@@ -416,112 +544,763 @@ impl<'manager> DaliManager<'manager> {
         Ok(())
     }
 
-    pub fn set_dtr(&mut self, bus: usize, value: u8) -> Result<DaliBusResult> {
-        let into_context = || DaliManagerError::Context(format!("Set DTR on bus {bus} to {value}"));
-        self.broadcast_command(
-            bus,
-            dali_commands::DALI_DATA_TRANSFER_REGISTER0,
-            value,
-            false,
-            &format!("Set DTR to {}", value),
-        )
-        .change_context_lazy(into_context)
+    // Get the short address of the selected device (None if it has no short address)
+    pub fn query_short_address(&mut self, bus: usize) -> Result<Option<u8>> {
+        let into_context =
+            || DaliManagerError::Context(format!("Query short address on bus {bus}"));
+
+        match self
+            .broadcast_command(
+                bus,
+                dali_commands::DALI_QUERY_SHORT_ADDRESS,
+                0,
+                false,
+                "Query short address",
+            )
+            .change_context_lazy(into_context)?
+        {
+            DaliBusResult::None | DaliBusResult::Value8(0xff) => Ok(None),
+            DaliBusResult::Value8(v) => Ok(Some(v >> 1)),
+            bus_result => Err(DaliManagerError::UnexpectedStatus(bus_result))
+                .change_context_lazy(into_context),
+        }
     }
 
-    pub fn set_light_fade_time(
+    pub fn set_search_address(&mut self, bus: usize, search_address: u32) -> Result<()> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set search address {search_address:#08x} on bus {bus}"
+            ))
+        };
+
+        for (command, value) in [
+            (
+                dali_commands::DALI_SEARCHADDRH,
+                (search_address >> 16) as u8,
+            ),
+            (dali_commands::DALI_SEARCHADDRM, (search_address >> 8) as u8),
+            (dali_commands::DALI_SEARCHADDRL, search_address as u8),
+        ] {
+            self.broadcast_command(bus, command, value, false, "Set search address")
+                .change_context_lazy(into_context)?;
+        }
+
+        Ok(())
+    }
+
+    // Find the lights that use a short address by searching only the lights with this address. The first light that is
+    // found keeps the address, the others are moved to unused addresses (which are added to used_addresses). Returns the
+    // addresses the lights were moved to.
+    pub fn resolve_address_conflict(
         &mut self,
         bus: usize,
         short_address: u8,
-        fade_time: u8,
-    ) -> Result<DaliBusResult> {
+        used_addresses: &mut Vec<u8>,
+    ) -> Result<Vec<u8>> {
         let into_context = || {
             DaliManagerError::Context(format!(
-                "Set fade time {fade_time} for short address {short_address}"
+                "Resolve address conflict of short address {short_address} on bus {bus}"
             ))
         };
-        if short_address >= 64 {
-            return Err(DaliManagerError::ShortAddress(short_address))
-                .change_context_lazy(into_context);
-        }
 
-        if fade_time > 15 {
-            return Err(DaliManagerError::FadeTime(fade_time)).change_context_lazy(into_context);
+        let mut device_iterator =
+            DaliBusIterator::new(self, bus, DaliDeviceSelection::Address(short_address), None)
+                .change_context_lazy(into_context)?;
+        let mut found_count = 0;
+        let mut moved_to = Vec::new();
+
+        while device_iterator
+            .find_next_device(self)
+            .change_context_lazy(into_context)?
+            .is_some()
+        {
+            found_count += 1;
+
+            let new_address = if found_count > 1 {
+                (0..64u8).find(|address| !used_addresses.contains(address))
+            } else {
+                None
+            };
+
+            match new_address {
+                Some(new_address) => {
+                    info!("Light with duplicate short address {short_address} on bus {bus} is moved to address {new_address}");
+                    self.program_short_address(bus, new_address)
+                        .change_context_lazy(into_context)?;
+                    used_addresses.push(new_address);
+                    moved_to.push(new_address);
+                }
+                None => {
+                    if found_count > 1 {
+                        error!("No unused short address for light with duplicate short address {short_address} on bus {bus}");
+                        device_iterator.terminate();
+                    }
+
+                    self.withdraw(bus).change_context_lazy(into_context)?;
+                }
+            }
         }
 
-        self.set_dtr(bus, fade_time)
-            .change_context_lazy(into_context)?;
+        Ok(moved_to)
+    }
 
-        self.send_command_to_address(bus, dali_commands::DALI_SET_FADE_TIME, short_address, true)
+    // Find the short addresses that are used on the bus by more than one light. Conflicts are resolved by
+    // moving the duplicate lights to unused addresses, the moved lights are added to the bus configuration (with the
+    // groups they are member of).
+    pub fn audit_bus(
+        &mut self,
+        bus_config: &mut BusConfig,
+        progress: Option<AuditBusProgress>,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<Vec<AddressConflict>> {
+        let bus = bus_config.bus;
+        let into_context = || DaliManagerError::Context(format!("Audit bus {bus}"));
+
+        // Lights using the same address may happen to send the same reply to a query, so the addresses are checked by
+        // searching all the lights once (without changing their random address) and collecting their short addresses
+        let mut device_iterator = DaliBusIterator::new_keeping_random_addresses(
+            self,
+            bus,
+            DaliDeviceSelection::All,
+            None,
+        )
+        .change_context_lazy(into_context)?;
+        let mut found_addresses = Vec::new();
+
+        device_iterator
+            .for_each_device(self, cancel, |device_iterator| {
+                if let Some(short_address) = device_iterator.current_short_address() {
+                    if let Some(progress) = progress.as_ref() {
+                        progress(AuditBusAction::CheckAddress(short_address));
+                    }
+
+                    found_addresses.push(short_address);
+                }
+            })
             .change_context_lazy(into_context)?;
 
-        if fade_time == 0 {
-            // Since DTR is 0 which means that the extended fade time multiplier is 0, its should disable fading
-            self.send_command_to_address(
-                bus,
-                dali_commands::DALI_SET_EXTENDED_FADE_TIME,
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            info!("Audit of bus {bus} was cancelled");
+            return Ok(Vec::new());
+        }
+
+        let mut duplicate_addresses: Vec<u8> = found_addresses
+            .iter()
+            .copied()
+            .filter(|short_address| {
+                found_addresses
+                    .iter()
+                    .filter(|found_address| *found_address == short_address)
+                    .count()
+                    > 1
+            })
+            .collect();
+
+        duplicate_addresses.sort();
+        duplicate_addresses.dedup();
+
+        let mut used_addresses = found_addresses;
+
+        used_addresses.extend(
+            bus_config
+                .channels
+                .iter()
+                .map(|channel| channel.short_address),
+        );
+
+        let mut conflicts = Vec::new();
+
+        // Only the addresses used by more than one light are searched again to move the duplicate lights
+        for short_address in duplicate_addresses {
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                info!("Audit of bus {bus} was cancelled");
+                break;
+            }
+
+            let moved_to = self
+                .resolve_address_conflict(bus, short_address, &mut used_addresses)
+                .change_context_lazy(into_context)?;
+
+            if moved_to.is_empty() {
+                continue;
+            }
+
+            for new_address in moved_to.iter().copied() {
+                let group_mask = self
+                    .query_group_membership(bus, new_address)
+                    .change_context_lazy(into_context)?;
+
+                bus_config.channels.push(Channel {
+                    short_address: new_address,
+                    description: format!("Light {}", new_address),
+                    identification: self
+                        .query_gear_identification(bus, new_address)
+                        .unwrap_or(None),
+                    random_address: None,
+                    levels: OperatingLevels::default(),
+                    fade_duration: None,
+                    fade_rate: None,
+                });
+
+                for group in bus_config.groups.iter_mut() {
+                    if group_mask & (1 << group.group_address) != 0 {
+                        group.members.push(new_address);
+                    }
+                }
+            }
+
+            bus_config
+                .channels
+                .sort_by_key(|channel| channel.short_address);
+
+            let conflict = AddressConflict {
                 short_address,
-                true,
-            )
-            .change_context_lazy(into_context)?;
+                moved_to,
+            };
+
+            if let Some(progress) = progress.as_ref() {
+                progress(AuditBusAction::ConflictResolved(&conflict));
+            }
+
+            conflicts.push(conflict);
         }
 
-        Ok(DaliBusResult::None)
+        Ok(conflicts)
     }
 
-    pub fn set_group_fade_time(
+    // Replace failed gear: a new light (without short address) takes over the short address of a light that no longer
+    // answers, and the light's configuration (groups, scenes, fade and operating levels) is programmed into it
+    pub fn replace_light(
         &mut self,
-        bus: usize,
-        group_address: u8,
-        fade_time: u8,
+        bus_config: &mut BusConfig,
+        short_address: u8,
     ) -> Result<DaliBusResult> {
-        let into_context = || {
-            DaliManagerError::Context(format!(
-                "Set fade time {fade_time} for group address {group_address}"
-            ))
-        };
-        if group_address >= 16 {
-            return Err(DaliManagerError::GroupAddress(group_address))
+        let bus = bus_config.bus;
+        let into_context =
+            || DaliManagerError::Context(format!("Replace light {short_address} on bus {bus}"));
+
+        // The status query is retried, so a light that misses it is not taken for a failed light
+        if let DaliBusResult::Value8(_) | DaliBusResult::ReceiveCollision =
+            reconcile::query_status(self, bus, short_address).change_context_lazy(into_context)?
+        {
+            return Err(DaliManagerError::LightStillAnswers(short_address))
                 .change_context_lazy(into_context);
         }
 
-        if fade_time > 15 {
-            return Err(DaliManagerError::FadeTime(fade_time)).change_context_lazy(into_context);
-        }
+        // Search all lights without short address, a short address is programmed only if exactly one light was found
+        let mut device_iterator =
+            DaliBusIterator::new(self, bus, DaliDeviceSelection::WithoutShortAddress, None)
+                .change_context_lazy(into_context)?;
+        let mut random_addresses = Vec::new();
 
-        self.set_dtr(bus, fade_time)
+        device_iterator
+            .for_each_device(self, None, |device_iterator| {
+                random_addresses.extend(device_iterator.random_address())
+            })
             .change_context_lazy(into_context)?;
 
-        self.send_command_to_group(bus, dali_commands::DALI_SET_FADE_TIME, group_address, true)
+        let [random_address] = random_addresses[..] else {
+            return Err(DaliManagerError::ReplacementLightCount(
+                random_addresses.len(),
+            ))
+            .change_context_lazy(into_context);
+        };
+
+        info!("Light with random address {random_address:#08x} replaces light {short_address} on bus {bus}");
+        self.program_short_addresses(bus, &[(random_address, short_address)])
             .change_context_lazy(into_context)?;
 
-        if fade_time == 0 {
-            // Since DTR is 0 which means that the extended fade time multiplier is 0, its should disable fading
-            self.send_command_to_group(
-                bus,
-                dali_commands::DALI_SET_EXTENDED_FADE_TIME,
-                group_address,
-                true,
-            )
+        let target = DaliTarget::Light(short_address);
+
+        for group in bus_config.groups.iter() {
+            if group.members.contains(&short_address) {
+                self.add_to_group_and_verify(bus, group.group_address, short_address)
+                    .change_context_lazy(into_context)?;
+            }
+        }
+
+        self.restore_scenes(bus_config, short_address)
             .change_context_lazy(into_context)?;
+
+        let identification = self
+            .query_gear_identification(bus, short_address)
+            .unwrap_or(None);
+
+        if let Some(index) = bus_config.get_channel_index(short_address) {
+            let channel = bus_config.channels[index].clone();
+
+            if !channel.levels.is_empty() {
+                self.set_operating_levels(bus, target, &channel.levels)
+                    .change_context_lazy(into_context)?;
+            }
+            if let Some(fade_duration) = channel.fade_duration {
+                self.set_fade_duration(bus, target, fade_duration)
+                    .change_context_lazy(into_context)?;
+            }
+            if let Some(fade_rate) = channel.fade_rate {
+                self.set_fade_rate(bus, target, fade_rate)
+                    .change_context_lazy(into_context)?;
+            }
+
+            bus_config.channels[index].identification = identification;
+            bus_config.channels[index].random_address = Some(random_address);
         }
 
         Ok(DaliBusResult::None)
     }
 
-    pub fn query_group_membership(&mut self, bus: usize, short_address: u8) -> Result<u16> {
-        let into_context = || {
-            DaliManagerError::Context(format!(
-                "Query group membership for short address {short_address} on bus {bus}"
-            ))
-        };
+    // Program the short addresses of devices that were found by a previous search (given as random address and short
+    // address pairs). The devices are initialized without randomizing, so they keep their random address.
+    pub fn program_short_addresses(&mut self, bus: usize, addresses: &[(u32, u8)]) -> Result<()> {
+        let into_context =
+            || DaliManagerError::Context(format!("Program short addresses on bus {bus}"));
 
-        let groups_0to7 = self
-            .send_command_to_address_and_get_byte(
-                bus,
-                dali_commands::DALI_QUERY_GROUPS_0_7,
-                short_address,
-                false,
-            )
+        if addresses.is_empty() {
+            return Ok(());
+        }
+
+        self.broadcast_command(bus, dali_commands::DALI_INITIALISE, 0, true, "Initialize")
+            .change_context_lazy(into_context)?;
+        sleep(Duration::from_millis(400));
+
+        for &(random_address, short_address) in addresses {
+            debug!("Program short address {short_address} to light with random address {random_address:#08x}");
+
+            self.set_search_address(bus, random_address)
+                .change_context_lazy(into_context)?;
+            self.broadcast_command(
+                bus,
+                dali_commands::DALI_PROGRAM_SHORT_ADDRESS,
+                (short_address << 1) | 0x01,
+                false,
+                &format!("Program short address {}", short_address),
+            )
+            .change_context_lazy(into_context)?;
+
+            if self
+                .query_short_address(bus)
+                .change_context_lazy(into_context)?
+                != Some(short_address)
+            {
+                // Do not leave the bus in initialize state
+                let _ = self.broadcast_command(
+                    bus,
+                    dali_commands::DALI_TERMINATE,
+                    0,
+                    false,
+                    "Terminate",
+                );
+                return Err(DaliManagerError::ProgramShortAddressFailed(
+                    short_address,
+                    random_address,
+                ))
+                .change_context_lazy(into_context);
+            }
+        }
+
+        self.broadcast_command(bus, dali_commands::DALI_TERMINATE, 0, false, "Terminate")
+            .change_context_lazy(into_context)?;
+        Ok(())
+    }
+
+    pub fn set_dtr(&mut self, bus: usize, value: u8) -> Result<DaliBusResult> {
+        let into_context = || DaliManagerError::Context(format!("Set DTR on bus {bus} to {value}"));
+        self.broadcast_command(
+            bus,
+            dali_commands::DALI_DATA_TRANSFER_REGISTER0,
+            value,
+            false,
+            &format!("Set DTR to {}", value),
+        )
+        .change_context_lazy(into_context)
+    }
+
+    pub fn set_dtr1(&mut self, bus: usize, value: u8) -> Result<DaliBusResult> {
+        let into_context =
+            || DaliManagerError::Context(format!("Set DTR1 on bus {bus} to {value}"));
+        self.broadcast_command(
+            bus,
+            dali_commands::DALI_DATA_TRANSFER_REGISTER1,
+            value,
+            false,
+            &format!("Set DTR1 to {}", value),
+        )
+        .change_context_lazy(into_context)
+    }
+
+    pub fn set_dtr2(&mut self, bus: usize, value: u8) -> Result<DaliBusResult> {
+        let into_context =
+            || DaliManagerError::Context(format!("Set DTR2 on bus {bus} to {value}"));
+        self.broadcast_command(
+            bus,
+            dali_commands::DALI_DATA_TRANSFER_REGISTER2,
+            value,
+            false,
+            &format!("Set DTR2 to {}", value),
+        )
+        .change_context_lazy(into_context)
+    }
+
+    // Enable the application extended commands (224-255) of a device type for the next command
+    pub fn enable_device_type(&mut self, bus: usize, device_type: u8) -> Result<DaliBusResult> {
+        let into_context =
+            || DaliManagerError::Context(format!("Enable device type {device_type} on bus {bus}"));
+        self.broadcast_command(
+            bus,
+            dali_commands::DALI_ENABLE_DEVICE_TYPE_X,
+            device_type,
+            false,
+            &format!("Enable device type {}", device_type),
+        )
+        .change_context_lazy(into_context)
+    }
+
+    // Send an application extended command, commands that should be sent twice are repeated as a whole
+    // (enable device type followed by the command)
+    pub fn send_application_extended_command(
+        &mut self,
+        bus: usize,
+        device_type: u8,
+        command: u16,
+        target: DaliTarget,
+        repeat: bool,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Sending device type {device_type} command {command} to {target} on bus {bus}"
+            ))
+        };
+
+        for _ in 0..(if repeat { 2 } else { 1 }) {
+            self.enable_device_type(bus, device_type)
+                .change_context_lazy(into_context)?;
+            self.send_command_to_target(bus, command, target, false)
+                .change_context_lazy(into_context)?;
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    // Send an application extended query, return None if the light does not reply (e.g. it does not support the device type)
+    pub fn query_application_extended_byte(
+        &mut self,
+        bus: usize,
+        device_type: u8,
+        command: u16,
+        short_address: u8,
+    ) -> Result<Option<u8>> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query device type {device_type} command {command} from light {short_address} on bus {bus}"
+            ))
+        };
+
+        self.enable_device_type(bus, device_type)
+            .change_context_lazy(into_context)?;
+        self.query_optional_byte(bus, command, short_address)
+            .change_context_lazy(into_context)
+    }
+
+    pub fn set_light_fade_time(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+        fade_time: u8,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set fade time {fade_time} for short address {short_address}"
+            ))
+        };
+        if short_address >= 64 {
+            return Err(DaliManagerError::ShortAddress(short_address))
+                .change_context_lazy(into_context);
+        }
+
+        if fade_time > 15 {
+            return Err(DaliManagerError::FadeTime(fade_time)).change_context_lazy(into_context);
+        }
+
+        self.set_dtr(bus, fade_time)
+            .change_context_lazy(into_context)?;
+
+        self.send_command_to_address(bus, dali_commands::DALI_SET_FADE_TIME, short_address, true)
+            .change_context_lazy(into_context)?;
+
+        if fade_time == 0 {
+            // Since DTR is 0 which means that the extended fade time multiplier is 0, its should disable fading
+            self.send_command_to_address(
+                bus,
+                dali_commands::DALI_SET_EXTENDED_FADE_TIME,
+                short_address,
+                true,
+            )
+            .change_context_lazy(into_context)?;
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    pub fn set_group_fade_time(
+        &mut self,
+        bus: usize,
+        group_address: u8,
+        fade_time: u8,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set fade time {fade_time} for group address {group_address}"
+            ))
+        };
+        if group_address >= 16 {
+            return Err(DaliManagerError::GroupAddress(group_address))
+                .change_context_lazy(into_context);
+        }
+
+        if fade_time > 15 {
+            return Err(DaliManagerError::FadeTime(fade_time)).change_context_lazy(into_context);
+        }
+
+        self.set_dtr(bus, fade_time)
+            .change_context_lazy(into_context)?;
+
+        self.send_command_to_group(bus, dali_commands::DALI_SET_FADE_TIME, group_address, true)
+            .change_context_lazy(into_context)?;
+
+        if fade_time == 0 {
+            // Since DTR is 0 which means that the extended fade time multiplier is 0, its should disable fading
+            self.send_command_to_group(
+                bus,
+                dali_commands::DALI_SET_EXTENDED_FADE_TIME,
+                group_address,
+                true,
+            )
+            .change_context_lazy(into_context)?;
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    // Program the specified operating levels (each level is set by storing it in DTR0)
+    pub fn set_operating_levels(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        levels: &OperatingLevels,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set operating levels {levels:?} for {target} on bus {bus}"
+            ))
+        };
+
+        info!("Set operating levels {levels:?} for {target} on bus {bus}");
+
+        for (command, level) in [
+            (dali_commands::DALI_SET_MAX_LEVEL, levels.max_level),
+            (dali_commands::DALI_SET_MIN_LEVEL, levels.min_level),
+            (
+                dali_commands::DALI_SET_POWER_ON_LEVEL,
+                levels.power_on_level,
+            ),
+            (
+                dali_commands::DALI_SET_SYSTEM_FAILURE_LEVEL,
+                levels.system_failure_level,
+            ),
+        ] {
+            if let Some(level) = level {
+                self.set_dtr(bus, level).change_context_lazy(into_context)?;
+                self.send_command_to_target(bus, command, target, true)
+                    .change_context_lazy(into_context)?;
+            }
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    pub fn query_operating_levels(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+    ) -> Result<OperatingLevels> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query operating levels of light {short_address} on bus {bus}"
+            ))
+        };
+        let mut query = |command: u16| {
+            self.send_command_to_address_and_get_byte(bus, command, short_address, false)
+                .change_context_lazy(into_context)
+                .map(Some)
+        };
+
+        Ok(OperatingLevels {
+            max_level: query(dali_commands::DALI_QUERY_MAX_LEVEL)?,
+            min_level: query(dali_commands::DALI_QUERY_MIN_LEVEL)?,
+            power_on_level: query(dali_commands::DALI_QUERY_POWER_ON_LEVEL)?,
+            system_failure_level: query(dali_commands::DALI_QUERY_SYSTEM_FAILURE_LEVEL)?,
+        })
+    }
+
+    // Standard fade time (code 1-15) is 0.5 * sqrt(2^code) seconds
+    fn standard_fade_duration(fade_time: u8) -> u32 {
+        (500.0 * 2f64.powf(fade_time as f64 / 2.0)).round() as u32
+    }
+
+    // Extended fade time (DALI-2) is base (bits 3-0, 1-16) multiplied by 100ms, 1s, 10s or 1min (bits 6-4, 1-4)
+    fn extended_fade_duration(extended_fade_time: u8) -> u32 {
+        let base = (extended_fade_time & 0x0f) as u32 + 1;
+
+        match (extended_fade_time >> 4) & 0x07 {
+            1 => base * 100,
+            2 => base * 1000,
+            3 => base * 10_000,
+            4 => base * 60_000,
+            _ => 0,
+        }
+    }
+
+    // Fade duration (milliseconds) of fade time and extended fade time (which is used only if fade time is 0)
+    pub fn decode_fade_duration(fade_time: u8, extended_fade_time: Option<u8>) -> u32 {
+        match (fade_time, extended_fade_time) {
+            (0, Some(extended_fade_time)) => {
+                DaliManager::extended_fade_duration(extended_fade_time)
+            }
+            (0, None) => 0,
+            (fade_time, _) => DaliManager::standard_fade_duration(fade_time),
+        }
+    }
+
+    // Get the (fade time, extended fade time) encoding for the requested duration. Standard fade time is used if it is
+    // close enough to the requested duration (DALI-1 gear does not support extended fade time, and fades instantly
+    // when its fade time is 0), otherwise the closest extended fade time is used
+    pub fn encode_fade_duration(fade_duration: u32) -> (u8, u8) {
+        if fade_duration == 0 {
+            return (0, 0);
+        }
+
+        let error = |duration: u32| duration.abs_diff(fade_duration);
+        let fade_time = (1..=15u8)
+            .min_by_key(|fade_time| error(DaliManager::standard_fade_duration(*fade_time)))
+            .unwrap();
+        let extended_fade_time = (1..=4u8)
+            .flat_map(|multiplier| (0..16u8).map(move |base| (multiplier << 4) | base))
+            .min_by_key(|extended_fade_time| {
+                error(DaliManager::extended_fade_duration(*extended_fade_time))
+            })
+            .unwrap();
+
+        let standard_error = error(DaliManager::standard_fade_duration(fade_time));
+
+        if standard_error <= fade_duration / DaliManager::STANDARD_FADE_TIME_TOLERANCE
+            || standard_error <= error(DaliManager::extended_fade_duration(extended_fade_time))
+        {
+            (fade_time, 0)
+        } else {
+            (0, extended_fade_time)
+        }
+    }
+
+    pub fn set_fade_duration(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        fade_duration: u32,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set fade duration {fade_duration}ms for {target} on bus {bus}"
+            ))
+        };
+        let (fade_time, extended_fade_time) = DaliManager::encode_fade_duration(fade_duration);
+
+        info!("Set fade duration {fade_duration}ms for {target} on bus {bus} (fade time {fade_time}, extended fade time {extended_fade_time:#04x})");
+
+        self.set_dtr(bus, fade_time)
+            .change_context_lazy(into_context)?;
+        self.send_command_to_target(bus, dali_commands::DALI_SET_FADE_TIME, target, true)
+            .change_context_lazy(into_context)?;
+        self.set_dtr(bus, extended_fade_time)
+            .change_context_lazy(into_context)?;
+        self.send_command_to_target(
+            bus,
+            dali_commands::DALI_SET_EXTENDED_FADE_TIME,
+            target,
+            true,
+        )
+        .change_context_lazy(into_context)
+    }
+
+    pub fn set_fade_rate(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        fade_rate: u8,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Set fade rate {fade_rate} for {target} on bus {bus}"
+            ))
+        };
+
+        if !(1..=15).contains(&fade_rate) {
+            return Err(DaliManagerError::FadeRate(fade_rate)).change_context_lazy(into_context);
+        }
+
+        info!("Set fade rate {fade_rate} for {target} on bus {bus}");
+
+        self.set_dtr(bus, fade_rate)
+            .change_context_lazy(into_context)?;
+        self.send_command_to_target(bus, dali_commands::DALI_SET_FADE_RATE, target, true)
+            .change_context_lazy(into_context)
+    }
+
+    pub fn query_fade_settings(&mut self, bus: usize, short_address: u8) -> Result<FadeSettings> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query fade settings of light {short_address} on bus {bus}"
+            ))
+        };
+
+        let fade_time_rate = self
+            .send_command_to_address_and_get_byte(
+                bus,
+                dali_commands::DALI_QUERY_FADE_TIME_FADE_RATE,
+                short_address,
+                false,
+            )
+            .change_context_lazy(into_context)?;
+        let extended_fade_time = self
+            .query_optional_byte(
+                bus,
+                dali_commands::DALI_QUERY_EXTENDED_FADE_TIME,
+                short_address,
+            )
+            .change_context_lazy(into_context)?;
+        let fade_time = fade_time_rate >> 4;
+
+        Ok(FadeSettings {
+            fade_time,
+            fade_rate: fade_time_rate & 0x0f,
+            extended_fade_time,
+            fade_duration: DaliManager::decode_fade_duration(fade_time, extended_fade_time),
+        })
+    }
+
+    pub fn query_group_membership(&mut self, bus: usize, short_address: u8) -> Result<u16> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query group membership for short address {short_address} on bus {bus}"
+            ))
+        };
+
+        let groups_0to7 = self
+            .send_command_to_address_and_get_byte(
+                bus,
+                dali_commands::DALI_QUERY_GROUPS_0_7,
+                short_address,
+                false,
+            )
             .change_context_lazy(into_context)?;
         let groups_8to15 = self
             .send_command_to_address_and_get_byte(
@@ -720,18 +1499,24 @@ impl<'manager> DaliManager<'manager> {
         )
         .change_context_lazy(into_context)?;
 
-        let description =
-            if let Some(existing_channel) = bus_config.remove_channel(existing_address) {
-                existing_channel.description
-            } else {
-                format!("Light {}", new_address)
-            };
+        let channel = match bus_config.remove_channel(existing_address) {
+            Some(existing_channel) => Channel {
+                short_address: new_address,
+                ..existing_channel
+            },
+            None => Channel {
+                short_address: new_address,
+                description: format!("Light {}", new_address),
+                identification: None,
+                random_address: None,
+                levels: OperatingLevels::default(),
+                fade_duration: None,
+                fade_rate: None,
+            },
+        };
 
         if new_address != 0xff {
-            bus_config.channels.push(Channel {
-                description,
-                short_address: new_address,
-            });
+            bus_config.channels.push(channel);
         }
 
         Ok(DaliBusResult::None)
@@ -772,6 +1557,7 @@ impl<'manager> DaliManager<'manager> {
         group_address: u8,
         light_name_pattern: &str,
         progress: Option<MatchGroupProgress>,
+        cancel: Option<&AtomicBool>,
     ) -> Result<DaliBusResult> {
         let into_context = || {
             DaliManagerError::Context(format!(
@@ -802,6 +1588,11 @@ impl<'manager> DaliManager<'manager> {
             .unwrap();
 
         for light in bus_config.channels.iter() {
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                info!("Matching group {group_address} to {light_name_pattern} was cancelled");
+                break;
+            }
+
             if re.is_match(&light.description) {
                 // If this light is not member of the group, add it
                 if !group.members.contains(&light.short_address) {
@@ -856,7 +1647,522 @@ impl<'manager> DaliManager<'manager> {
             }
         }
 
-        Ok(DaliBusResult::None)
+        Ok(DaliBusResult::None)
+    }
+
+    pub fn send_arc_command(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        command: ArcPowerCommand,
+    ) -> Result<DaliBusResult> {
+        let into_context =
+            || DaliManagerError::Context(format!("Sending {command:?} to {target} on bus {bus}"));
+
+        info!("Send {command:?} to {target} on bus {bus}");
+        self.send_command_to_target(bus, command.into(), target, false)
+            .change_context_lazy(into_context)
+    }
+
+    pub fn query_actual_level(&mut self, bus: usize, short_address: u8) -> Result<u8> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query actual level of light {short_address} on bus {bus}"
+            ))
+        };
+
+        self.send_command_to_address_and_get_byte(
+            bus,
+            dali_commands::DALI_QUERY_ACTUAL_LEVEL,
+            short_address,
+            false,
+        )
+        .change_context_lazy(into_context)
+    }
+
+    // Make a light identify itself (e.g. blink) for the given number of seconds. DALI-2 gear identifies itself for 10
+    // seconds after IDENTIFY DEVICE, so the command is sent again while the time is not up, and the identification is
+    // stopped by setting the light back to its original level. DALI-1 gear has no identify command, so the light is
+    // toggled between its minimum and maximum level and then set back to its original level. Identification is stopped
+    // early if cancelled.
+    pub fn identify(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+        seconds: u32,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Identify light {short_address} on bus {bus} for {seconds} seconds"
+            ))
+        };
+        let is_cancelled = || cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+
+        if seconds == 0 {
+            return Ok(DaliBusResult::None);
+        }
+
+        let version = self
+            .send_command_to_address_and_get_byte(
+                bus,
+                dali_commands::DALI_QUERY_VERSION_NUMBER,
+                short_address,
+                false,
+            )
+            .change_context_lazy(into_context)?;
+        let level = self
+            .query_actual_level(bus, short_address)
+            .change_context_lazy(into_context)?;
+        let is_dali2 = (version >> 2) >= 2;
+
+        info!("Identify light {short_address} on bus {bus} for {seconds} seconds (version {version:#04x})");
+
+        // The light is identifying in 500ms steps
+        let identify_steps = DaliManager::IDENTIFY_SECONDS * 2;
+
+        for step in 0..seconds * 2 {
+            if is_cancelled() {
+                info!("Identifying light {short_address} on bus {bus} was cancelled");
+                break;
+            }
+
+            let command = match (is_dali2, step) {
+                (true, step) if step % identify_steps == 0 => {
+                    Some(dali_commands::DALI_IDENTIFY_DEVICE)
+                }
+                (true, _) => None,
+                (false, step) if step % 2 == 0 => Some(dali_commands::DALI_RECALL_MAX_LEVEL),
+                (false, _) => Some(dali_commands::DALI_RECALL_MIN_LEVEL),
+            };
+
+            if let Some(command) = command {
+                self.send_command_to_address(bus, command, short_address, is_dali2)
+                    .change_context_lazy(into_context)?;
+            }
+
+            sleep(Duration::from_millis(500));
+        }
+
+        self.set_light_brightness(bus, short_address, level)
+            .change_context_lazy(into_context)
+    }
+
+    // Reset the lights to their factory defaults (short address and memory banks are not changed). Reset clears group
+    // membership and scenes, it may take the gear up to 300ms during which it does not respond
+    pub fn reset(&mut self, bus: usize, target: DaliTarget) -> Result<DaliBusResult> {
+        let into_context = || DaliManagerError::Context(format!("Reset {target} on bus {bus}"));
+
+        info!("Reset {target} on bus {bus}");
+
+        self.send_command_to_target(bus, dali_commands::DALI_RESET, target, true)
+            .change_context_lazy(into_context)?;
+        sleep(Duration::from_millis(300));
+        Ok(DaliBusResult::None)
+    }
+
+    // Reset the content of a writable memory bank (0 resets all memory banks)
+    pub fn reset_memory_bank(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        bank: u8,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!("Reset memory bank {bank} of {target} on bus {bus}"))
+        };
+
+        info!("Reset memory bank {bank} of {target} on bus {bus}");
+
+        self.set_dtr(bus, bank).change_context_lazy(into_context)?;
+        self.send_command_to_target(bus, dali_commands::DALI_RESET_MEMORY_BANK, target, true)
+            .change_context_lazy(into_context)?;
+        sleep(Duration::from_millis(300));
+        Ok(DaliBusResult::None)
+    }
+
+    // Ask the gear to store its variables in non-volatile memory (DALI-2)
+    pub fn save_persistent_variables(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Save persistent variables of {target} on bus {bus}"
+            ))
+        };
+
+        self.send_command_to_target(
+            bus,
+            dali_commands::DALI_SAVE_PERSISTENT_VARIABLES,
+            target,
+            true,
+        )
+        .change_context_lazy(into_context)?;
+        sleep(Duration::from_millis(300));
+        Ok(DaliBusResult::None)
+    }
+
+    fn check_scene(scene: u8) -> Result<()> {
+        if scene < 16 {
+            Ok(())
+        } else {
+            Err(DaliManagerError::Scene(scene).into())
+        }
+    }
+
+    pub fn go_to_scene(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        scene: u8,
+    ) -> Result<DaliBusResult> {
+        let into_context =
+            || DaliManagerError::Context(format!("Go to scene {scene} for {target} on bus {bus}"));
+
+        DaliManager::check_scene(scene).change_context_lazy(into_context)?;
+        info!("Go to scene {scene} for {target} on bus {bus}");
+
+        self.send_command_to_target(
+            bus,
+            dali_commands::DALI_GO_TO_SCENE0 + (scene as u16),
+            target,
+            false,
+        )
+        .change_context_lazy(into_context)
+    }
+
+    pub fn store_scene(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        scene: u8,
+        level: u8,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Store level {level} as scene {scene} for {target} on bus {bus}"
+            ))
+        };
+
+        DaliManager::check_scene(scene).change_context_lazy(into_context)?;
+        info!("Store level {level} as scene {scene} for {target} on bus {bus}");
+
+        self.set_dtr(bus, level).change_context_lazy(into_context)?;
+        self.send_command_to_target(
+            bus,
+            dali_commands::DALI_SET_SCENE0 + (scene as u16),
+            target,
+            true,
+        )
+        .change_context_lazy(into_context)
+    }
+
+    pub fn remove_from_scene(
+        &mut self,
+        bus: usize,
+        target: DaliTarget,
+        scene: u8,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!("Remove {target} from scene {scene} on bus {bus}"))
+        };
+
+        DaliManager::check_scene(scene).change_context_lazy(into_context)?;
+        info!("Remove {target} from scene {scene} on bus {bus}");
+
+        self.send_command_to_target(
+            bus,
+            dali_commands::DALI_REMOVE_FROM_SCENE0 + (scene as u16),
+            target,
+            true,
+        )
+        .change_context_lazy(into_context)
+    }
+
+    // Return the level of each of the 16 scenes, None if the light is not part of the scene (level is MASK)
+    pub fn query_scene_levels(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+    ) -> Result<[Option<u8>; 16]> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query scene levels of light {short_address} on bus {bus}"
+            ))
+        };
+        let mut levels = [None; 16];
+
+        for (scene, level) in levels.iter_mut().enumerate() {
+            let value = self
+                .send_command_to_address_and_get_byte(
+                    bus,
+                    dali_commands::DALI_QUERY_SCENE0_LEVEL + (scene as u16),
+                    short_address,
+                    false,
+                )
+                .change_context_lazy(into_context)?;
+
+            if value != 0xff {
+                *level = Some(value);
+            }
+        }
+
+        Ok(levels)
+    }
+
+    // Program the scenes stored in the configuration into a light (for example after gear was replaced)
+    pub fn restore_scenes(
+        &mut self,
+        bus_config: &BusConfig,
+        short_address: u8,
+    ) -> Result<DaliBusResult> {
+        let bus = bus_config.bus;
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Restore scenes of light {short_address} on bus {bus}"
+            ))
+        };
+
+        for scene in 0..16u8 {
+            match bus_config.get_scene_level(scene, short_address) {
+                Some(level) => {
+                    self.store_scene(bus, DaliTarget::Light(short_address), scene, level)
+                }
+                None => self.remove_from_scene(bus, DaliTarget::Light(short_address), scene),
+            }
+            .change_context_lazy(into_context)?;
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    // Send a query that only some gear answers (e.g. DALI-2 queries), return None if there is no reply
+    fn query_optional_byte(
+        &mut self,
+        bus: usize,
+        command: u16,
+        short_address: u8,
+    ) -> Result<Option<u8>> {
+        match self.send_command_to_address(bus, command, short_address, false)? {
+            DaliBusResult::Value8(b) => Ok(Some(b)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn query_device_types(&mut self, bus: usize, short_address: u8) -> Result<Vec<u8>> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query device types of light {short_address} on bus {bus}"
+            ))
+        };
+
+        let device_type = self
+            .send_command_to_address_and_get_byte(
+                bus,
+                dali_commands::DALI_QUERY_DEVICE_TYPE,
+                short_address,
+                false,
+            )
+            .change_context_lazy(into_context)?;
+
+        if device_type != 0xff {
+            return Ok(vec![device_type]);
+        }
+
+        // MASK - gear supports more than one device type, get them one by one until 254 (no more types) is returned
+        let mut device_types = Vec::new();
+
+        while device_types.len() < 32 {
+            match self
+                .query_optional_byte(
+                    bus,
+                    dali_commands::DALI_QUERY_NEXT_DEVICE_TYPE,
+                    short_address,
+                )
+                .change_context_lazy(into_context)?
+            {
+                Some(0xfe) | None => break,
+                Some(device_type) => device_types.push(device_type),
+            }
+        }
+
+        Ok(device_types)
+    }
+
+    pub fn query_device_info(&mut self, bus: usize, short_address: u8) -> Result<DeviceInfo> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Query device information of light {short_address} on bus {bus}"
+            ))
+        };
+        let mut query = |command: u16| {
+            self.send_command_to_address_and_get_byte(bus, command, short_address, false)
+                .change_context_lazy(into_context)
+        };
+
+        let version = query(dali_commands::DALI_QUERY_VERSION_NUMBER)?;
+        let physical_min_level = query(dali_commands::DALI_QUERY_PHYSICAL_MINIMUM_LEVEL)?;
+        let min_level = query(dali_commands::DALI_QUERY_MIN_LEVEL)?;
+        let max_level = query(dali_commands::DALI_QUERY_MAX_LEVEL)?;
+        let power_on_level = query(dali_commands::DALI_QUERY_POWER_ON_LEVEL)?;
+        let system_failure_level = query(dali_commands::DALI_QUERY_SYSTEM_FAILURE_LEVEL)?;
+        let random_address = ((query(dali_commands::DALI_QUERY_RANDOM_ADDRESS_H)? as u32) << 16)
+            | ((query(dali_commands::DALI_QUERY_RANDOM_ADDRESS_M)? as u32) << 8)
+            | (query(dali_commands::DALI_QUERY_RANDOM_ADDRESS_L)? as u32);
+
+        let device_types = self
+            .query_device_types(bus, short_address)
+            .change_context_lazy(into_context)?;
+        let fade_settings = self
+            .query_fade_settings(bus, short_address)
+            .change_context_lazy(into_context)?;
+
+        Ok(DeviceInfo {
+            version,
+            device_types,
+            physical_min_level,
+            min_level,
+            max_level,
+            power_on_level,
+            system_failure_level,
+            fade_time: fade_settings.fade_time,
+            fade_rate: fade_settings.fade_rate,
+            extended_fade_time: fade_settings.extended_fade_time,
+            fade_duration: fade_settings.fade_duration,
+            random_address,
+        })
+    }
+
+    // Read all locations of a memory bank (location 0 is the address of the last accessible location)
+    pub fn read_memory_bank(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+        bank: u8,
+    ) -> Result<MemoryBank> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Read memory bank {bank} of light {short_address} on bus {bus}"
+            ))
+        };
+
+        self.set_dtr1(bus, bank).change_context_lazy(into_context)?;
+        self.set_dtr(bus, 0).change_context_lazy(into_context)?;
+
+        // Each read increments DTR0 (the location)
+        let last_location = self
+            .query_optional_byte(bus, dali_commands::DALI_READ_MEMORY_LOCATION, short_address)
+            .change_context_lazy(into_context)?
+            .ok_or(DaliManagerError::NoResult)
+            .change_context_lazy(into_context)?;
+        let mut data = vec![Some(last_location)];
+
+        for _ in 1..=last_location {
+            data.push(
+                self.query_optional_byte(
+                    bus,
+                    dali_commands::DALI_READ_MEMORY_LOCATION,
+                    short_address,
+                )
+                .change_context_lazy(into_context)?,
+            );
+        }
+
+        let identification = if bank == 0 {
+            DaliManager::decode_memory_bank0(&data)
+        } else {
+            None
+        };
+
+        Ok(MemoryBank {
+            bank,
+            data,
+            identification,
+        })
+    }
+
+    pub fn query_gear_identification(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+    ) -> Result<Option<GearIdentification>> {
+        Ok(self.read_memory_bank(bus, short_address, 0)?.identification)
+    }
+
+    // Decode memory bank 0 (IEC 62386-102 ed2 layout, DALI-1 gear may implement only part of it)
+    fn decode_memory_bank0(data: &[Option<u8>]) -> Option<GearIdentification> {
+        let get = |location: usize| data.get(location).copied().flatten();
+        let get_number = |locations: std::ops::RangeInclusive<usize>| {
+            locations
+                .map(get)
+                .try_fold(0u64, |value, b| b.map(|b| (value << 8) | b as u64))
+        };
+        let get_version =
+            |major: usize, minor: usize| Some(format!("{}.{}", get(major)?, get(minor)?));
+
+        let identification_number = get_number(0x0b..=0x12).or_else(|| get_number(0x0b..=0x0e)); // DALI-1 has 4 bytes serial number
+
+        Some(GearIdentification {
+            gtin: get_number(0x03..=0x08)?,
+            firmware_version: get_version(0x09, 0x0a)?,
+            identification_number: identification_number?,
+            hardware_version: get_version(0x13, 0x14),
+            version_101: get(0x15),
+            version_102: get(0x16),
+        })
+    }
+
+    // Write to a memory bank location. Bank 0 and the first two locations of each bank (last location address and
+    // checksum) are read only
+    pub fn write_memory_location(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+        bank: u8,
+        location: u8,
+        value: u8,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Write {value} to memory bank {bank} location {location} of light {short_address} on bus {bus}"
+            ))
+        };
+
+        if bank == 0 || location < 2 {
+            return Err(DaliManagerError::MemoryReadOnly(bank, location))
+                .change_context_lazy(into_context);
+        }
+
+        info!("Write {value} to memory bank {bank} location {location} of light {short_address} on bus {bus}");
+
+        self.set_dtr1(bus, bank).change_context_lazy(into_context)?;
+        self.set_dtr(bus, location)
+            .change_context_lazy(into_context)?;
+        self.send_command_to_address(
+            bus,
+            dali_commands::DALI_ENABLE_WRITE_MEMORY,
+            short_address,
+            true,
+        )
+        .change_context_lazy(into_context)?;
+
+        // Only the light that was enabled for writing writes the value, and replies with it
+        match self
+            .broadcast_command(
+                bus,
+                dali_commands::DALI_WRITE_MEMORY_LOCATION,
+                value,
+                false,
+                &format!("Write memory location: {}", value),
+            )
+            .change_context_lazy(into_context)?
+        {
+            DaliBusResult::Value8(written) if written == value => Ok(DaliBusResult::None),
+            _ => Err(DaliManagerError::MemoryWriteFailed(bank, location))
+                .change_context_lazy(into_context),
+        }
     }
 
     pub fn query_light_status(&mut self, bus: usize, short_address: u8) -> Result<LightStatus> {
@@ -881,11 +2187,34 @@ impl<'manager> DaliManager<'manager> {
 }
 
 impl DaliBusIterator {
+    // Start searching the selected lights, the lights are given new random addresses
     pub fn new(
         dali_manager: &mut DaliManager,
         bus: usize,
         selection: DaliDeviceSelection,
         progress: Option<FindDeviceProgress>,
+    ) -> Result<DaliBusIterator> {
+        DaliBusIterator::start(dali_manager, bus, selection, progress, true)
+    }
+
+    // Start searching the selected lights without changing their random addresses (the random address is kept by the
+    // gear), so lights can be recognized by the random address they had when they were found before. Lights that share
+    // a random address are still randomized when they are found.
+    pub fn new_keeping_random_addresses(
+        dali_manager: &mut DaliManager,
+        bus: usize,
+        selection: DaliDeviceSelection,
+        progress: Option<FindDeviceProgress>,
+    ) -> Result<DaliBusIterator> {
+        DaliBusIterator::start(dali_manager, bus, selection, progress, false)
+    }
+
+    fn start(
+        dali_manager: &mut DaliManager,
+        bus: usize,
+        selection: DaliDeviceSelection,
+        progress: Option<FindDeviceProgress>,
+        randomise: bool,
     ) -> Result<DaliBusIterator> {
         let parameter = match selection {
             DaliDeviceSelection::All => 0,
@@ -911,10 +2240,13 @@ impl DaliBusIterator {
             )
             .change_context_lazy(into_context)?;
         std::thread::sleep(std::time::Duration::from_millis(400));
-        dali_manager
-            .broadcast_command(bus, dali_commands::DALI_RANDOMISE, 0, true, "Randomize")
-            .change_context_lazy(into_context)?;
-        std::thread::sleep(std::time::Duration::from_millis(250));
+
+        if randomise {
+            dali_manager
+                .broadcast_command(bus, dali_commands::DALI_RANDOMISE, 0, true, "Randomize")
+                .change_context_lazy(into_context)?;
+            std::thread::sleep(std::time::Duration::from_millis(250));
+        }
 
         Ok(DaliBusIterator {
             bus,
@@ -924,10 +2256,21 @@ impl DaliBusIterator {
             previous_mid_byte: None,
             previous_high_byte: None,
             short_address: 0,
+            random_address: None,
+            current_short_address: None,
+            search_low: 0,
+            randomise_count: 0,
             terminate: false,
         })
     }
 
+    // Number of COMPARE retries when no light answers (a light may miss a command, or its reply may be lost)
+    const COMPARE_RETRIES: u8 = 1;
+    // Number of searches for a light that ended on an address with no light before giving up
+    const MAX_SEARCH_ATTEMPTS: usize = 5;
+    // Number of times lights that share a random address are randomized before giving up
+    const MAX_RANDOMISE_COUNT: usize = 10;
+
     fn diff_value(previous: Option<u8>, new: u8) -> Option<u8> {
         match previous {
             None => Some(new),
@@ -1025,84 +2368,596 @@ impl DaliBusIterator {
         }
     }
 
+    fn send_terminate(&mut self, dali_manager: &mut DaliManager) -> Result<DaliBusResult> {
+        dali_manager.broadcast_command(
+            self.bus,
+            dali_commands::DALI_TERMINATE,
+            0,
+            false,
+            "terminate",
+        )
+    }
+
+    // Binary search for the lowest random address between low and high (a light with random address less or equal to
+    // high must exist). Returns the address, the lights with this random address are selected.
+    fn search_lowest_random_address(
+        &mut self,
+        dali_manager: &mut DaliManager,
+        mut low: u32,
+        mut high: u32,
+    ) -> Result<u32> {
+        let mut step = 0;
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+
+            debug!("find_next_device: Send search address {}", middle);
+            self.send_search_address(dali_manager, middle)?;
+
+            if self.is_random_address_le(dali_manager, DaliBusIterator::COMPARE_RETRIES)? {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+
+            if let Some(progress) = self.progress.as_ref() {
+                progress(self.short_address, step);
+            }
+
+            step += 1;
+        }
+
+        self.send_search_address(dali_manager, high)?;
+        Ok(high)
+    }
+
+    // Find the light with the lowest random address (of the lights that were not withdrawn). The light that is found is
+    // verified to be the only light with its random address, lights that share a random address are randomized again.
+    //
+    // Lights are found in increasing random address order, so the search for the next light starts from the random
+    // address of the previous light. If a light below this address was missed, the search ends on the (withdrawn)
+    // previous light and is repeated over the whole range. After the last light was found, a single COMPARE is enough
+    // to tell that no light is left.
     pub fn find_next_device(&mut self, dali_manager: &mut DaliManager) -> Result<Option<u8>> {
         let bus = self.bus;
         let into_context =
             || DaliManagerError::Context(format!("Finding next device on bus {bus}",));
-        // Find next device by trying to match its random address
-        let mut search_address = 0x00800000; // Start in half the range (24 bits)
-        let mut delta = 0x00400000;
-        let mut step = 0;
+        let mut search_attempts = 0;
 
         if self.terminate {
-            dali_manager
-                .broadcast_command(
-                    self.bus,
-                    dali_commands::DALI_TERMINATE,
-                    0,
-                    false,
-                    "terminate",
-                )
+            self.send_terminate(dali_manager)
                 .change_context_lazy(into_context)?;
             return Ok(None);
         }
 
-        while delta > 0 {
-            info!("find_next_device: Send search address {}", search_address);
+        loop {
+            self.send_search_address(dali_manager, 0xffffff)
+                .change_context_lazy(into_context)?;
+
+            if !self
+                .is_random_address_le(dali_manager, DaliBusIterator::COMPARE_RETRIES)
+                .change_context_lazy(into_context)?
+            {
+                debug!("No more devices found!");
+                self.send_terminate(dali_manager)
+                    .change_context_lazy(into_context)?;
+                return Ok(None);
+            }
 
-            self.send_search_address(dali_manager, search_address)
+            let random_address = self
+                .search_lowest_random_address(dali_manager, self.search_low, 0xffffff)
                 .change_context_lazy(into_context)?;
 
-            let random_address_le = self.is_random_address_le(dali_manager, 2)?; // On real hardware consider changing this to 1 retry
+            // Only the selected lights (random address is equal to the search address) answer
+            match dali_manager
+                .broadcast_command_allow_collision(
+                    bus,
+                    dali_commands::DALI_QUERY_SHORT_ADDRESS,
+                    0,
+                    false,
+                    "Query short address",
+                )
+                .change_context_lazy(into_context)?
+            {
+                DaliBusResult::Value8(value) => {
+                    debug!("Found light at long address {}", random_address);
+                    self.search_low = random_address;
+                    self.random_address = Some(random_address);
+                    self.current_short_address = (value != 0xff).then_some(value >> 1);
+
+                    let short_address = self.short_address;
+                    self.short_address += 1;
+                    return Ok(Some(short_address));
+                }
+                DaliBusResult::None => {
+                    // A reply was missed (or a light below the lower bound was missed while searching for the previous
+                    // light), so the search ended on an address with no light
+                    debug!("No light at random address {random_address:#08x}, search whole range");
+                    search_attempts += 1;
+                    self.search_low = 0;
+
+                    if search_attempts >= DaliBusIterator::MAX_SEARCH_ATTEMPTS {
+                        let _ = self.send_terminate(dali_manager);
+                        return Err(DaliManagerError::SearchFailed(bus))
+                            .change_context_lazy(into_context);
+                    }
+                }
+                _ => {
+                    info!("More than one light has random address {random_address:#08x} on bus {bus}, randomize");
+
+                    if self.randomise_count >= DaliBusIterator::MAX_RANDOMISE_COUNT {
+                        let _ = self.send_terminate(dali_manager);
+                        return Err(DaliManagerError::SearchFailed(bus))
+                            .change_context_lazy(into_context);
+                    }
 
-            if random_address_le {
-                search_address -= delta;
-            } else {
-                search_address += delta;
+                    dali_manager
+                        .broadcast_command(bus, dali_commands::DALI_RANDOMISE, 0, true, "Randomize")
+                        .change_context_lazy(into_context)?;
+                    sleep(Duration::from_millis(250));
+                    self.randomise_count += 1;
+                    self.search_low = 0;
+                }
             }
+        }
+    }
 
-            delta >>= 1;
+    // Find all the selected lights. Each light that is found is passed to found_device and is then withdrawn (excluded
+    // from the rest of the search). The search ends when no light is left or when it is cancelled.
+    pub fn for_each_device(
+        &mut self,
+        dali_manager: &mut DaliManager,
+        cancel: Option<&AtomicBool>,
+        mut found_device: impl FnMut(&DaliBusIterator),
+    ) -> Result<()> {
+        let bus = self.bus;
+        let into_context =
+            || DaliManagerError::Context(format!("Finding all devices on bus {bus}"));
 
-            if let Some(progress) = self.progress.as_ref() {
-                progress(self.short_address, step);
+        loop {
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                self.terminate();
             }
 
-            step += 1;
-        }
-
-        self.send_search_address(dali_manager, search_address)?;
-        if !self
-            .is_random_address_le(dali_manager, 2)
-            .change_context_lazy(into_context)?
-        {
-            search_address += 1;
-            self.send_search_address(dali_manager, search_address)
-                .change_context_lazy(into_context)?;
-            self.is_random_address_le(dali_manager, 2)
-                .change_context_lazy(into_context)?;
-        }
+            if self
+                .find_next_device(dali_manager)
+                .change_context_lazy(into_context)?
+                .is_none()
+            {
+                return Ok(());
+            }
 
-        if search_address > 0xffffff {
-            debug!("No more devices found!");
+            found_device(self);
             dali_manager
-                .broadcast_command(
-                    self.bus,
-                    dali_commands::DALI_TERMINATE,
-                    0,
-                    false,
-                    "terminate",
-                )
+                .withdraw(bus)
                 .change_context_lazy(into_context)?;
-            Ok(None)
-        } else {
-            debug!("Found light at long address {}", search_address);
-            let short_address = self.short_address;
-            self.short_address += 1;
-            Ok(Some(short_address))
         }
     }
 
     pub fn terminate(&mut self) {
         self.terminate = true;
     }
+
+    // Random address of the device that was found by the last call to find_next_device
+    pub fn random_address(&self) -> Option<u32> {
+        self.random_address
+    }
+
+    // Short address (None if it had no short address) of the device that was found by the last call to find_next_device
+    pub fn current_short_address(&self) -> Option<u8> {
+        self.current_short_address
+    }
+
+    // Number of times the lights were randomized because several lights had the same random address. Randomizing
+    // changes the random address of the lights that were already found (but not their short address).
+    pub fn randomise_count(&self) -> usize {
+        self.randomise_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command_payload::{DaliTarget, RgbwafColor};
+    use crate::config_payload::OperatingLevels;
+    use crate::dali_commands;
+    use crate::dali_emulator::{DaliBusEmulator, DaliControllerEmulator};
+    use crate::dali_manager::{DaliBusIterator, DaliBusResult, DaliDeviceSelection, DaliManager};
+    use crate::test_builders::{bus_config, group};
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_fade_duration_encoding() {
+        assert_eq!(DaliManager::encode_fade_duration(0), (0, 0));
+        assert_eq!(DaliManager::encode_fade_duration(2000), (4, 0));
+        assert_eq!(DaliManager::encode_fade_duration(707), (1, 0));
+        assert_eq!(DaliManager::encode_fade_duration(700), (1, 0)); // 707ms
+        assert_eq!(DaliManager::encode_fade_duration(1500), (3, 0)); // 1414ms
+        assert_eq!(DaliManager::encode_fade_duration(100_000), (15, 0)); // 90510ms
+        assert_eq!(DaliManager::encode_fade_duration(300), (0, 0x12)); // 3 * 100ms (shorter than any fade time)
+        assert_eq!(DaliManager::encode_fade_duration(5 * 60_000), (0, 0x44)); // 5 * 1min
+        assert_eq!(DaliManager::encode_fade_duration(60 * 60_000), (0, 0x4f)); // Longest possible (16min)
+
+        assert_eq!(DaliManager::decode_fade_duration(4, Some(0x1e)), 2000);
+        assert_eq!(DaliManager::decode_fade_duration(0, Some(0x1e)), 1500);
+        assert_eq!(DaliManager::decode_fade_duration(0, Some(0)), 0);
+        assert_eq!(DaliManager::decode_fade_duration(0, None), 0);
+        assert_eq!(DaliManager::decode_fade_duration(15, None), 90510);
+    }
+
+    #[test]
+    fn test_decode_memory_bank0() {
+        let bank0: Vec<Option<u8>> = [
+            0x1a, 0xff, 0x01, // Last location, reserved, last memory bank
+            0x00, 0x12, 0x34, 0x56, 0x78, 0x9a, // GTIN
+            0x02, 0x05, // Firmware version
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xe2, 0x40, // Identification number
+            0x03, 0x01, // Hardware version
+            0x08, 0x09, 0xff, 0x00, 0x01, 0x00,
+        ]
+        .iter()
+        .map(|b| Some(*b))
+        .collect();
+
+        let identification = DaliManager::decode_memory_bank0(&bank0).unwrap();
+
+        assert_eq!(identification.gtin, 0x123456789a);
+        assert_eq!(identification.firmware_version, "2.5");
+        assert_eq!(identification.identification_number, 123456);
+        assert_eq!(identification.hardware_version.as_deref(), Some("3.1"));
+        assert_eq!(identification.version_102, Some(0x09));
+
+        // DALI-1 gear with 4 bytes serial number
+        let mut bank0 = bank0[0..=0x0e].to_vec();
+        bank0[0] = Some(0x0e);
+        bank0[0x0b..=0x0e].copy_from_slice(&[Some(0x87), Some(0x65), Some(0x43), Some(0x21)]);
+        let identification = DaliManager::decode_memory_bank0(&bank0).unwrap();
+
+        assert_eq!(identification.identification_number, 0x87654321);
+        assert_eq!(identification.hardware_version, None);
+    }
+
+    #[test]
+    fn test_set_color_temperature() {
+        let bus = DaliBusEmulator::new_with_config(&bus_config(&[1]));
+        let mut controller = DaliControllerEmulator::new(vec![bus]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+
+        dali_manager
+            .set_color_temperature(0, DaliTarget::Light(1), 250)
+            .unwrap();
+
+        let color_info = dali_manager.query_color(0, 1).unwrap();
+
+        assert_eq!(color_info.color_temperature, Some(250));
+        assert!(color_info.status.color_temperature_active);
+        assert!(!color_info.status.color_temperature_out_of_range);
+        assert_eq!(color_info.coolest_color_temperature, Some(153));
+        assert_eq!(color_info.warmest_color_temperature, Some(370));
+    }
+
+    #[test]
+    fn test_set_color_temperature_out_of_range() {
+        let bus = DaliBusEmulator::new_with_config(&bus_config(&[1]));
+        let mut controller = DaliControllerEmulator::new(vec![bus]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+
+        // The gear clamps the colour temperature to its coolest colour temperature
+        dali_manager
+            .set_color_temperature(0, DaliTarget::Light(1), 100)
+            .unwrap();
+
+        let color_info = dali_manager.query_color(0, 1).unwrap();
+
+        assert_eq!(color_info.color_temperature, Some(153));
+        assert!(color_info.status.color_temperature_out_of_range);
+
+        // 0 and MASK are rejected without changing the colour temperature
+        assert!(dali_manager
+            .set_color_temperature(0, DaliTarget::Light(1), 0)
+            .is_err());
+        assert!(dali_manager
+            .set_color_temperature(0, DaliTarget::Light(1), 0xffff)
+            .is_err());
+        assert_eq!(
+            dali_manager.query_color(0, 1).unwrap().color_temperature,
+            Some(153)
+        );
+    }
+
+    #[test]
+    fn test_set_color() {
+        let bus = DaliBusEmulator::new_with_config(&bus_config(&[1]));
+        let mut controller = DaliControllerEmulator::new(vec![bus]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+        let color = RgbwafColor {
+            red: Some(10),
+            green: Some(20),
+            blue: Some(30),
+            ..RgbwafColor::default()
+        };
+
+        dali_manager
+            .set_color(0, DaliTarget::Light(1), &color)
+            .unwrap();
+
+        let color_info = dali_manager.query_color(0, 1).unwrap();
+
+        assert_eq!(color_info.color, Some(color));
+        assert!(color_info.status.rgbwaf_active);
+        assert!(!color_info.status.color_temperature_active);
+
+        // Channels which are not given are not changed
+        dali_manager
+            .set_color(
+                0,
+                DaliTarget::Light(1),
+                &RgbwafColor {
+                    green: Some(200),
+                    ..RgbwafColor::default()
+                },
+            )
+            .unwrap();
+
+        let color = dali_manager.query_color(0, 1).unwrap().color.unwrap();
+
+        assert_eq!(
+            (color.red, color.green, color.blue),
+            (Some(10), Some(200), Some(30))
+        );
+    }
+
+    #[test]
+    fn test_identify_dali2_gear() {
+        let bus = DaliBusEmulator::new_with_config(&bus_config(&[1]));
+        let mut controller = DaliControllerEmulator::new(vec![bus]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+
+        dali_manager.set_light_brightness(0, 1, 100).unwrap();
+        dali_manager.identify(0, 1, 1, None).unwrap();
+
+        assert_eq!(dali_manager.query_actual_level(0, 1).unwrap(), 100);
+
+        // IDENTIFY DEVICE is sent (the gear identifies by itself) instead of flashing the light
+        let frames = controller.sent_frames(0);
+        let light_command = |command: u16| (0x03, command as u8);
+
+        assert!(frames.contains(&light_command(dali_commands::DALI_IDENTIFY_DEVICE)));
+        assert!(!frames.contains(&light_command(dali_commands::DALI_RECALL_MAX_LEVEL)));
+    }
+
+    #[test]
+    fn test_identify_dali1_gear() {
+        let bus = DaliBusEmulator::new_with_config(&bus_config(&[1]));
+        bus.set_dali1();
+
+        let mut controller = DaliControllerEmulator::new(vec![bus]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+
+        dali_manager.set_light_brightness(0, 1, 100).unwrap();
+        dali_manager.identify(0, 1, 1, None).unwrap();
+
+        assert_eq!(dali_manager.query_actual_level(0, 1).unwrap(), 100);
+
+        // DALI-1 gear does not support IDENTIFY DEVICE, so the light is flashed between its max and min levels
+        let frames = controller.sent_frames(0);
+        let light_command = |command: u16| (0x03, command as u8);
+
+        assert!(!frames.contains(&light_command(dali_commands::DALI_IDENTIFY_DEVICE)));
+        assert!(frames.contains(&light_command(dali_commands::DALI_RECALL_MAX_LEVEL)));
+        assert!(frames.contains(&light_command(dali_commands::DALI_RECALL_MIN_LEVEL)));
+    }
+
+    #[test]
+    fn test_replace_light() {
+        // Light 2 has failed and a new light (without short address) was installed instead
+        let bus = DaliBusEmulator::new_with_config(&bus_config(&[1, 0xff]));
+        let mut controller = DaliControllerEmulator::new(vec![bus]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+        let mut bus_config = bus_config(&[1, 2]);
+
+        dali_manager.replace_light(&mut bus_config, 2).unwrap();
+
+        assert!(matches!(
+            dali_manager
+                .send_command_to_address(0, dali_commands::DALI_QUERY_STATUS, 2, false)
+                .unwrap(),
+            DaliBusResult::Value8(_)
+        ));
+        assert!(bus_config.channels[1].random_address.is_some());
+    }
+
+    #[test]
+    fn test_replace_light_that_answers() {
+        let bus = DaliBusEmulator::new_with_config(&bus_config(&[1, 2, 0xff]));
+        let mut controller = DaliControllerEmulator::new(vec![bus]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+        let mut bus_config = bus_config(&[1, 2]);
+
+        let e = dali_manager.replace_light(&mut bus_config, 2).unwrap_err();
+
+        assert!(format!("{e:?}").contains("Light 2 still answers"));
+
+        // The new light is left without short address
+        let mut device_iterator = DaliBusIterator::new(
+            &mut dali_manager,
+            0,
+            DaliDeviceSelection::WithoutShortAddress,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            find_all_devices(&mut dali_manager, &mut device_iterator),
+            vec![0]
+        );
+        assert_eq!(bus_config.channels[1].random_address, None);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut bus_config = bus_config(&[1, 2]);
+
+        bus_config.groups.push(group(0, &[1, 2]));
+
+        let bus = DaliBusEmulator::new_with_config(&bus_config);
+        let mut controller = DaliControllerEmulator::new(vec![bus]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+
+        dali_manager
+            .store_scene(0, DaliTarget::Bus, 3, 100)
+            .unwrap();
+        dali_manager
+            .set_operating_levels(
+                0,
+                DaliTarget::Bus,
+                &OperatingLevels {
+                    max_level: Some(200),
+                    ..OperatingLevels::default()
+                },
+            )
+            .unwrap();
+
+        dali_manager.reset(0, DaliTarget::Light(1)).unwrap();
+
+        // Light 1 is no longer in any group or scene and has the default levels, light 2 was not reset
+        assert_eq!(dali_manager.query_group_membership(0, 1).unwrap(), 0);
+        assert_eq!(dali_manager.query_scene_levels(0, 1).unwrap(), [None; 16]);
+        assert_eq!(
+            dali_manager.query_operating_levels(0, 1).unwrap().max_level,
+            Some(254)
+        );
+
+        assert_eq!(dali_manager.query_group_membership(0, 2).unwrap(), 0x0001);
+        assert_eq!(dali_manager.query_scene_levels(0, 2).unwrap()[3], Some(100));
+        assert_eq!(
+            dali_manager.query_operating_levels(0, 2).unwrap().max_level,
+            Some(200)
+        );
+    }
+
+    #[test]
+    fn test_audit_bus_moves_duplicate_short_address() {
+        // Two of the lights on the bus use short address 1, the configuration knows only one of them
+        let bus = DaliBusEmulator::new_with_config(&bus_config(&[1, 1, 2]));
+        let mut controller = DaliControllerEmulator::new(vec![bus]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+        let mut bus_config = bus_config(&[1, 2]);
+
+        let conflicts = dali_manager.audit_bus(&mut bus_config, None, None).unwrap();
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].short_address, 1);
+        assert_eq!(conflicts[0].moved_to, vec![0]);
+        assert!(bus_config
+            .channels
+            .iter()
+            .any(|channel| channel.short_address == 0));
+
+        let conflicts = dali_manager.audit_bus(&mut bus_config, None, None).unwrap();
+
+        assert!(conflicts.is_empty());
+    }
+
+    fn find_all_devices(
+        dali_manager: &mut DaliManager,
+        device_iterator: &mut DaliBusIterator,
+    ) -> Vec<u8> {
+        let mut found = Vec::new();
+
+        while let Some(short_address) = device_iterator.find_next_device(dali_manager).unwrap() {
+            found.push(short_address);
+            dali_manager.withdraw(0).unwrap();
+        }
+
+        found
+    }
+
+    #[test]
+    fn test_find_next_device_finds_all_lights() {
+        let mut controller = DaliControllerEmulator::new(vec![DaliBusEmulator::new(0, 16)]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+        let mut device_iterator =
+            DaliBusIterator::new(&mut dali_manager, 0, DaliDeviceSelection::All, None).unwrap();
+
+        let found = find_all_devices(&mut dali_manager, &mut device_iterator);
+
+        assert_eq!(found, (0..16).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_for_each_device() {
+        let mut controller = DaliControllerEmulator::new(vec![DaliBusEmulator::new(0, 4)]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+        let mut device_iterator =
+            DaliBusIterator::new(&mut dali_manager, 0, DaliDeviceSelection::All, None).unwrap();
+        let mut random_addresses = Vec::new();
+
+        device_iterator
+            .for_each_device(&mut dali_manager, None, |device_iterator| {
+                random_addresses.extend(device_iterator.random_address())
+            })
+            .unwrap();
+
+        assert_eq!(random_addresses.len(), 4);
+        assert!(random_addresses.is_sorted());
+
+        // A cancelled search ends without finding any light
+        let cancel = AtomicBool::new(true);
+        let mut device_iterator =
+            DaliBusIterator::new(&mut dali_manager, 0, DaliDeviceSelection::All, None).unwrap();
+        let mut found_count = 0;
+
+        device_iterator
+            .for_each_device(&mut dali_manager, Some(&cancel), |_| found_count += 1)
+            .unwrap();
+
+        assert_eq!(found_count, 0);
+    }
+
+    #[test]
+    fn test_find_next_device_randomises_duplicate_random_addresses() {
+        // The emulated lights start with the same random address, so keeping it makes the first search end on all of them
+        let mut controller = DaliControllerEmulator::new(vec![DaliBusEmulator::new(0, 4)]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+        let mut device_iterator = DaliBusIterator::new_keeping_random_addresses(
+            &mut dali_manager,
+            0,
+            DaliDeviceSelection::All,
+            None,
+        )
+        .unwrap();
+
+        let found = find_all_devices(&mut dali_manager, &mut device_iterator);
+
+        assert_eq!(found, vec![0, 1, 2, 3]);
+        assert!(device_iterator.randomise_count() > 0);
+    }
+
+    #[test]
+    fn test_find_next_device_searches_below_missed_light() {
+        let mut controller = DaliControllerEmulator::new(vec![DaliBusEmulator::new(0, 4)]);
+        let mut dali_manager = DaliManager::new(&mut controller);
+        let mut device_iterator =
+            DaliBusIterator::new(&mut dali_manager, 0, DaliDeviceSelection::All, None).unwrap();
+
+        assert_eq!(
+            device_iterator.find_next_device(&mut dali_manager).unwrap(),
+            Some(0)
+        );
+        dali_manager.withdraw(0).unwrap();
+
+        // Act as if the other lights were missed while searching for the first one (emulated random addresses are
+        // below 0x1000)
+        device_iterator.search_low = 0x1000;
+
+        let found = find_all_devices(&mut dali_manager, &mut device_iterator);
+
+        assert_eq!(found, vec![1, 2, 3]);
+        assert_eq!(
+            device_iterator
+                .random_address()
+                .map(|address| address < 0x1000),
+            Some(true)
+        );
+    }
 }
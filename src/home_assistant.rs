@@ -0,0 +1,227 @@
+use crate::config_payload::{Channel, Group};
+use serde::Serialize;
+
+/// Home Assistant MQTT discovery (https://www.home-assistant.io/integrations/light.mqtt/)
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+// DALI level 255 (MASK) means "no change", so brightness is scaled to 0-254
+const DALI_MAX_LEVEL: u8 = 254;
+
+#[derive(Serialize)]
+pub struct HomeAssistantDevice {
+    identifiers: Vec<String>,
+    name: String,
+    model: String,
+    sw_version: String,
+}
+
+#[derive(Serialize)]
+pub struct HomeAssistantLightConfig {
+    name: String,
+    unique_id: String,
+    command_topic: String,
+    payload_on: String,
+    payload_off: String,
+    on_command_type: String,
+    brightness_command_topic: String,
+    brightness_command_template: String,
+    brightness_scale: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_value_template: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness_state_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brightness_value_template: Option<String>,
+    availability_topic: String,
+    payload_available: String,
+    payload_not_available: String,
+    device: HomeAssistantDevice,
+}
+
+// Discovery node and object ids may contain only [a-zA-Z0-9_-]
+fn to_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl HomeAssistantDevice {
+    pub fn new(controller: &str, version: &str) -> HomeAssistantDevice {
+        HomeAssistantDevice {
+            identifiers: vec![format!("dali_{}", to_id(controller))],
+            name: controller.to_owned(),
+            model: "mqtt_dali".to_owned(),
+            sw_version: version.to_owned(),
+        }
+    }
+}
+
+impl HomeAssistantLightConfig {
+    pub fn get_light_object_id(bus: usize, short_address: u8) -> String {
+        format!("bus{}_light{}", bus, short_address)
+    }
+
+    pub fn get_group_object_id(bus: usize, group_address: u8) -> String {
+        format!("bus{}_group{}", bus, group_address)
+    }
+
+    pub fn get_discovery_topic(controller: &str, object_id: &str) -> String {
+        format!(
+            "{}/light/dali_{}/{}/config",
+            DISCOVERY_PREFIX,
+            to_id(controller),
+            object_id
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        controller: &str,
+        object_id: &str,
+        name: &str,
+        command_topic: &str,
+        command_fields: &str,
+        state_topic: Option<&str>,
+        availability_topic: &str,
+        device: HomeAssistantDevice,
+    ) -> HomeAssistantLightConfig {
+        // command_fields are the JSON fields of the set brightness command (except for the value)
+        let set_brightness = |value: &str| format!(r#"{{{},"value":{}}}"#, command_fields, value);
+
+        HomeAssistantLightConfig {
+            name: name.to_owned(),
+            unique_id: format!("dali_{}_{}", to_id(controller), object_id),
+            command_topic: command_topic.to_owned(),
+            payload_on: set_brightness(&DALI_MAX_LEVEL.to_string()),
+            payload_off: set_brightness("0"),
+            on_command_type: "brightness".to_owned(),
+            brightness_command_topic: command_topic.to_owned(),
+            brightness_command_template: set_brightness("{{ value }}"),
+            brightness_scale: DALI_MAX_LEVEL,
+            state_topic: state_topic.map(|t| t.to_owned()),
+            state_value_template: state_topic
+                .map(|_| "{{ 'ON' if value_json.level > 0 else 'OFF' }}".to_owned()),
+            brightness_state_topic: state_topic.map(|t| t.to_owned()),
+            brightness_value_template: state_topic.map(|_| "{{ value_json.level }}".to_owned()),
+            availability_topic: availability_topic.to_owned(),
+            payload_available: "true".to_owned(),
+            payload_not_available: "false".to_owned(),
+            device,
+        }
+    }
+
+    pub fn new_light(
+        controller: &str,
+        bus: usize,
+        channel: &Channel,
+        command_topic: &str,
+        state_topic: &str,
+        availability_topic: &str,
+        device: HomeAssistantDevice,
+    ) -> HomeAssistantLightConfig {
+        HomeAssistantLightConfig::new(
+            controller,
+            &HomeAssistantLightConfig::get_light_object_id(bus, channel.short_address),
+            &channel.description,
+            command_topic,
+            &format!(
+                r#""command":"SetLightBrightness","bus":{},"address":{}"#,
+                bus, channel.short_address
+            ),
+            Some(state_topic),
+            availability_topic,
+            device,
+        )
+    }
+
+    // Groups have no state topic, so Home Assistant handles them in optimistic mode
+    pub fn new_group(
+        controller: &str,
+        bus: usize,
+        group: &Group,
+        command_topic: &str,
+        availability_topic: &str,
+        device: HomeAssistantDevice,
+    ) -> HomeAssistantLightConfig {
+        HomeAssistantLightConfig::new(
+            controller,
+            &HomeAssistantLightConfig::get_group_object_id(bus, group.group_address),
+            &group.description,
+            command_topic,
+            &format!(
+                r#""command":"SetGroupBrightness","bus":{},"group":{}"#,
+                bus, group.group_address
+            ),
+            None,
+            availability_topic,
+            device,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command_payload::DaliCommand;
+    use crate::config_payload::{Channel, OperatingLevels};
+    use crate::home_assistant::{HomeAssistantDevice, HomeAssistantLightConfig};
+
+    #[test]
+    fn test_light_discovery_commands() {
+        let channel = Channel {
+            short_address: 3,
+            description: "Main light".to_owned(),
+            identification: None,
+            random_address: None,
+            levels: OperatingLevels::default(),
+            fade_duration: None,
+            fade_rate: None,
+        };
+        let light_config = HomeAssistantLightConfig::new_light(
+            "My Kitchen",
+            1,
+            &channel,
+            "DALI/Controllers/My Kitchen/Command",
+            "DALI/State/My Kitchen/Bus_1/Address_3",
+            "DALI/Active/My Kitchen",
+            HomeAssistantDevice::new("My Kitchen", "test"),
+        );
+
+        assert_eq!(light_config.unique_id, "dali_My_Kitchen_bus1_light3");
+        assert_eq!(
+            HomeAssistantLightConfig::get_discovery_topic("My Kitchen", "bus1_light3"),
+            "homeassistant/light/dali_My_Kitchen/bus1_light3/config"
+        );
+
+        // Payloads sent by Home Assistant must be valid controller commands
+        let c: DaliCommand = serde_json::from_str(&light_config.payload_off).unwrap();
+        assert!(matches!(
+            c,
+            DaliCommand::SetLightBrightness {
+                bus: 1,
+                address: 3,
+                value: 0
+            }
+        ));
+
+        let brightness_command = light_config
+            .brightness_command_template
+            .replace("{{ value }}", "128");
+        let c: DaliCommand = serde_json::from_str(&brightness_command).unwrap();
+        assert!(matches!(
+            c,
+            DaliCommand::SetLightBrightness {
+                bus: 1,
+                address: 3,
+                value: 128
+            }
+        ));
+    }
+}
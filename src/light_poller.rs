@@ -0,0 +1,142 @@
+use crate::command_payload::{LightEventKind, LightStatus};
+use crate::config_payload::DaliConfig;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+struct PolledLight {
+    present: bool,
+    lamp_failure: bool,
+    power_failure: bool,
+    level: Option<u8>,
+}
+
+/// Cycle through all the lights in the configuration (one light on each poll), and detect changes in their status
+pub struct LightPoller {
+    interval: Option<Duration>,
+    bus_index: usize,
+    channel_index: usize,
+    lights: HashMap<(usize, u8), PolledLight>,
+}
+
+impl LightPoller {
+    pub fn new(interval: Option<Duration>) -> LightPoller {
+        LightPoller {
+            interval,
+            bus_index: 0,
+            channel_index: 0,
+            lights: HashMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    // Get the next light (bus number, short address) to poll
+    pub fn next_light(&mut self, dali_config: &DaliConfig) -> Option<(usize, u8)> {
+        let light_count: usize = dali_config.buses.iter().map(|bus| bus.channels.len()).sum();
+
+        for _ in 0..=(light_count + dali_config.buses.len()) {
+            let bus = dali_config.buses.get(self.bus_index)?;
+
+            if let Some(channel) = bus.channels.get(self.channel_index) {
+                self.channel_index += 1;
+                return Some((bus.bus, channel.short_address));
+            }
+
+            self.channel_index = 0;
+            self.bus_index = (self.bus_index + 1) % dali_config.buses.len();
+        }
+
+        None
+    }
+
+    // Update the light's last known state, and return the events caused by the changes from the previous poll
+    pub fn update(
+        &mut self,
+        bus: usize,
+        short_address: u8,
+        state: Option<(LightStatus, u8)>,
+    ) -> Vec<LightEventKind> {
+        let mut events = Vec::new();
+        let current = match state {
+            Some((status, level)) => PolledLight {
+                present: true,
+                lamp_failure: status.is_lamp_failure(),
+                power_failure: status.is_power_failure(),
+                level: Some(level),
+            },
+            None => PolledLight {
+                present: false,
+                lamp_failure: false,
+                power_failure: false,
+                level: None,
+            },
+        };
+
+        match self.lights.insert((bus, short_address), current) {
+            Some(previous) => {
+                if previous.present && !current.present {
+                    events.push(LightEventKind::Disappeared);
+                } else if !previous.present && current.present {
+                    events.push(LightEventKind::Reappeared);
+                }
+
+                if current.lamp_failure && !previous.lamp_failure {
+                    events.push(LightEventKind::LampFailure);
+                }
+                if current.power_failure && !previous.power_failure {
+                    events.push(LightEventKind::PowerFailure);
+                }
+                if previous.present && current.present && current.level != previous.level {
+                    events.push(LightEventKind::LevelChanged);
+                }
+            }
+            None => {
+                // First time this light is polled, report only problems
+                if !current.present {
+                    events.push(LightEventKind::Disappeared);
+                }
+                if current.lamp_failure {
+                    events.push(LightEventKind::LampFailure);
+                }
+                if current.power_failure {
+                    events.push(LightEventKind::PowerFailure);
+                }
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command_payload::{LightEventKind, LightStatus};
+    use crate::light_poller::LightPoller;
+
+    #[test]
+    fn test_light_events() {
+        let mut poller = LightPoller::new(None);
+
+        assert!(poller
+            .update(0, 1, Some((LightStatus::from(0x04), 100)))
+            .is_empty());
+        assert_eq!(
+            poller.update(0, 1, Some((LightStatus::from(0x04), 50))),
+            vec![LightEventKind::LevelChanged]
+        );
+        assert_eq!(poller.update(0, 1, None), vec![LightEventKind::Disappeared]);
+        assert!(poller.update(0, 1, None).is_empty());
+        assert_eq!(
+            poller.update(0, 1, Some((LightStatus::from(0x82), 0))),
+            vec![
+                LightEventKind::Reappeared,
+                LightEventKind::LampFailure,
+                LightEventKind::PowerFailure
+            ]
+        );
+        assert_eq!(poller.update(0, 2, None), vec![LightEventKind::Disappeared]);
+    }
+}
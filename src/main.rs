@@ -1,12 +1,22 @@
 use log::info;
 use rustop::opts;
+use std::time::Duration;
 
 mod command_payload;
 mod config_payload;
 mod mqtt;
 mod dali_manager;
+mod dali_dt8;
+mod dali_dt6;
 mod dali_commands;
 mod setup;
+mod light_poller;
+mod bus_scheduler;
+mod commissioning;
+mod reconcile;
+mod home_assistant;
+#[cfg(test)]
+mod test_builders;
 
 mod dali_emulator;
 mod dali_atx;
@@ -15,9 +25,13 @@ use crate::config_payload::DaliConfig;
 use crate::dali_emulator::DaliControllerEmulator;
 use crate::dali_atx::DaliAtx;
 use crate::setup::Setup;
+use crate::mqtt::MqttConnectionConfig;
 
 pub struct Config {
     config_filename: String,
+    poll_interval: Option<Duration>,
+    home_assistant: bool,
+    mqtt: MqttConnectionConfig,
 }
 
 #[tokio::main]
@@ -31,6 +45,16 @@ async fn main()  {
         opt console: bool = false, desc: "Enable console logging";
         opt filter: String = String::from("mqtt_dali"), desc: "Filter for logging";
         opt config: String = String::from("dali.json"), desc: "Configuration filename (dali.json)";
+        opt poll_interval: u64 = 0, desc: "Interval (milliseconds) between polling of lights status (0 = no polling)";
+        opt home_assistant: bool = false, desc: "Publish Home Assistant MQTT discovery configuration";
+        opt port: Option<u16>, desc: "MQTT broker port (default 1883, or 8883 when using TLS)";
+        opt client_id: Option<String>, desc: "MQTT client id (default DALI-<controller name>)";
+        opt username: Option<String>, desc: "MQTT user name";
+        opt password: Option<String>, desc: "MQTT password (can also be set by MQTT_PASSWORD environment variable)";
+        opt tls: bool = false, desc: "Connect to MQTT broker using TLS";
+        opt ca_cert: Option<String>, desc: "CA certificate (PEM) file for verifying the MQTT broker (implies --tls)";
+        opt client_cert: Option<String>, desc: "Client certificate (PEM) file for TLS client authentication";
+        opt client_key: Option<String>, desc: "Client private key (PEM) file for TLS client authentication";
     }.parse_or_exit();
     
     if args.log {
@@ -59,6 +83,18 @@ async fn main()  {
 
     let config = Config {
         config_filename: args.config.clone(),
+        poll_interval: if args.poll_interval > 0 { Some(Duration::from_millis(args.poll_interval)) } else { None },
+        home_assistant: args.home_assistant,
+        mqtt: MqttConnectionConfig {
+            port: args.port,
+            client_id: args.client_id.clone(),
+            username: args.username.clone(),
+            password: args.password.clone().or_else(|| std::env::var("MQTT_PASSWORD").ok()),
+            tls: args.tls,
+            ca_cert: args.ca_cert.clone(),
+            client_cert: args.client_cert.clone(),
+            client_key: args.client_key.clone(),
+        },
     };
 
     info!("Loading configuration from {config_filename}", config_filename = args.config.clone());
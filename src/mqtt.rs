@@ -1,14 +1,41 @@
-use crate::command_payload::{DaliCommand, QueryLightReply};
-use crate::config_payload::{BusStatus, DaliConfig, Group};
+use crate::bus_scheduler::{BusScheduler, ScheduledWork};
+use crate::command_payload::{
+    CommandReply, DaliCommand, DaliCommandRequest, DaliTarget, LightEvent, LightState, LightStatus,
+    OperationProgress, ProgressKind, QueryColorReply, QueryDeviceInfoReply, QueryLedGearReply,
+    QueryLightReply, QuerySceneLevelsReply, ReadMemoryBankReply, ReconcilePolicy,
+    RESET_CONFIRMATION,
+};
+use crate::commissioning::{self, FoundDevice};
+use crate::config_payload::{
+    BusConfig, BusStatus, DaliConfig, GearIdentification, Group, OperatingLevels,
+};
 use crate::dali_manager::{
-    DaliBusIterator, DaliBusResult, DaliDeviceSelection, DaliManager, MatchGroupAction,
+    self, AuditBusAction, AuditBusProgress, DaliBusIterator, DaliBusResult, DaliDeviceSelection,
+    DaliManager, FindDeviceProgress, MatchGroupAction, MatchGroupProgress,
 };
+use crate::home_assistant::{HomeAssistantDevice, HomeAssistantLightConfig};
+use crate::light_poller::LightPoller;
+use crate::reconcile::{self, ReconcileProgress};
 use crate::{get_version, Config};
 use error_stack::{Report, ResultExt};
 use log::{error, info};
-use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, Publish, QoS};
+use rumqttc::{
+    AsyncClient, ConnectionError, Event, EventLoop, LastWill, MqttOptions, Packet, Publish, QoS,
+    TlsConfiguration, Transport,
+};
+use rustls::{ClientConfig, RootCertStore};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
 use tracing::span;
 
 pub struct MqttDali<'a> {
@@ -16,6 +43,109 @@ pub struct MqttDali<'a> {
     // mqtt_client: AsyncClient,
     // mqtt_events: EventLoop,
     dali_manager: &'a mut DaliManager<'a>,
+    light_poller: LightPoller,
+    home_assistant: bool,
+    discovery_topics: HashSet<String>, // Home Assistant discovery topics that were published
+    scheduler: BusScheduler,
+    bus_job_sender: mpsc::UnboundedSender<BusJobEvent>,
+    status_ok: bool,
+    reported_queue_depth: Option<usize>, // Last published command queue depth
+    bus_workers: HashMap<usize, std::sync::mpsc::Sender<BusWork>>, // Threads executing the DALI operations of each bus
+    config_dirty: bool, // Configuration was changed by a bus worker and was not yet saved
+}
+
+// Events sent by jobs and commands running on a busy bus (on the bus worker). Configuration changes are sent as events,
+// since the configuration is owned by the session.
+enum BusJobEvent {
+    Progress(OperationProgress),
+    BusStatusUpdated {
+        bus: usize,
+        status: BusStatus,
+    },
+    LightReply {
+        topic: String,
+        reply: serde_json::Value,
+    },
+    LightStates {
+        bus: usize,
+        states: Vec<(u8, dali_manager::Result<(LightStatus, u8)>)>,
+    },
+    LightPolled {
+        bus: usize,
+        short_address: u8,
+        state: Result<dali_manager::Result<(LightStatus, u8)>>, // Err if polling panicked
+    },
+    LightFound {
+        bus: usize,
+        short_address: u8,
+        random_address: Option<u32>,
+    },
+    LightIdentified {
+        bus: usize,
+        short_address: u8,
+        identification: GearIdentification,
+    },
+    GroupsUpdated {
+        bus: usize,
+        groups: Vec<Group>,
+    },
+    BusConfigUpdated {
+        bus: usize,
+        bus_config: BusConfig,
+    },
+    Completed {
+        buses: Vec<usize>,
+        request_id: Option<String>,
+        command: DaliCommand,
+        result: BusJobResult,
+    },
+}
+
+// Result of a bus job, with an optional value that is included in the command reply
+type BusJobResult = Result<Option<serde_json::Value>>;
+
+// Work executed by a bus worker
+type BusWork = Box<dyn FnOnce(&mut DaliManager) + Send>;
+
+// A job (e.g. finding lights) running on a busy bus
+#[derive(Clone)]
+struct BusJob {
+    bus: usize,
+    controller_name: String,
+    operation: &'static str,
+    request_id: Option<String>,
+    cancel: Arc<AtomicBool>,
+    events: mpsc::UnboundedSender<BusJobEvent>,
+}
+
+impl BusJob {
+    fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    // Send an event to the session (if the session has ended the event is dropped)
+    fn send(&self, event: BusJobEvent) {
+        let _ = self.events.send(event);
+    }
+
+    fn progress(&self, progress: ProgressKind) {
+        self.send(BusJobEvent::Progress(OperationProgress::new(
+            &self.controller_name,
+            self.bus,
+            self.operation,
+            self.request_id.as_deref(),
+            progress,
+        )));
+    }
+
+    // Publish a query reply on the light's reply topic, the reply is also included in the command reply
+    fn light_reply(&self, topic: String, reply: serde_json::Value) -> BusJobResult {
+        self.send(BusJobEvent::LightReply {
+            topic,
+            reply: reply.clone(),
+        });
+        Ok(Some(reply))
+    }
 }
 
 #[derive(Debug, Error)]
@@ -44,14 +174,145 @@ pub enum CommandError {
     #[error("Bus {0} has no group {1}")]
     NoSuchGroup(usize, u8),
 
+    #[error("Reset was not confirmed (confirm should be '{0}')")]
+    ResetNotConfirmed(&'static str),
+
+    #[error("No operation is running on bus {0}")]
+    NothingToCancel(usize),
+
+    #[error("Random addresses of lights on bus {0} kept changing while searching")]
+    RandomAddressesChanged(usize),
+
+    #[error("Worker of bus {0} is not running")]
+    BusWorkerStopped(usize),
+
+    #[error("All short addresses on bus {0} are used")]
+    NoUnusedShortAddress(usize),
+
+    #[error("{0} on bus {1} panicked")]
+    JobPanicked(&'static str, usize),
+
     #[error("Mqtt Error {0}")]
     MqttError(String),
 
+    #[error("Invalid MQTT TLS configuration: {0}")]
+    TlsConfiguration(String),
+
     #[error("In context of '{0}'")]
     Context(String),
 }
 
 type Result<T> = std::result::Result<T, Report<CommandError>>;
+type MqttEvents = mpsc::UnboundedReceiver<std::result::Result<Event, ConnectionError>>;
+type BusJobEvents = mpsc::UnboundedReceiver<BusJobEvent>;
+
+/// MQTT broker connection options
+///
+/// To test against a local mosquitto broker, configure a listener on port 8883 with cafile, certfile and keyfile
+/// (and require_certificate true for client certificates), and a password_file created with mosquitto_passwd.
+/// The tests use a broker stand-in with the certificates in test_data/mqtt_tls.
+pub struct MqttConnectionConfig {
+    pub port: Option<u16>, // Default is 1883, or 8883 when using TLS
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: bool,
+    pub ca_cert: Option<String>,     // PEM file, implies TLS
+    pub client_cert: Option<String>, // PEM file, for TLS client authentication
+    pub client_key: Option<String>,  // PEM file, for TLS client authentication
+}
+
+impl MqttConnectionConfig {
+    fn use_tls(&self) -> bool {
+        self.tls || self.ca_cert.is_some()
+    }
+
+    fn read_pem_file(filename: &str) -> Result<Vec<u8>> {
+        std::fs::read(filename).change_context_lazy(|| {
+            CommandError::Context(format!("Reading TLS certificate/key file {}", filename))
+        })
+    }
+
+    fn get_tls_configuration(&self) -> Result<TlsConfiguration> {
+        // Only the ring crypto provider is built, make it the process default (fails if it is already installed)
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
+        let client_auth = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Some((
+                MqttConnectionConfig::read_pem_file(cert)?,
+                MqttConnectionConfig::read_pem_file(key)?,
+            )),
+            (None, None) => None,
+            _ => {
+                return Err(Report::new(CommandError::TlsConfiguration(
+                    "both client certificate and client key must be specified".to_owned(),
+                )))
+            }
+        };
+
+        match &self.ca_cert {
+            Some(ca_cert) => Ok(TlsConfiguration::Simple {
+                ca: MqttConnectionConfig::read_pem_file(ca_cert)?,
+                alpn: None,
+                client_auth,
+            }),
+            None if client_auth.is_some() => Err(Report::new(CommandError::TlsConfiguration(
+                "client certificate requires a CA certificate".to_owned(),
+            ))),
+            None => Ok(TlsConfiguration::Rustls(Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(MqttConnectionConfig::get_platform_root_certificates()?)
+                    .with_no_client_auth(),
+            ))),
+        }
+    }
+
+    // Root certificates for verifying the broker when no CA certificate is specified
+    fn get_platform_root_certificates() -> Result<RootCertStore> {
+        let native_certs = rustls_native_certs::load_native_certs();
+
+        for e in native_certs.errors.iter() {
+            error!("Loading platform root certificates: {e}");
+        }
+
+        let mut root_store = RootCertStore::empty();
+        let (added, ignored) = root_store.add_parsable_certificates(native_certs.certs);
+
+        if ignored > 0 {
+            info!("{ignored} platform root certificates could not be parsed and were ignored");
+        }
+
+        if added == 0 {
+            return Err(Report::new(CommandError::TlsConfiguration(
+                "no platform root certificates were found (use --ca-cert to specify the CA certificate)"
+                    .to_owned(),
+            )));
+        }
+
+        Ok(root_store)
+    }
+
+    pub fn get_mqtt_options(&self, mqtt_broker: &str, name: &str) -> Result<MqttOptions> {
+        let client_id = self
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("DALI-{}", name));
+        let port = self
+            .port
+            .unwrap_or(if self.use_tls() { 8883 } else { 1883 });
+        let mut mqtt_options = MqttOptions::new(client_id, mqtt_broker, port);
+
+        if let Some(username) = &self.username {
+            mqtt_options.set_credentials(username, self.password.clone().unwrap_or_default());
+        }
+
+        if self.use_tls() {
+            mqtt_options.set_transport(Transport::tls_with_config(self.get_tls_configuration()?));
+        }
+
+        Ok(mqtt_options)
+    }
+}
 
 impl<'a> MqttDali<'a> {
     fn get_command_topic(&self) -> String {
@@ -81,6 +342,29 @@ impl<'a> MqttDali<'a> {
         )
     }
 
+    fn get_command_reply_topic(&self) -> String {
+        format!("DALI/Reply/Command/{}", self.dali_config.name)
+    }
+
+    fn get_queue_depth_topic(&self) -> String {
+        format!("DALI/QueueDepth/{}", self.dali_config.name)
+    }
+
+    fn get_progress_topic(&self) -> String {
+        format!("DALI/Progress/{}", self.dali_config.name)
+    }
+
+    fn get_events_topic(&self) -> String {
+        format!("DALI/Events/{}", self.dali_config.name)
+    }
+
+    fn get_light_state_topic(&self, bus: usize, short_address: u8) -> String {
+        format!(
+            "DALI/State/{}/Bus_{}/Address_{}",
+            self.dali_config.name, bus, short_address
+        )
+    }
+
     async fn publish_config(
         client: &AsyncClient,
         config_topic: &str,
@@ -100,17 +384,158 @@ impl<'a> MqttDali<'a> {
             .change_context_lazy(into_context)
     }
 
-    fn update_bus_status(&mut self) -> Result<DaliBusResult> {
-        let into_context = || CommandError::Context("MQTT: UpdateBusStatus command".to_owned());
+    // Publish Home Assistant discovery configuration for all lights and groups, and remove the configuration
+    // of lights and groups that are no longer defined
+    async fn publish_home_assistant_discovery(&mut self, mqtt_client: &AsyncClient) -> Result<()> {
+        let into_context =
+            || CommandError::Context("MQTT: Publish Home Assistant discovery".to_owned());
+
+        if !self.home_assistant {
+            return Ok(());
+        }
+
+        let controller = &self.dali_config.name;
+        let command_topic = self.get_command_topic();
+        let availability_topic = MqttDali::get_is_active_topic(controller);
+        let version = get_version();
+        let mut discovery = Vec::new();
+
+        for bus in self.dali_config.buses.iter() {
+            for channel in bus.channels.iter() {
+                discovery.push((
+                    HomeAssistantLightConfig::get_discovery_topic(
+                        controller,
+                        &HomeAssistantLightConfig::get_light_object_id(
+                            bus.bus,
+                            channel.short_address,
+                        ),
+                    ),
+                    HomeAssistantLightConfig::new_light(
+                        controller,
+                        bus.bus,
+                        channel,
+                        &command_topic,
+                        &self.get_light_state_topic(bus.bus, channel.short_address),
+                        &availability_topic,
+                        HomeAssistantDevice::new(controller, &version),
+                    ),
+                ));
+            }
 
-        for (bus_number, bus) in self.dali_config.buses.iter_mut().enumerate() {
-            bus.status = self
-                .dali_manager
-                .controller
-                .get_bus_status(bus_number)
+            for group in bus.groups.iter() {
+                discovery.push((
+                    HomeAssistantLightConfig::get_discovery_topic(
+                        controller,
+                        &HomeAssistantLightConfig::get_group_object_id(
+                            bus.bus,
+                            group.group_address,
+                        ),
+                    ),
+                    HomeAssistantLightConfig::new_group(
+                        controller,
+                        bus.bus,
+                        group,
+                        &command_topic,
+                        &availability_topic,
+                        HomeAssistantDevice::new(controller, &version),
+                    ),
+                ));
+            }
+        }
+
+        let discovery_topics: HashSet<String> =
+            discovery.iter().map(|(topic, _)| topic.clone()).collect();
+
+        // An empty retained payload removes the entity from Home Assistant
+        for stale_topic in self.discovery_topics.difference(&discovery_topics) {
+            mqtt_client
+                .publish(stale_topic, QoS::AtLeastOnce, true, Vec::new())
+                .await
+                .change_context_lazy(into_context)?;
+        }
+
+        for (topic, light_config) in discovery.iter() {
+            mqtt_client
+                .publish(
+                    topic,
+                    QoS::AtLeastOnce,
+                    true,
+                    serde_json::to_vec(light_config).change_context_lazy(into_context)?,
+                )
+                .await
                 .change_context_lazy(into_context)?;
         }
 
+        self.discovery_topics = discovery_topics;
+        Ok(())
+    }
+
+    async fn publish_command_reply(
+        &self,
+        mqtt_client: &AsyncClient,
+        command_reply: &CommandReply,
+    ) -> Result<()> {
+        let into_context = || CommandError::Context("MQTT: Publish command reply".to_owned());
+
+        mqtt_client
+            .publish(
+                self.get_command_reply_topic(),
+                QoS::AtLeastOnce,
+                false,
+                serde_json::to_vec(command_reply).change_context_lazy(into_context)?,
+            )
+            .await
+            .change_context_lazy(into_context)
+    }
+
+    // Query the status of the buses that are not busy. The controller is queried on a thread, so the session is not
+    // blocked, and the statuses are sent to the session as events. The buses are busy until their status was queried.
+    fn start_update_bus_status(
+        &mut self,
+        request_id: Option<String>,
+        command: &DaliCommand,
+    ) -> Result<DaliBusResult> {
+        let into_context = || CommandError::Context("MQTT: UpdateBusStatus command".to_owned());
+        let buses: Vec<usize> = (0..self.dali_config.buses.len())
+            .filter(|bus_number| !self.scheduler.is_busy(*bus_number))
+            .collect();
+        let mut controller = self.dali_manager.controller.clone_controller();
+        let events = self.bus_job_sender.clone();
+        let command = command.clone();
+
+        let queried_buses = buses.clone();
+
+        for bus in buses.iter() {
+            self.scheduler.set_executing(*bus);
+        }
+
+        let spawn_result = thread::Builder::new()
+            .name("UpdateBusStatus".to_owned())
+            .spawn(move || {
+                let result = buses.iter().try_for_each(|&bus| -> Result<()> {
+                    let status = controller
+                        .get_bus_status(bus)
+                        .change_context_lazy(into_context)?;
+
+                    let _ = events.send(BusJobEvent::BusStatusUpdated { bus, status });
+                    Ok(())
+                });
+
+                let _ = events.send(BusJobEvent::Completed {
+                    buses,
+                    request_id,
+                    command,
+                    result: result.map(|_| None),
+                });
+            });
+
+        if let Err(e) = spawn_result {
+            for bus in queried_buses {
+                self.scheduler.set_idle(bus);
+            }
+            return Err(e).change_context_lazy(into_context);
+        }
+
         Ok(DaliBusResult::None)
     }
 
@@ -132,17 +557,31 @@ impl<'a> MqttDali<'a> {
         }
     }
 
-    fn check_bus(&mut self, bus_number: usize) -> Result<DaliBusResult> {
+    // Get the bus status from the controller (on the bus worker) and check that the bus is active. The status is sent
+    // to the session.
+    fn check_bus_job(dali_manager: &mut DaliManager, job: &BusJob) -> Result<DaliBusResult> {
+        let bus_number = job.bus;
         let into_context =
             || CommandError::Context(format!("MQTT Checking bus {bus_number} status"));
 
-        self.update_bus_status()?;
+        let status = dali_manager
+            .controller
+            .get_bus_status(bus_number)
+            .change_context_lazy(into_context)?;
+        let result = MqttDali::check_bus_status(bus_number, &status);
 
-        if let Some(bus) = self.dali_config.buses.get(bus_number) {
-            MqttDali::check_bus_status(bus_number, &bus.status)
-        } else {
-            Err(CommandError::BusNumber(bus_number)).change_context_lazy(into_context)
-        }
+        job.send(BusJobEvent::BusStatusUpdated {
+            bus: bus_number,
+            status,
+        });
+        result
+    }
+
+    fn get_bus(&self, bus_number: usize) -> Result<&BusConfig> {
+        self.dali_config
+            .buses
+            .get(bus_number)
+            .ok_or_else(|| Report::new(CommandError::BusNumber(bus_number)))
     }
 
     fn rename_bus(&mut self, bus_number: usize, name: &str) -> Result<DaliBusResult> {
@@ -238,131 +677,127 @@ impl<'a> MqttDali<'a> {
         }
     }
 
-    fn remove_group(&mut self, bus_number: usize, group_address: u8) -> Result<DaliBusResult> {
+    fn remove_group(
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
+        group_address: u8,
+    ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
         let into_context = || {
             CommandError::Context(format!(
                 "MQTT: Remove group {group_address} from bus {bus_number}"
             ))
         };
 
-        if let Some(bus) = self.dali_config.buses.get_mut(bus_number) {
-            MqttDali::check_bus_status(bus_number, &bus.status)?;
+        MqttDali::check_bus_status(bus_number, &bus.status)?;
 
-            if let Some(index) = bus
-                .groups
-                .iter()
-                .position(|g| g.group_address == group_address)
-            {
-                let group = bus.groups.get_mut(index).unwrap();
+        if let Some(index) = bus
+            .groups
+            .iter()
+            .position(|g| g.group_address == group_address)
+        {
+            let group = bus.groups.get_mut(index).unwrap();
 
-                // If group is not empty, remove membership of all members from this group
-                if !group.members.is_empty()
-                    && MqttDali::check_bus_status(bus_number, &bus.status).is_ok()
-                {
-                    for short_address in group.members.iter() {
-                        self.dali_manager
-                            .remove_from_group(bus_number, group_address, *short_address)
-                            .change_context_lazy(into_context)?;
-                    }
+            // If group is not empty, remove membership of all members from this group
+            if !group.members.is_empty()
+                && MqttDali::check_bus_status(bus_number, &bus.status).is_ok()
+            {
+                for short_address in group.members.iter() {
+                    dali_manager
+                        .remove_from_group(bus_number, group_address, *short_address)
+                        .change_context_lazy(into_context)?;
                 }
-
-                bus.groups.remove(index);
-                Ok(DaliBusResult::None)
-            } else {
-                Err(CommandError::NoSuchGroup(bus_number, group_address))
-                    .change_context_lazy(into_context)
             }
+
+            bus.groups.remove(index);
+            Ok(DaliBusResult::None)
         } else {
-            Err(CommandError::BusNumber(bus_number)).change_context_lazy(into_context)
+            Err(CommandError::NoSuchGroup(bus_number, group_address))
+                .change_context_lazy(into_context)
         }
     }
 
     fn add_to_group(
-        &mut self,
-        bus_number: usize,
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
         group_address: u8,
         short_address: u8,
     ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
         let into_context = || {
             CommandError::Context(format!(
                 "MQTT: Add light {short_address} to group {group_address} on bus {bus_number}"
             ))
         };
 
-        if let Some(bus) = self.dali_config.buses.get_mut(bus_number) {
-            let group = bus
-                .groups
-                .iter_mut()
-                .find(|g| g.group_address == group_address);
-
-            // Create group if not found
-            if group.is_none() {
-                bus.groups.push(Group {
-                    description: format!("Group {}", group_address),
-                    group_address,
-                    members: Vec::new(),
-                });
-            }
-
-            MqttDali::check_bus_status(bus_number, &bus.status)
-                .change_context_lazy(into_context)?;
-            self.dali_manager
-                .add_to_group_and_verify(bus_number, group_address, short_address)
-                .change_context_lazy(into_context)?;
+        let group = bus
+            .groups
+            .iter_mut()
+            .find(|g| g.group_address == group_address);
+
+        // Create group if not found
+        if group.is_none() {
+            bus.groups.push(Group {
+                description: format!("Group {}", group_address),
+                group_address,
+                members: Vec::new(),
+            });
+        }
 
-            let group = bus
-                .groups
-                .iter_mut()
-                .find(|g| g.group_address == group_address)
-                .unwrap();
-            if !group.members.contains(&short_address) {
-                group.members.push(short_address);
-            }
+        MqttDali::check_bus_status(bus_number, &bus.status).change_context_lazy(into_context)?;
+        dali_manager
+            .add_to_group_and_verify(bus_number, group_address, short_address)
+            .change_context_lazy(into_context)?;
 
-            Ok(DaliBusResult::None)
-        } else {
-            Err(CommandError::BusNumber(bus_number)).change_context_lazy(into_context)
+        let group = bus
+            .groups
+            .iter_mut()
+            .find(|g| g.group_address == group_address)
+            .unwrap();
+        if !group.members.contains(&short_address) {
+            group.members.push(short_address);
         }
+
+        Ok(DaliBusResult::None)
     }
 
     fn remove_from_group(
-        &mut self,
-        bus_number: usize,
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
         group_address: u8,
         short_address: u8,
     ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
         let into_context = || {
             CommandError::Context(format!(
                 "MQTT: Remove light {short_address} from group {group_address} on bus {bus_number}"
             ))
         };
 
-        if let Some(bus) = self.dali_config.buses.get_mut(bus_number) {
-            if let Some(group) = bus
-                .groups
-                .iter_mut()
-                .find(|g| g.group_address == group_address)
-            {
-                if let Some(index) = group.members.iter().position(|m| *m == short_address) {
-                    MqttDali::check_bus_status(bus_number, &bus.status)
-                        .change_context_lazy(into_context)?;
-                    self.dali_manager
-                        .remove_from_group_and_verify(bus_number, group_address, short_address)
-                        .change_context_lazy(into_context)?;
-                    group.members.remove(index);
-                }
-                Ok(DaliBusResult::None)
-            } else {
-                Err(CommandError::NoSuchGroup(bus_number, group_address))
-                    .change_context_lazy(into_context)
+        if let Some(group) = bus
+            .groups
+            .iter_mut()
+            .find(|g| g.group_address == group_address)
+        {
+            if let Some(index) = group.members.iter().position(|m| *m == short_address) {
+                MqttDali::check_bus_status(bus_number, &bus.status)
+                    .change_context_lazy(into_context)?;
+                dali_manager
+                    .remove_from_group_and_verify(bus_number, group_address, short_address)
+                    .change_context_lazy(into_context)?;
+                group.members.remove(index);
             }
+            Ok(DaliBusResult::None)
         } else {
-            Err(CommandError::BusNumber(bus_number)).change_context_lazy(into_context)
+            Err(CommandError::NoSuchGroup(bus_number, group_address))
+                .change_context_lazy(into_context)
         }
     }
 
-    fn match_group(
+    fn start_match_group(
         &mut self,
+        request_id: Option<String>,
+        command: &DaliCommand,
         bus_number: usize,
         group_address: u8,
         light_name_pattern: &str,
@@ -371,155 +806,2225 @@ impl<'a> MqttDali<'a> {
             CommandError::Context(format!("MQTT: Match group {group_address} on bus {bus_number} to pattern {light_name_pattern}"))
         };
 
-        if let Some(bus) = self.dali_config.buses.get_mut(bus_number) {
-            MqttDali::check_bus_status(bus_number, &bus.status)
-                .change_context_lazy(into_context)?;
+        let bus = self.get_bus(bus_number).change_context_lazy(into_context)?;
+
+        // The job matches the group on a copy of the bus configuration, the updated groups are sent back to the session
+        let mut bus_config = bus.clone();
+        let light_name_pattern = light_name_pattern.to_owned();
+
+        self.start_bus_job(request_id, command, bus_number, "MatchGroup", move |dali_manager, job| {
+            let into_context = || {
+                CommandError::Context(format!("MQTT: Match group {group_address} on bus {bus_number} to pattern {light_name_pattern}"))
+            };
+            let progress_job = job.clone();
+            let progress: MatchGroupProgress = Box::new(move |action, _| {
+                progress_job.progress(match action {
+                    MatchGroupAction::AddMember(light) => ProgressKind::GroupMemberAdded {
+                        group: group_address,
+                        light: light.to_owned(),
+                    },
+                    MatchGroupAction::RemoveMember(light) => ProgressKind::GroupMemberRemoved {
+                        group: group_address,
+                        light: light.to_owned(),
+                    },
+                })
+            });
 
-            self.dali_manager
+            let result = dali_manager
                 .match_group(
-                    bus,
+                    &mut bus_config,
                     group_address,
-                    light_name_pattern,
-                    Option::<Box<dyn Fn(MatchGroupAction, &str)>>::None,
+                    &light_name_pattern,
+                    Some(progress),
+                    Some(job.cancel.as_ref()),
                 )
-                .change_context_lazy(into_context)?;
-            Ok(DaliBusResult::None)
-        } else {
-            Err(CommandError::BusNumber(bus_number)).change_context_lazy(into_context)
-        }
+                .change_context_lazy(into_context);
+
+            // Group members that were changed before a failure are kept
+            job.send(BusJobEvent::GroupsUpdated {
+                bus: bus_number,
+                groups: bus_config.groups,
+            });
+            result.map(|_| None)
+        })
     }
 
-    async fn query_light_status(
+    fn start_audit_bus(
         &mut self,
-        mqtt_client: &AsyncClient,
-        bus: usize,
-        short_address: u8,
+        request_id: Option<String>,
+        command: &DaliCommand,
+        bus_number: usize,
     ) -> Result<DaliBusResult> {
-        let into_context =
-            || CommandError::Context(format!("MQTT: Query light {short_address} on bus {bus}"));
+        let into_context = || CommandError::Context(format!("MQTT: Audit bus {bus_number}"));
 
-        let light_status = self.dali_manager.query_light_status(bus, short_address);
-        let query_light_reply = match light_status {
-            Ok(light_status) => {
-                QueryLightReply::new(&self.dali_config.name, bus, short_address, light_status)
-            }
-            Err(e) => QueryLightReply::new_failure(
-                &self.dali_config.name,
-                bus,
-                short_address,
-                &e.to_string(),
-            ),
-        };
-        let topic = self.get_light_reply_topic("QueryLightStatus", bus, short_address);
+        let bus = self.get_bus(bus_number).change_context_lazy(into_context)?;
 
-        mqtt_client
-            .publish(
-                topic,
-                QoS::AtMostOnce,
-                false,
-                serde_json::to_vec(&query_light_reply).change_context_lazy(into_context)?,
-            )
-            .await
-            .change_context_lazy(into_context)?;
+        // Lights that were moved to other addresses are added to a copy of the bus configuration, which is sent back
+        // to the session
+        let mut bus_config = bus.clone();
 
-        Ok(DaliBusResult::None)
+        self.start_bus_job(
+            request_id,
+            command,
+            bus_number,
+            "AuditBus",
+            move |dali_manager, job| {
+                let into_context =
+                    || CommandError::Context(format!("MQTT: Audit bus {bus_number}"));
+                let progress_job = job.clone();
+                let progress: AuditBusProgress = Box::new(move |action| {
+                    progress_job.progress(match action {
+                        AuditBusAction::CheckAddress(short_address) => {
+                            ProgressKind::AddressChecked { short_address }
+                        }
+                        AuditBusAction::ConflictResolved(conflict) => {
+                            ProgressKind::AddressConflict {
+                                short_address: conflict.short_address,
+                                moved_to: conflict.moved_to.clone(),
+                            }
+                        }
+                    })
+                });
+
+                let result = dali_manager
+                    .audit_bus(&mut bus_config, Some(progress), Some(job.cancel.as_ref()))
+                    .change_context_lazy(into_context);
+
+                // Lights that were moved before a failure are kept
+                job.send(BusJobEvent::BusConfigUpdated {
+                    bus: bus_number,
+                    bus_config,
+                });
+                result.map(|_| None)
+            },
+        )
     }
 
-    async fn remove_short_address(
+    fn start_replace_light(
         &mut self,
+        request_id: Option<String>,
+        command: &DaliCommand,
         bus_number: usize,
         short_address: u8,
     ) -> Result<DaliBusResult> {
         let into_context = || {
             CommandError::Context(format!(
-                "MQTT: Remove short address {short_address} from bus {bus_number}"
+                "MQTT: Replace light {short_address} on bus {bus_number}"
             ))
         };
 
-        if let Some(bus) = self.dali_config.buses.get_mut(bus_number) {
-            MqttDali::check_bus_status(bus_number, &bus.status)
-                .change_context_lazy(into_context)?;
-
-            self.dali_manager
-                .remove_short_address(bus, short_address)
-                .change_context_lazy(into_context)?;
+        let bus = self.get_bus(bus_number).change_context_lazy(into_context)?;
 
-            Ok(DaliBusResult::None)
-        } else {
-            Err(CommandError::BusNumber(bus_number)).change_context_lazy(into_context)
+        if bus.get_channel_index(short_address).is_none() {
+            return Err(CommandError::ShortAddress(short_address))
+                .change_context_lazy(into_context);
         }
+
+        // The configuration is updated (with the new light's identification) only if the light was replaced
+        let mut bus_config = bus.clone();
+
+        self.start_bus_job(
+            request_id,
+            command,
+            bus_number,
+            "ReplaceLight",
+            move |dali_manager, job| {
+                dali_manager
+                    .replace_light(&mut bus_config, short_address)
+                    .change_context_lazy(|| {
+                        CommandError::Context(format!(
+                            "MQTT: Replace light {short_address} on bus {bus_number}"
+                        ))
+                    })?;
+
+                job.send(BusJobEvent::BusConfigUpdated {
+                    bus: bus_number,
+                    bus_config,
+                });
+                Ok(None)
+            },
+        )
     }
 
-    async fn find_lights(
+    // Identify runs as a bus job, since it takes the requested number of seconds (it can be stopped by Cancel)
+    fn start_identify(
         &mut self,
-        mqtt_client: &AsyncClient,
-        config_topic: &str,
+        request_id: Option<String>,
+        command: &DaliCommand,
         bus_number: usize,
-        selection: DaliDeviceSelection,
+        short_address: u8,
+        seconds: u32,
     ) -> Result<DaliBusResult> {
-        let into_context =
-            || CommandError::Context(format!("MQTT: Find lights on bus {bus_number}"));
-
-        self.check_bus(bus_number)
-            .change_context_lazy(into_context)?;
+        let into_context = move || {
+            CommandError::Context(format!(
+                "MQTT: Identify light {short_address} on bus {bus_number} for {seconds} seconds"
+            ))
+        };
 
-        if matches!(selection, DaliDeviceSelection::All) {
-            let bus = self.dali_config.buses.get_mut(bus_number).unwrap();
+        self.get_bus(bus_number).change_context_lazy(into_context)?;
 
-            bus.channels.clear();
+        self.start_bus_job(
+            request_id,
+            command,
+            bus_number,
+            "Identify",
+            move |dali_manager, job| {
+                dali_manager
+                    .identify(
+                        bus_number,
+                        short_address,
+                        seconds,
+                        Some(job.cancel.as_ref()),
+                    )
+                    .change_context_lazy(into_context)?;
+                Ok(None)
+            },
+        )
+    }
+
+    fn start_reconcile(
+        &mut self,
+        request_id: Option<String>,
+        command: &DaliCommand,
+        bus_number: usize,
+        policy: ReconcilePolicy,
+    ) -> Result<DaliBusResult> {
+        let into_context =
+            || CommandError::Context(format!("MQTT: Reconcile bus {bus_number} ({policy:?})"));
+
+        let bus = self.get_bus(bus_number).change_context_lazy(into_context)?;
+
+        // The report (differences between the configuration and the gear) is returned in the command reply
+        let mut bus_config = bus.clone();
+
+        self.start_bus_job(
+            request_id,
+            command,
+            bus_number,
+            "Reconcile",
+            move |dali_manager, job| {
+                let into_context = || {
+                    CommandError::Context(format!("MQTT: Reconcile bus {bus_number} ({policy:?})"))
+                };
+                let progress_job = job.clone();
+                let progress: ReconcileProgress = Box::new(move |short_address| {
+                    progress_job.progress(ProgressKind::AddressChecked { short_address })
+                });
+
+                let Some(report) = reconcile::reconcile(
+                    dali_manager,
+                    &mut bus_config,
+                    policy,
+                    Some(progress),
+                    Some(job.cancel.as_ref()),
+                )
+                .change_context_lazy(into_context)?
+                else {
+                    return Ok(None);
+                };
+
+                if report.is_empty() {
+                    info!("Configuration of bus {bus_number} matches the gear on the bus");
+                } else if policy != ReconcilePolicy::ReportOnly {
+                    job.send(BusJobEvent::BusConfigUpdated {
+                        bus: bus_number,
+                        bus_config,
+                    });
+                }
+
+                serde_json::to_value(&report)
+                    .map(Some)
+                    .change_context_lazy(into_context)
+            },
+        )
+    }
+
+    fn cancel_bus_job(&mut self, bus_number: usize) -> Result<DaliBusResult> {
+        let into_context =
+            || CommandError::Context(format!("MQTT: Cancel operation on bus {bus_number}"));
+
+        if self.scheduler.cancel(bus_number) {
+            info!("Cancelling operation running on bus {bus_number}");
+            Ok(DaliBusResult::None)
+        } else {
+            Err(CommandError::NothingToCancel(bus_number)).change_context_lazy(into_context)
+        }
+    }
+
+    fn query_light_status(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+        topic: String,
+        short_address: u8,
+    ) -> BusJobResult {
+        let bus = job.bus;
+        let into_context =
+            || CommandError::Context(format!("MQTT: Query light {short_address} on bus {bus}"));
+
+        let query_light_reply = match dali_manager.query_light_status(bus, short_address) {
+            Ok(light_status) => {
+                QueryLightReply::new(&job.controller_name, bus, short_address, light_status)
+            }
+            Err(e) => QueryLightReply::new_failure(
+                &job.controller_name,
+                bus,
+                short_address,
+                &e.to_string(),
+            ),
+        };
+
+        job.light_reply(
+            topic,
+            serde_json::to_value(&query_light_reply).change_context_lazy(into_context)?,
+        )
+    }
+
+    fn store_scene(
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
+        target: DaliTarget,
+        scene: u8,
+        level: u8,
+    ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Store level {level} as scene {scene} for {target} on bus {bus_number}"
+            ))
+        };
+
+        MqttDali::check_bus_status(bus_number, &bus.status).change_context_lazy(into_context)?;
+
+        dali_manager
+            .store_scene(bus_number, target, scene, level)
+            .change_context_lazy(into_context)?;
+
+        for short_address in bus.get_target_members(target) {
+            bus.set_scene_level(scene, short_address, level);
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    fn set_operating_levels(
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
+        target: DaliTarget,
+        levels: &OperatingLevels,
+    ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Set operating levels {levels:?} for {target} on bus {bus_number}"
+            ))
+        };
+
+        MqttDali::check_bus_status(bus_number, &bus.status).change_context_lazy(into_context)?;
+
+        dali_manager
+            .set_operating_levels(bus_number, target, levels)
+            .change_context_lazy(into_context)?;
+        bus.set_operating_levels(target, levels);
+
+        // Gear may adjust the levels (e.g. min level below physical min level), so store the actual levels
+        for short_address in bus.get_target_members(target) {
+            if let Ok(actual_levels) =
+                dali_manager.query_operating_levels(bus_number, short_address)
+            {
+                bus.set_operating_levels(DaliTarget::Light(short_address), &actual_levels);
+            }
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    fn set_fade_duration(
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
+        target: DaliTarget,
+        fade_duration: u32,
+    ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Set fade duration {fade_duration}ms for {target} on bus {bus_number}"
+            ))
+        };
+
+        MqttDali::check_bus_status(bus_number, &bus.status).change_context_lazy(into_context)?;
+
+        dali_manager
+            .set_fade_duration(bus_number, target, fade_duration)
+            .change_context_lazy(into_context)?;
+        bus.update_target_channels(target, |channel| {
+            channel.fade_duration = Some(fade_duration)
+        });
+
+        Ok(DaliBusResult::None)
+    }
+
+    fn set_fade_rate(
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
+        target: DaliTarget,
+        fade_rate: u8,
+    ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Set fade rate {fade_rate} for {target} on bus {bus_number}"
+            ))
+        };
+
+        MqttDali::check_bus_status(bus_number, &bus.status).change_context_lazy(into_context)?;
+
+        dali_manager
+            .set_fade_rate(bus_number, target, fade_rate)
+            .change_context_lazy(into_context)?;
+        bus.update_target_channels(target, |channel| channel.fade_rate = Some(fade_rate));
+
+        Ok(DaliBusResult::None)
+    }
+
+    fn remove_from_scene(
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
+        target: DaliTarget,
+        scene: u8,
+    ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Remove {target} from scene {scene} on bus {bus_number}"
+            ))
+        };
+
+        MqttDali::check_bus_status(bus_number, &bus.status).change_context_lazy(into_context)?;
+
+        dali_manager
+            .remove_from_scene(bus_number, target, scene)
+            .change_context_lazy(into_context)?;
+
+        for short_address in bus.get_target_members(target) {
+            bus.remove_from_scene(scene, short_address);
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    // Reset lights (after checking the confirmation token) and remove them from the groups and scenes in the configuration
+    fn reset(
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
+        target: DaliTarget,
+        confirm: &str,
+    ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
+        let into_context =
+            || CommandError::Context(format!("MQTT: Reset {target} on bus {bus_number}"));
+
+        if confirm != RESET_CONFIRMATION {
+            return Err(CommandError::ResetNotConfirmed(RESET_CONFIRMATION))
+                .change_context_lazy(into_context);
+        }
+
+        MqttDali::check_bus_status(bus_number, &bus.status).change_context_lazy(into_context)?;
+
+        dali_manager
+            .reset(bus_number, target)
+            .change_context_lazy(into_context)?;
+        bus.reset_lights(target);
+
+        Ok(DaliBusResult::None)
+    }
+
+    fn reset_memory_bank(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+        target: DaliTarget,
+        bank: u8,
+        confirm: &str,
+    ) -> Result<DaliBusResult> {
+        let bus_number = job.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Reset memory bank {bank} of {target} on bus {bus_number}"
+            ))
+        };
+
+        if confirm != RESET_CONFIRMATION {
+            return Err(CommandError::ResetNotConfirmed(RESET_CONFIRMATION))
+                .change_context_lazy(into_context);
+        }
+
+        MqttDali::check_bus_job(dali_manager, job).change_context_lazy(into_context)?;
+        dali_manager
+            .reset_memory_bank(bus_number, target, bank)
+            .change_context_lazy(into_context)
+    }
+
+    fn query_scene_levels(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+        topic: String,
+        short_address: u8,
+    ) -> BusJobResult {
+        let bus = job.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Query scene levels of light {short_address} on bus {bus}"
+            ))
+        };
+
+        let query_scene_levels_reply = match dali_manager.query_scene_levels(bus, short_address) {
+            Ok(levels) => {
+                QuerySceneLevelsReply::new(&job.controller_name, bus, short_address, &levels)
+            }
+            Err(e) => QuerySceneLevelsReply::new_failure(
+                &job.controller_name,
+                bus,
+                short_address,
+                &e.to_string(),
+            ),
+        };
+
+        job.light_reply(
+            topic,
+            serde_json::to_value(&query_scene_levels_reply).change_context_lazy(into_context)?,
+        )
+    }
+
+    fn query_device_info(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+        topic: String,
+        short_address: u8,
+    ) -> BusJobResult {
+        let bus = job.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Query device info of light {short_address} on bus {bus}"
+            ))
+        };
+
+        let query_device_info_reply = match dali_manager.query_device_info(bus, short_address) {
+            Ok(device_info) => {
+                QueryDeviceInfoReply::new(&job.controller_name, bus, short_address, &device_info)
+            }
+            Err(e) => QueryDeviceInfoReply::new_failure(
+                &job.controller_name,
+                bus,
+                short_address,
+                &e.to_string(),
+            ),
+        };
+
+        job.light_reply(
+            topic,
+            serde_json::to_value(&query_device_info_reply).change_context_lazy(into_context)?,
+        )
+    }
+
+    fn query_color(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+        topic: String,
+        short_address: u8,
+    ) -> BusJobResult {
+        let bus = job.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Query colour of light {short_address} on bus {bus}"
+            ))
+        };
+
+        let query_color_reply = match dali_manager.query_color(bus, short_address) {
+            Ok(color_info) => {
+                QueryColorReply::new(&job.controller_name, bus, short_address, &color_info)
+            }
+            Err(e) => QueryColorReply::new_failure(
+                &job.controller_name,
+                bus,
+                short_address,
+                &e.to_string(),
+            ),
+        };
+
+        job.light_reply(
+            topic,
+            serde_json::to_value(&query_color_reply).change_context_lazy(into_context)?,
+        )
+    }
+
+    fn query_led_gear(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+        topic: String,
+        short_address: u8,
+    ) -> BusJobResult {
+        let bus = job.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Query LED gear of light {short_address} on bus {bus}"
+            ))
+        };
+
+        let query_led_gear_reply = match dali_manager.query_led_gear_info(bus, short_address) {
+            Ok(led_gear_info) => {
+                QueryLedGearReply::new(&job.controller_name, bus, short_address, &led_gear_info)
+            }
+            Err(e) => QueryLedGearReply::new_failure(
+                &job.controller_name,
+                bus,
+                short_address,
+                &e.to_string(),
+            ),
+        };
+
+        job.light_reply(
+            topic,
+            serde_json::to_value(&query_led_gear_reply).change_context_lazy(into_context)?,
+        )
+    }
+
+    // Read a memory bank, if memory bank 0 is read the light's identification is stored in the configuration
+    fn read_memory_bank(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+        bus_config: &mut BusConfig,
+        topic: String,
+        short_address: u8,
+        bank: u8,
+    ) -> BusJobResult {
+        let bus = job.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Read memory bank {bank} of light {short_address} on bus {bus}"
+            ))
+        };
+
+        MqttDali::check_bus_job(dali_manager, job).change_context_lazy(into_context)?;
+
+        let memory_bank = dali_manager.read_memory_bank(bus, short_address, bank);
+        let read_memory_bank_reply = match memory_bank {
+            Ok(memory_bank) => {
+                if bank == 0 {
+                    if let Some(index) = bus_config.get_channel_index(short_address) {
+                        bus_config.channels[index].identification =
+                            memory_bank.identification.clone();
+                    }
+                }
+
+                ReadMemoryBankReply::new(&job.controller_name, bus, short_address, &memory_bank)
+            }
+            Err(e) => ReadMemoryBankReply::new_failure(
+                &job.controller_name,
+                bus,
+                short_address,
+                &e.to_string(),
+            ),
+        };
+
+        job.light_reply(
+            topic,
+            serde_json::to_value(&read_memory_bank_reply).change_context_lazy(into_context)?,
+        )
+    }
+
+    fn query_light_state(
+        dali_manager: &mut DaliManager,
+        bus: usize,
+        short_address: u8,
+    ) -> dali_manager::Result<(LightStatus, u8)> {
+        let status = dali_manager.query_light_status(bus, short_address)?;
+        let level = dali_manager.query_actual_level(bus, short_address)?;
+
+        Ok((status, level))
+    }
+
+    fn query_target_states(
+        dali_manager: &mut DaliManager,
+        bus: &BusConfig,
+        target: DaliTarget,
+    ) -> Vec<(u8, dali_manager::Result<(LightStatus, u8)>)> {
+        bus.get_target_members(target)
+            .into_iter()
+            .map(|short_address| {
+                (
+                    short_address,
+                    MqttDali::query_light_state(dali_manager, bus.bus, short_address),
+                )
+            })
+            .collect()
+    }
+
+    async fn publish_light_state(
+        &self,
+        mqtt_client: &AsyncClient,
+        bus: usize,
+        short_address: u8,
+        state: &dali_manager::Result<(LightStatus, u8)>,
+    ) -> Result<DaliBusResult> {
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Publish state of light {short_address} on bus {bus}"
+            ))
+        };
+
+        let light_state = match state {
+            Ok((status, level)) => {
+                LightState::new(&self.dali_config.name, bus, short_address, *status, *level)
+            }
+            Err(e) => {
+                LightState::new_failure(&self.dali_config.name, bus, short_address, &e.to_string())
+            }
+        };
+        let topic = self.get_light_state_topic(bus, short_address);
+
+        mqtt_client
+            .publish(
+                topic,
+                QoS::AtLeastOnce,
+                true,
+                serde_json::to_vec(&light_state).change_context_lazy(into_context)?,
+            )
+            .await
+            .change_context_lazy(into_context)?;
+
+        Ok(DaliBusResult::None)
+    }
+
+    // Poll the next light (on its bus worker), the state is sent to the session when the light was polled
+    fn start_poll_next_light(&mut self) -> Result<DaliBusResult> {
+        let Some((bus, short_address)) = self.light_poller.next_light(self.dali_config) else {
+            return Ok(DaliBusResult::None);
+        };
+
+        if self.scheduler.is_busy(bus) {
+            return Ok(DaliBusResult::None);
+        }
+
+        self.scheduler.set_executing(bus);
+        self.run_bus_work(
+            bus,
+            "Polling",
+            move |dali_manager| {
+                Ok(MqttDali::query_light_state(
+                    dali_manager,
+                    bus,
+                    short_address,
+                ))
+            },
+            move |state| BusJobEvent::LightPolled {
+                bus,
+                short_address,
+                state,
+            },
+        )
+    }
+
+    // Publish events if the status of the polled light has changed since it was last polled
+    async fn light_polled(
+        &mut self,
+        mqtt_client: &AsyncClient,
+        bus: usize,
+        short_address: u8,
+        state: dali_manager::Result<(LightStatus, u8)>,
+    ) -> Result<DaliBusResult> {
+        let into_context =
+            || CommandError::Context(format!("MQTT: Polling light {short_address} on bus {bus}"));
+
+        let events = self
+            .light_poller
+            .update(bus, short_address, state.as_ref().ok().copied());
+
+        if events.is_empty() {
+            return Ok(DaliBusResult::None);
+        }
+
+        let description = match &state {
+            Ok((status, _)) => status.to_string(),
+            Err(e) => e.to_string(),
+        };
+        let level = state.as_ref().ok().map(|(_, level)| *level);
+        let events_topic = self.get_events_topic();
+
+        for event in events {
+            info!("Light {short_address} on bus {bus}: {event:?} ({description})");
+
+            let light_event = LightEvent::new(
+                &self.dali_config.name,
+                bus,
+                short_address,
+                event,
+                level,
+                &description,
+            );
+
+            mqtt_client
+                .publish(
+                    &events_topic,
+                    QoS::AtLeastOnce,
+                    false,
+                    serde_json::to_vec(&light_event).change_context_lazy(into_context)?,
+                )
+                .await
+                .change_context_lazy(into_context)?;
+        }
+
+        self.publish_light_state(mqtt_client, bus, short_address, &state)
+            .await
+            .change_context_lazy(into_context)
+    }
+
+    fn remove_short_address(
+        dali_manager: &mut DaliManager,
+        bus: &mut BusConfig,
+        short_address: u8,
+    ) -> Result<DaliBusResult> {
+        let bus_number = bus.bus;
+        let into_context = || {
+            CommandError::Context(format!(
+                "MQTT: Remove short address {short_address} from bus {bus_number}"
+            ))
+        };
+
+        MqttDali::check_bus_status(bus_number, &bus.status).change_context_lazy(into_context)?;
+
+        dali_manager
+            .remove_short_address(bus, short_address)
+            .change_context_lazy(into_context)?;
+
+        Ok(DaliBusResult::None)
+    }
+
+    // DALI operations are synchronous and may take a long time, so they are never executed by the session. Each bus
+    // has a worker thread (with its own handle to the DALI controller) that is started the first time it is needed.
+    // The scheduler makes sure that the bus is busy while work is executed on it.
+    fn run_on_bus_worker(&mut self, bus_number: usize, work: BusWork) -> Result<DaliBusResult> {
+        let into_context =
+            || CommandError::Context(format!("MQTT: Running work on bus {bus_number} worker"));
+
+        if !self.bus_workers.contains_key(&bus_number) {
+            let (work_sender, work_receiver) = std::sync::mpsc::channel::<BusWork>();
+            let mut controller = self.dali_manager.controller.clone_controller();
+
+            let spawn_result = thread::Builder::new()
+                .name(format!("Bus-{bus_number}"))
+                .spawn(move || {
+                    while let Ok(work) = work_receiver.recv() {
+                        work(&mut DaliManager::new(controller.as_mut()));
+                    }
+                });
+
+            if let Err(e) = spawn_result {
+                self.scheduler.set_idle(bus_number);
+                return Err(e).change_context_lazy(into_context);
+            }
+
+            self.bus_workers.insert(bus_number, work_sender);
         }
 
-        let mut device_iterator = DaliBusIterator::new(
-            self.dali_manager,
-            bus_number,
-            selection,
-            Option::<Box<dyn Fn(u8, u8)>>::None,
-        )
-        .change_context_lazy(into_context)?;
+        if self.bus_workers[&bus_number].send(work).is_err() {
+            self.bus_workers.remove(&bus_number);
+            self.scheduler.set_idle(bus_number);
+            return Err(CommandError::BusWorkerStopped(bus_number))
+                .change_context_lazy(into_context);
+        }
+
+        Ok(DaliBusResult::None)
+    }
+
+    // Run work on the bus worker, the event made of its result is sent to the session when it is done. Work that
+    // panics fails, so the session is still told that the work is done and the bus does not stay busy.
+    fn run_bus_work<T, F, E>(
+        &mut self,
+        bus_number: usize,
+        operation: &'static str,
+        work: F,
+        into_event: E,
+    ) -> Result<DaliBusResult>
+    where
+        F: FnOnce(&mut DaliManager) -> Result<T> + Send + 'static,
+        E: FnOnce(Result<T>) -> BusJobEvent + Send + 'static,
+    {
+        let events = self.bus_job_sender.clone();
+
+        self.run_on_bus_worker(
+            bus_number,
+            Box::new(move |dali_manager| {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| work(dali_manager)))
+                    .unwrap_or_else(|_| {
+                        Err(Report::new(CommandError::JobPanicked(
+                            operation, bus_number,
+                        )))
+                    });
+
+                let _ = events.send(into_event(result));
+            }),
+        )
+    }
+
+    // Run a job on the bus worker, the job's result is sent to the session when it is done
+    fn run_bus_job<F>(
+        &mut self,
+        job: BusJob,
+        command: &DaliCommand,
+        run_job: F,
+    ) -> Result<DaliBusResult>
+    where
+        F: FnOnce(&mut DaliManager, &BusJob) -> BusJobResult + Send + 'static,
+    {
+        let bus_number = job.bus;
+        let operation = job.operation;
+        let request_id = job.request_id.clone();
+        let command = command.clone();
+
+        self.run_bus_work(
+            bus_number,
+            operation,
+            move |dali_manager| run_job(dali_manager, &job),
+            move |result| BusJobEvent::Completed {
+                buses: vec![bus_number],
+                request_id,
+                command,
+                result,
+            },
+        )
+    }
+
+    // Long operations run on the bus worker. The bus is busy until the job is done, so commands for it are queued,
+    // while commands for other buses are executed.
+    fn start_bus_job<F>(
+        &mut self,
+        request_id: Option<String>,
+        command: &DaliCommand,
+        bus_number: usize,
+        operation: &'static str,
+        run_job: F,
+    ) -> Result<DaliBusResult>
+    where
+        F: FnOnce(&mut DaliManager, &BusJob) -> BusJobResult + Send + 'static,
+    {
+        let job = BusJob {
+            bus: bus_number,
+            controller_name: self.dali_config.name.clone(),
+            operation,
+            request_id,
+            cancel: self.scheduler.set_busy(bus_number),
+            events: self.bus_job_sender.clone(),
+        };
+
+        self.run_bus_job(job, command, move |dali_manager, job| {
+            let result =
+                MqttDali::check_bus_job(dali_manager, job).and_then(|_| run_job(dali_manager, job));
+
+            job.progress(ProgressKind::Finished {
+                ok: result.is_ok(),
+                cancelled: job.is_cancelled(),
+            });
+            result
+        })
+    }
+
+    // Commands are executed by the bus worker on a copy of the bus configuration. If the command changes the
+    // configuration, the copy is sent back to the session. The state of the lights the command was sent to is
+    // published after the command was executed.
+    fn start_bus_command<F>(
+        &mut self,
+        request_id: Option<String>,
+        command: &DaliCommand,
+        bus_number: usize,
+        changes_config: bool,
+        update_state: Option<DaliTarget>,
+        execute: F,
+    ) -> Result<DaliBusResult>
+    where
+        F: FnOnce(&mut DaliManager, &BusJob, &mut BusConfig) -> BusJobResult + Send + 'static,
+    {
+        let mut bus_config = self
+            .get_bus(bus_number)
+            .change_context_lazy(|| {
+                CommandError::Context(format!("MQTT: Execute command on bus {bus_number}"))
+            })?
+            .clone();
+        let job = BusJob {
+            bus: bus_number,
+            controller_name: self.dali_config.name.clone(),
+            operation: "Command",
+            request_id,
+            cancel: Arc::new(AtomicBool::new(false)),
+            events: self.bus_job_sender.clone(),
+        };
+
+        self.scheduler.set_executing(bus_number);
+        self.run_bus_job(job, command, move |dali_manager, job| {
+            let result = execute(dali_manager, job, &mut bus_config);
+
+            if result.is_ok() {
+                if let Some(target) = update_state {
+                    job.send(BusJobEvent::LightStates {
+                        bus: bus_number,
+                        states: MqttDali::query_target_states(dali_manager, &bus_config, target),
+                    });
+                }
+
+                if changes_config {
+                    job.send(BusJobEvent::BusConfigUpdated {
+                        bus: bus_number,
+                        bus_config,
+                    });
+                }
+            }
+
+            result
+        })
+    }
+
+    fn start_find_lights(
+        &mut self,
+        request_id: Option<String>,
+        command: &DaliCommand,
+        bus_number: usize,
+        selection: DaliDeviceSelection,
+    ) -> Result<DaliBusResult> {
+        let into_context =
+            || CommandError::Context(format!("MQTT: Find lights on bus {bus_number}"));
+
+        let bus = self.get_bus(bus_number).change_context_lazy(into_context)?;
+
+        if let DaliDeviceSelection::All = selection {
+            // The configuration is rebuilt from a copy of the bus configuration once all the lights were found
+            let bus_config = bus.clone();
+
+            return self.start_bus_job(
+                request_id,
+                command,
+                bus_number,
+                "FindAllLights",
+                move |dali_manager, job| {
+                    MqttDali::recommission_job(dali_manager, job, bus_config).map(|_| None)
+                },
+            );
+        }
+
+        let used_addresses: Vec<u8> = bus
+            .channels
+            .iter()
+            .map(|channel| channel.short_address)
+            .collect();
+
+        self.start_bus_job(
+            request_id,
+            command,
+            bus_number,
+            "FindNewLights",
+            move |dali_manager, job| {
+                MqttDali::find_lights_job(dali_manager, job, selection, used_addresses)
+                    .map(|_| None)
+            },
+        )
+    }
+
+    // Number of searches for all lights before giving up because the lights' random addresses keep changing
+    const SEARCH_ATTEMPTS: usize = 3;
+
+    // Search all the lights on the bus (without changing their short address). Returns None if the random addresses of
+    // lights that were found were changed while searching (lights are randomized again if several lights have the same
+    // random address).
+    fn search_all_devices(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+    ) -> Result<Option<Vec<FoundDevice>>> {
+        let bus_number = job.bus;
+        let into_context =
+            || CommandError::Context(format!("MQTT: Search all lights on bus {bus_number}"));
+
+        let progress: FindDeviceProgress = {
+            let job = job.clone();
+
+            Box::new(move |found, step| {
+                job.progress(ProgressKind::SearchStep {
+                    step,
+                    found: found as usize,
+                    short_address: None,
+                })
+            })
+        };
+
+        // Lights are not randomized, so they can be matched to the configured lights by their random address
+        let mut device_iterator = DaliBusIterator::new_keeping_random_addresses(
+            dali_manager,
+            bus_number,
+            DaliDeviceSelection::All,
+            Some(progress),
+        )
+        .change_context_lazy(into_context)?;
+        let mut devices = Vec::new();
+        let mut first_found_randomise_count = None;
+
+        device_iterator
+            .for_each_device(dali_manager, Some(job.cancel.as_ref()), |device_iterator| {
+                let Some(random_address) = device_iterator.random_address() else {
+                    return;
+                };
+                let short_address = device_iterator.current_short_address();
+
+                first_found_randomise_count.get_or_insert(device_iterator.randomise_count());
+                devices.push(FoundDevice {
+                    random_address,
+                    short_address,
+                    identification: None,
+                });
+                job.progress(ProgressKind::DeviceFound {
+                    random_address,
+                    short_address,
+                    found: devices.len(),
+                });
+            })
+            .change_context_lazy(into_context)?;
+
+        match first_found_randomise_count {
+            Some(randomise_count) if randomise_count != device_iterator.randomise_count() => {
+                Ok(None)
+            }
+            _ => Ok(Some(devices)),
+        }
+    }
+
+    // Find all the lights on the bus and match them to the configured lights (by serial number, random address or
+    // short address), so lights keep their short address, name, groups and scenes. Short addresses are programmed
+    // and the configuration is updated only after all lights were found. If the job is cancelled (or fails) while
+    // searching, nothing is changed.
+    fn recommission_job(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+        bus_config: BusConfig,
+    ) -> Result<()> {
+        let bus_number = job.bus;
+        let into_context =
+            || CommandError::Context(format!("MQTT: Re-commission lights on bus {bus_number}"));
+        let mut found_devices = None;
+
+        for attempt in 1..=MqttDali::SEARCH_ATTEMPTS {
+            found_devices = MqttDali::search_all_devices(dali_manager, job)
+                .change_context_lazy(into_context)?;
+
+            if found_devices.is_some() || job.is_cancelled() {
+                break;
+            }
+
+            info!("Lights on bus {bus_number} were randomized while searching, search again (attempt {attempt})");
+        }
+
+        if job.is_cancelled() {
+            info!("Re-commissioning lights on bus {bus_number} was cancelled, configuration is not changed");
+            return Ok(());
+        }
+
+        let Some(mut devices) = found_devices else {
+            return Err(CommandError::RandomAddressesChanged(bus_number))
+                .change_context_lazy(into_context);
+        };
+
+        // Identify lights by their current short address (if the address is not shared by several lights)
+        for index in 0..devices.len() {
+            if let Some(short_address) = devices[index].short_address {
+                if devices
+                    .iter()
+                    .filter(|device| device.short_address == Some(short_address))
+                    .count()
+                    == 1
+                {
+                    if let Ok(identification) =
+                        dali_manager.query_gear_identification(bus_number, short_address)
+                    {
+                        devices[index].identification = identification;
+                    }
+                }
+            }
+        }
+
+        let mut assignments = commissioning::assign_addresses(&bus_config, devices);
+        let changed_addresses: Vec<(u32, u8)> = assignments
+            .iter()
+            .filter(|assignment| assignment.is_changed())
+            .map(|assignment| (assignment.device.random_address, assignment.short_address))
+            .collect();
+
+        dali_manager
+            .program_short_addresses(bus_number, &changed_addresses)
+            .change_context_lazy(into_context)?;
+
+        // Lights that had no (unique) short address are identified after their address was programmed
+        for assignment in assignments
+            .iter_mut()
+            .filter(|assignment| assignment.device.identification.is_none())
+        {
+            if let Ok(identification) =
+                dali_manager.query_gear_identification(bus_number, assignment.short_address)
+            {
+                assignment.device.identification = identification;
+            }
+        }
+
+        let kept = assignments
+            .iter()
+            .filter(|assignment| assignment.channel.is_some())
+            .count();
+        info!(
+            "Re-commissioned bus {bus_number}: {kept} lights kept, {} new lights, {} lights not found, {} addresses programmed",
+            assignments.len() - kept,
+            bus_config.channels.len() - kept,
+            changed_addresses.len()
+        );
+
+        job.send(BusJobEvent::BusConfigUpdated {
+            bus: bus_number,
+            bus_config: commissioning::rebuild_bus_config(&bus_config, &assignments),
+        });
+
+        Ok(())
+    }
+
+    // Find lights and program their short address. When the job is cancelled, the search is terminated (TERMINATE
+    // command is sent) before looking for the next light.
+    fn find_lights_job(
+        dali_manager: &mut DaliManager,
+        job: &BusJob,
+        selection: DaliDeviceSelection,
+        mut used_addresses: Vec<u8>,
+    ) -> Result<()> {
+        let bus_number = job.bus;
+        let into_context =
+            || CommandError::Context(format!("MQTT: Find lights on bus {bus_number}"));
+        let next_short_address = Rc::new(Cell::new(None));
+
+        let progress: FindDeviceProgress = {
+            let job = job.clone();
+            let next_short_address = next_short_address.clone();
+
+            Box::new(move |found, step| {
+                job.progress(ProgressKind::SearchStep {
+                    step,
+                    found: found as usize,
+                    short_address: next_short_address.get(),
+                })
+            })
+        };
+
+        let mut device_iterator =
+            DaliBusIterator::new(dali_manager, bus_number, selection, Some(progress))
+                .change_context_lazy(into_context)?;
+        let mut found_lights = Vec::new();
+
+        loop {
+            let short_address =
+                (0..64u8).find(|short_address| !used_addresses.contains(short_address));
+
+            next_short_address.set(short_address);
+
+            if job.is_cancelled() {
+                info!("Finding lights on bus {bus_number} was cancelled");
+                device_iterator.terminate();
+            }
+
+            if device_iterator
+                .find_next_device(dali_manager)
+                .change_context_lazy(into_context)?
+                .is_none()
+            {
+                break;
+            }
+
+            let Some(short_address) = short_address else {
+                // Do not leave the bus in initialize state
+                device_iterator.terminate();
+                let _ = device_iterator.find_next_device(dali_manager);
+
+                return Err(CommandError::NoUnusedShortAddress(bus_number))
+                    .change_context_lazy(into_context);
+            };
+
+            dali_manager
+                .program_short_address(bus_number, short_address)
+                .change_context_lazy(into_context)?;
+
+            used_addresses.push(short_address);
+            found_lights.push(short_address);
+            job.send(BusJobEvent::LightFound {
+                bus: bus_number,
+                short_address,
+                random_address: device_iterator.random_address(),
+            });
+            job.progress(ProgressKind::LightFound {
+                short_address,
+                found: found_lights.len(),
+            });
+        }
+
+        // Identify the lights that were found (light that does not implement memory bank 0 is left unidentified)
+        for short_address in found_lights {
+            if let Ok(Some(identification)) =
+                dali_manager.query_gear_identification(bus_number, short_address)
+            {
+                job.send(BusJobEvent::LightIdentified {
+                    bus: bus_number,
+                    short_address,
+                    identification,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Execute the next queued command (or poll the next light)
+    async fn execute_next(&mut self, config: &Config, mqtt_client: &AsyncClient) -> Result<()> {
+        match self.scheduler.next() {
+            Some(ScheduledWork::Command(request)) => {
+                self.execute_command(config, mqtt_client, request).await?
+            }
+            Some(ScheduledWork::PollLight) => {
+                self.start_poll_next_light()?;
+            }
+            None => {}
+        }
+
+        self.publish_queue_depth(mqtt_client).await
+    }
+
+    async fn publish_queue_depth(&mut self, mqtt_client: &AsyncClient) -> Result<()> {
+        let into_context = || CommandError::Context("MQTT: Publish command queue depth".to_owned());
+        let queue_depth = self.scheduler.queue_depth();
+
+        if self.reported_queue_depth != Some(queue_depth) {
+            mqtt_client
+                .publish(
+                    self.get_queue_depth_topic(),
+                    QoS::AtMostOnce,
+                    true,
+                    queue_depth.to_string().as_bytes(),
+                )
+                .await
+                .change_context_lazy(into_context)?;
+            self.reported_queue_depth = Some(queue_depth);
+        }
+
+        Ok(())
+    }
+
+    async fn execute_command(
+        &mut self,
+        config: &Config,
+        mqtt_client: &AsyncClient,
+        request: DaliCommandRequest,
+    ) -> Result<()> {
+        let DaliCommandRequest {
+            request_id,
+            command,
+        } = request;
+        let mut republish_config = true; // Should the configuration republished after command execution
+        let mut reply_deferred = true; // Command is executed by the bus worker, reply is published when it is done
+
+        let _span = span!(tracing::Level::INFO, "Command", command = ?command);
+
+        info!("Received command {:?}", command);
+
+        let command_result: Result<DaliBusResult> = match command.clone() {
+            DaliCommand::SetLightBrightness {
+                bus,
+                address,
+                value,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                Some(DaliTarget::Light(address)),
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .set_light_brightness(bus, address, value)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SetLightBrightness command on bus {bus} address {address} value {value}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::SetGroupBrightness { bus, group, value } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                Some(DaliTarget::Group(group)),
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .set_group_brightness(bus, group, value)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SetGroupBrightness command on bus {bus} group {group} value {value}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::SetBusBrightness { bus, value } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                Some(DaliTarget::Bus),
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .set_bus_brightness(bus, value)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SetBusBrightness command on bus {bus} value {value}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::BroadcastCommand {
+                bus,
+                dali_command,
+                repeat,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                Some(DaliTarget::Bus),
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .send_command_to_bus(bus, dali_command, repeat)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: BroadcastCommand command on bus {bus} command {dali_command}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::ArcCommand {
+                bus,
+                target,
+                command: arc_command,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                Some(target),
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .send_arc_command(bus, target, arc_command)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: ArcCommand command on bus {bus} {target} {arc_command:?}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::UpdateBusStatus => {
+                self.start_update_bus_status(request_id.clone(), &command)
+            }
+            DaliCommand::RenameBus {
+                bus: bus_number,
+                name,
+            } => {
+                reply_deferred = false;
+                self.rename_bus(bus_number, &name)
+            }
+            DaliCommand::RenameLight { bus, address, name } => {
+                reply_deferred = false;
+                self.rename_light(bus, address, &name)
+            }
+            DaliCommand::RenameGroup { bus, group, name } => {
+                reply_deferred = false;
+                self.rename_group(bus, group, &name)
+            }
+            DaliCommand::NewGroup { bus } => {
+                reply_deferred = false;
+                self.new_group(bus)
+            }
+            DaliCommand::MatchGroup {
+                bus,
+                group,
+                pattern,
+            } => self.start_match_group(request_id.clone(), &command, bus, group, &pattern),
+            DaliCommand::RemoveGroup { bus, group } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::remove_group(dali_manager, bus_config, group).map(|_| None)
+                },
+            ),
+            DaliCommand::AddToGroup {
+                bus,
+                group,
+                address,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::add_to_group(dali_manager, bus_config, group, address).map(|_| None)
+                },
+            ),
+            DaliCommand::RemoveFromGroup {
+                bus,
+                group,
+                address,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::remove_from_group(dali_manager, bus_config, group, address)
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::AuditBus { bus } => {
+                self.start_audit_bus(request_id.clone(), &command, bus)
+            }
+            DaliCommand::ReplaceLight { bus, address } => {
+                self.start_replace_light(request_id.clone(), &command, bus, address)
+            }
+            DaliCommand::Reconcile { bus, policy } => {
+                self.start_reconcile(request_id.clone(), &command, bus, policy)
+            }
+            DaliCommand::FindAllLights { bus } => {
+                self.start_find_lights(request_id.clone(), &command, bus, DaliDeviceSelection::All)
+            }
+            DaliCommand::FindNewLights { bus } => self.start_find_lights(
+                request_id.clone(),
+                &command,
+                bus,
+                DaliDeviceSelection::WithoutShortAddress,
+            ),
+            DaliCommand::QueryLightStatus { bus, address } => {
+                let topic = self.get_light_reply_topic("QueryLightStatus", bus, address);
+
+                self.start_bus_command(
+                    request_id.clone(),
+                    &command,
+                    bus,
+                    false,
+                    None,
+                    move |dali_manager, job, _| {
+                        MqttDali::query_light_status(dali_manager, job, topic, address)
+                    },
+                )
+            }
+            DaliCommand::RemoveShortAddress { bus, address } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::remove_short_address(dali_manager, bus_config, address).map(|_| None)
+                },
+            ),
+            DaliCommand::SetLightFadeTime {
+                bus,
+                address,
+                fade_time,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                None,
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .set_light_fade_time(bus, address, fade_time)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SetLightFadeTime command on bus {bus} address {address} fade_time {fade_time}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::SetGroupFadeTime {
+                bus,
+                group,
+                fade_time,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                None,
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .set_group_fade_time(bus, group, fade_time)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SetGroupFadeTime command on bus {bus} group {group} fade_time {fade_time}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::SetLightFadeDuration {
+                bus,
+                address,
+                fade_duration,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::set_fade_duration(
+                        dali_manager,
+                        bus_config,
+                        DaliTarget::Light(address),
+                        fade_duration,
+                    )
+                    .map(|_| None)
+                },
+            ),
+            DaliCommand::SetGroupFadeDuration {
+                bus,
+                group,
+                fade_duration,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::set_fade_duration(
+                        dali_manager,
+                        bus_config,
+                        DaliTarget::Group(group),
+                        fade_duration,
+                    )
+                    .map(|_| None)
+                },
+            ),
+            DaliCommand::SetLightFadeRate {
+                bus,
+                address,
+                fade_rate,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::set_fade_rate(
+                        dali_manager,
+                        bus_config,
+                        DaliTarget::Light(address),
+                        fade_rate,
+                    )
+                    .map(|_| None)
+                },
+            ),
+            DaliCommand::SetGroupFadeRate {
+                bus,
+                group,
+                fade_rate,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::set_fade_rate(
+                        dali_manager,
+                        bus_config,
+                        DaliTarget::Group(group),
+                        fade_rate,
+                    )
+                    .map(|_| None)
+                },
+            ),
+            DaliCommand::SetLightOperatingLevels {
+                bus,
+                address,
+                levels,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::set_operating_levels(
+                        dali_manager,
+                        bus_config,
+                        DaliTarget::Light(address),
+                        &levels,
+                    )
+                    .map(|_| None)
+                },
+            ),
+            DaliCommand::SetGroupOperatingLevels { bus, group, levels } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::set_operating_levels(
+                        dali_manager,
+                        bus_config,
+                        DaliTarget::Group(group),
+                        &levels,
+                    )
+                    .map(|_| None)
+                },
+            ),
+            DaliCommand::GoToScene { bus, target, scene } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                Some(target),
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .go_to_scene(bus, target, scene)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: GoToScene command on bus {bus} {target} scene {scene}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::StoreScene {
+                bus,
+                target,
+                scene,
+                level,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::store_scene(dali_manager, bus_config, target, scene, level)
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::RemoveFromScene { bus, target, scene } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                true,
+                None,
+                move |dali_manager, _, bus_config| {
+                    MqttDali::remove_from_scene(dali_manager, bus_config, target, scene)
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::QuerySceneLevels { bus, address } => {
+                let topic = self.get_light_reply_topic("QuerySceneLevels", bus, address);
 
-        while device_iterator
-            .find_next_device(self.dali_manager)
-            .change_context_lazy(into_context)?
-            .is_some()
-        {
-            let short_address = (0..64u8)
-                .find(|short_address| {
-                    !self.dali_config.buses[bus_number]
-                        .channels
-                        .iter()
-                        .any(|channel| channel.short_address == *short_address)
-                })
-                .expect("Unable to find unused short address!!");
+                self.start_bus_command(
+                    request_id.clone(),
+                    &command,
+                    bus,
+                    false,
+                    None,
+                    move |dali_manager, job, _| {
+                        MqttDali::query_scene_levels(dali_manager, job, topic, address)
+                    },
+                )
+            }
+            DaliCommand::QueryDeviceInfo { bus, address } => {
+                let topic = self.get_light_reply_topic("QueryDeviceInfo", bus, address);
 
-            self.dali_manager
-                .program_short_address(bus_number, short_address)
-                .change_context_lazy(into_context)?;
-            {
-                let bus = self.dali_config.buses.get_mut(bus_number).unwrap();
-                bus.channels.push(crate::config_payload::Channel {
-                    description: format!("Light {}", short_address),
-                    short_address,
-                });
+                self.start_bus_command(
+                    request_id.clone(),
+                    &command,
+                    bus,
+                    false,
+                    None,
+                    move |dali_manager, job, _| {
+                        MqttDali::query_device_info(dali_manager, job, topic, address)
+                    },
+                )
+            }
+            DaliCommand::SetColorTemperature { bus, target, mirek } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                None,
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .set_color_temperature(bus, target, mirek)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SetColorTemperature command on bus {bus} {target} to {mirek} mirek"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::SetColor { bus, target, color } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                None,
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .set_color(bus, target, &color)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SetColor command on bus {bus} {target}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::QueryColor { bus, address } => {
+                let topic = self.get_light_reply_topic("QueryColor", bus, address);
+
+                self.start_bus_command(
+                    request_id.clone(),
+                    &command,
+                    bus,
+                    false,
+                    None,
+                    move |dali_manager, job, _| {
+                        MqttDali::query_color(dali_manager, job, topic, address)
+                    },
+                )
+            }
+            DaliCommand::SetDimmingCurve { bus, target, curve } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                None,
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .set_dimming_curve(bus, target, curve)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SetDimmingCurve command on bus {bus} {target} to {curve:?}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::SetFastFadeTime {
+                bus,
+                target,
+                fast_fade_time,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                None,
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .set_fast_fade_time(bus, target, fast_fade_time)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SetFastFadeTime command on bus {bus} {target} to {fast_fade_time}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::QueryLedGear { bus, address } => {
+                let topic = self.get_light_reply_topic("QueryLedGear", bus, address);
+
+                self.start_bus_command(
+                    request_id.clone(),
+                    &command,
+                    bus,
+                    false,
+                    None,
+                    move |dali_manager, job, _| {
+                        MqttDali::query_led_gear(dali_manager, job, topic, address)
+                    },
+                )
+            }
+            DaliCommand::Identify {
+                bus,
+                address,
+                seconds,
+            } => self.start_identify(request_id.clone(), &command, bus, address, seconds),
+            DaliCommand::Reset {
+                bus,
+                target,
+                confirm,
+            } => {
+                // Reset lights go to their maximum level, group members are no longer known
+                self.start_bus_command(
+                    request_id.clone(),
+                    &command,
+                    bus,
+                    true,
+                    Some(DaliTarget::Bus),
+                    move |dali_manager, _, bus_config| {
+                        MqttDali::reset(dali_manager, bus_config, target, &confirm).map(|_| None)
+                    },
+                )
+            }
+            DaliCommand::ResetMemoryBank {
+                bus,
+                target,
+                bank,
+                confirm,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                None,
+                move |dali_manager, job, _| {
+                    MqttDali::reset_memory_bank(dali_manager, job, target, bank, &confirm)
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::SavePersistentVariables { bus, target } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                None,
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .save_persistent_variables(bus, target)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: SavePersistentVariables command on bus {bus} {target}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::ReadMemoryBank { bus, address, bank } => {
+                let topic = self.get_light_reply_topic("ReadMemoryBank", bus, address);
+
+                // Reading memory bank 0 updates the light's identification
+                self.start_bus_command(
+                    request_id.clone(),
+                    &command,
+                    bus,
+                    bank == 0,
+                    None,
+                    move |dali_manager, job, bus_config| {
+                        MqttDali::read_memory_bank(
+                            dali_manager,
+                            job,
+                            bus_config,
+                            topic,
+                            address,
+                            bank,
+                        )
+                    },
+                )
+            }
+            DaliCommand::WriteMemoryLocation {
+                bus,
+                address,
+                bank,
+                location,
+                value,
+            } => self.start_bus_command(
+                request_id.clone(),
+                &command,
+                bus,
+                false,
+                None,
+                move |dali_manager, _, _| {
+                    dali_manager
+                        .write_memory_location(bus, address, bank, location, value)
+                        .change_context_lazy(|| {
+                            CommandError::Context(format!(
+                                "MQTT: WriteMemoryLocation command on bus {bus} address {address} bank {bank} location {location} value {value}"
+                            ))
+                        })
+                        .map(|_| None)
+                },
+            ),
+            DaliCommand::Cancel { bus } => {
+                republish_config = false;
+                reply_deferred = false;
+                self.cancel_bus_job(bus)
             }
+        };
+
+        if reply_deferred && command_result.is_ok() {
+            return Ok(());
+        }
+
+        let succeeded = self
+            .publish_command_result(
+                mqtt_client,
+                request_id.as_deref(),
+                &command,
+                &command_result,
+                None,
+            )
+            .await?;
+
+        if succeeded && republish_config {
+            self.config_changed(config, mqtt_client).await?;
+        }
+
+        Ok(())
+    }
+
+    // Publish the command reply and update the status topic, returns true if the command succeeded
+    async fn publish_command_result(
+        &mut self,
+        mqtt_client: &AsyncClient,
+        request_id: Option<&str>,
+        command: &DaliCommand,
+        command_result: &Result<DaliBusResult>,
+        reply_result: Option<serde_json::Value>,
+    ) -> Result<bool> {
+        let into_context =
+            || CommandError::Context("MQTT session: Publish command result".to_owned());
+        let status_topic = &self.get_status_topic();
 
-            MqttDali::publish_config(mqtt_client, config_topic, self.dali_config)
+        let command_reply = match command_result {
+            Ok(_) => CommandReply::new(&self.dali_config.name, request_id, reply_result),
+            Err(e) => {
+                CommandReply::new_failure(&self.dali_config.name, request_id, &format!("{e:#}"))
+            }
+        };
+        self.publish_command_reply(mqtt_client, &command_reply)
+            .await?;
+
+        if let Err(e) = command_result {
+            let error_message =
+                serde_json::to_string(&format!("Command {:?} completed with error {}", command, e))
+                    .change_context_lazy(into_context)?;
+
+            error!("{}", error_message);
+            mqtt_client
+                .publish(
+                    status_topic,
+                    QoS::AtMostOnce,
+                    false,
+                    error_message.as_bytes(),
+                )
                 .await
                 .change_context_lazy(into_context)?;
+
+            self.status_ok = false;
+            Ok(false)
+        } else {
+            if !self.status_ok {
+                mqtt_client
+                    .publish(status_topic, QoS::AtLeastOnce, false, "\"OK\"".as_bytes())
+                    .await
+                    .change_context_lazy(into_context)?;
+                self.status_ok = true;
+            }
+
+            Ok(true)
         }
+    }
 
-        Ok(DaliBusResult::None)
+    // Publish and save the changed configuration
+    async fn config_changed(&mut self, config: &Config, mqtt_client: &AsyncClient) -> Result<()> {
+        let into_context =
+            || CommandError::Context("MQTT session: Configuration changed".to_owned());
+
+        MqttDali::publish_config(mqtt_client, &self.get_config_topic(), self.dali_config)
+            .await
+            .change_context_lazy(into_context)?;
+
+        config.save(self.dali_config).expect("Saving config file");
+        self.config_dirty = false;
+
+        self.publish_home_assistant_discovery(mqtt_client)
+            .await
+            .change_context_lazy(into_context)
+    }
+
+    async fn handle_bus_job_event(
+        &mut self,
+        config: &Config,
+        mqtt_client: &AsyncClient,
+        event: BusJobEvent,
+    ) -> Result<()> {
+        let into_context = || CommandError::Context("MQTT session: Bus job event".to_owned());
+
+        match event {
+            BusJobEvent::Progress(progress) => {
+                mqtt_client
+                    .publish(
+                        self.get_progress_topic(),
+                        QoS::AtMostOnce,
+                        false,
+                        serde_json::to_vec(&progress).change_context_lazy(into_context)?,
+                    )
+                    .await
+                    .change_context_lazy(into_context)?;
+            }
+            BusJobEvent::BusStatusUpdated { bus, status } => {
+                let bus_config = &mut self.dali_config.buses[bus];
+
+                if bus_config.status != status {
+                    bus_config.status = status;
+                    self.config_dirty = true;
+                }
+            }
+            BusJobEvent::LightReply { topic, reply } => {
+                mqtt_client
+                    .publish(
+                        topic,
+                        QoS::AtMostOnce,
+                        false,
+                        serde_json::to_vec(&reply).change_context_lazy(into_context)?,
+                    )
+                    .await
+                    .change_context_lazy(into_context)?;
+            }
+            BusJobEvent::LightStates { bus, states } => {
+                for (short_address, state) in states {
+                    self.publish_light_state(mqtt_client, bus, short_address, &state)
+                        .await?;
+                }
+            }
+            BusJobEvent::LightPolled {
+                bus,
+                short_address,
+                state,
+            } => {
+                self.scheduler.set_idle(bus);
+
+                match state {
+                    Ok(state) => {
+                        self.light_polled(mqtt_client, bus, short_address, state)
+                            .await?;
+                    }
+                    Err(e) => error!("Polling light {short_address} on bus {bus}: {e:?}"),
+                }
+            }
+            BusJobEvent::LightFound {
+                bus,
+                short_address,
+                random_address,
+            } => {
+                info!("Found light on bus {bus}, short address {short_address}");
+
+                self.dali_config.buses[bus]
+                    .channels
+                    .push(crate::config_payload::Channel {
+                        description: format!("Light {}", short_address),
+                        short_address,
+                        identification: None,
+                        random_address,
+                        levels: OperatingLevels::default(),
+                        fade_duration: None,
+                        fade_rate: None,
+                    });
+                self.config_dirty = true;
+
+                MqttDali::publish_config(mqtt_client, &self.get_config_topic(), self.dali_config)
+                    .await
+                    .change_context_lazy(into_context)?;
+            }
+            BusJobEvent::LightIdentified {
+                bus,
+                short_address,
+                identification,
+            } => {
+                let bus = &mut self.dali_config.buses[bus];
+
+                if let Some(index) = bus.get_channel_index(short_address) {
+                    bus.channels[index].identification = Some(identification);
+                }
+                self.config_dirty = true;
+            }
+            BusJobEvent::GroupsUpdated { bus, groups } => {
+                self.dali_config.buses[bus].groups = groups;
+                self.config_dirty = true;
+            }
+            BusJobEvent::BusConfigUpdated {
+                bus,
+                mut bus_config,
+            } => {
+                // The status may have been queried (by the bus worker) after the configuration was copied
+                bus_config.status = self.dali_config.buses[bus].status.clone();
+                self.dali_config.buses[bus] = bus_config;
+                self.config_dirty = true;
+            }
+            BusJobEvent::Completed {
+                buses,
+                request_id,
+                command,
+                result,
+            } => {
+                let cancelled_buses: Vec<bool> = buses
+                    .into_iter()
+                    .map(|bus| self.scheduler.set_idle(bus))
+                    .collect();
+                let cancelled = cancelled_buses.contains(&true);
+                let (command_result, reply_value) = match result {
+                    Ok(reply_value) => (Ok(DaliBusResult::None), reply_value),
+                    Err(e) => (Err(e), None),
+                };
+                let reply_result = if cancelled {
+                    Some(serde_json::json!({ "cancelled": true }))
+                } else {
+                    reply_value
+                };
+
+                self.publish_command_result(
+                    mqtt_client,
+                    request_id.as_deref(),
+                    &command,
+                    &command_result,
+                    reply_result,
+                )
+                .await?;
+
+                // Changes made before a failure (or cancellation) are kept, so the configuration is saved in any case
+                if self.config_dirty {
+                    self.config_changed(config, mqtt_client).await?;
+                }
+            }
+        }
+
+        Ok(())
     }
 
-    pub async fn run_session(
+    async fn run_session(
         &mut self,
         config: &Config,
         mqtt_client: AsyncClient,
-        mut mqtt_events: EventLoop,
+        mut mqtt_events: MqttEvents,
+        bus_job_events: &mut BusJobEvents,
     ) -> Result<()> {
-        let into_context = || CommandError::Context("MQTT session: Event loop".to_owned());
         let config_topic = &self.get_config_topic();
-        let status_topic = &self.get_status_topic();
-        let mut status_ok = false;
+
+        self.status_ok = false;
+        self.reported_queue_depth = None;
 
         info!("MQTT session started: Connecting to MQTT broker");
         let active_topic = MqttDali::get_is_active_topic(&self.dali_config.name);
@@ -543,6 +3048,9 @@ impl<'a> MqttDali<'a> {
         MqttDali::publish_config(&mqtt_client, config_topic, self.dali_config)
             .await
             .map_err(|e| CommandError::MqttError(e.to_string()))?;
+        self.publish_home_assistant_discovery(&mqtt_client)
+            .await
+            .map_err(|e| CommandError::MqttError(e.to_string()))?;
 
         let command_topic = &self.get_command_topic();
         mqtt_client
@@ -550,174 +3058,75 @@ impl<'a> MqttDali<'a> {
             .await
             .map_err(|e| CommandError::MqttError(e.to_string()))?;
 
+        let poll_interval = self.light_poller.interval();
+        let mut poll_timer = interval(poll_interval.unwrap_or(Duration::from_secs(1)));
+        poll_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         loop {
-            let event = mqtt_events
-                .poll()
-                .await
-                .map_err(|e| CommandError::MqttError(e.to_string()))?;
+            // MQTT events (commands) are always queued before executing the next queued command, so superseded commands
+            // are removed from the queue before they are executed
+            let event = tokio::select! {
+                biased;
+
+                event = mqtt_events.recv() => event
+                    .ok_or_else(|| CommandError::MqttError("MQTT event loop terminated".to_owned()))?
+                    .map_err(|e| CommandError::MqttError(e.to_string()))?,
+                Some(job_event) = bus_job_events.recv() => {
+                    self.handle_bus_job_event(config, &mqtt_client, job_event).await?;
+                    continue;
+                }
+                _ = poll_timer.tick(), if poll_interval.is_some() => {
+                    self.scheduler.schedule_poll();
+                    continue;
+                }
+                _ = std::future::ready(()), if self.scheduler.has_ready_work() => {
+                    self.execute_next(config, &mqtt_client).await?;
+                    continue;
+                }
+            };
 
             if let Event::Incoming(Packet::Publish(Publish {
                 ref topic, payload, ..
             })) = event
             {
                 if topic == command_topic {
-                    let mut republish_config = true; // Should the configuration republished after command execution
-
                     match serde_json::from_slice(payload.as_ref())
-                        as serde_json::Result<DaliCommand>
+                        as serde_json::Result<DaliCommandRequest>
                     {
-                        Ok(command) => {
-                            let _span = span!(tracing::Level::INFO, "Command", command = ?command);
-
-                            info!("Received command {:?}", command);
-
-                            let command_result: Result<DaliBusResult> = match command {
-                                DaliCommand::SetLightBrightness {
-                                    bus,
-                                    address,
-                                    value,
-                                } => {
-                                    republish_config = false;
-                                    self.dali_manager
-                                        .set_light_brightness_async(bus, address, value)
-                                        .await
-                                        .change_context_lazy(|| CommandError::Context(format!("MQTT: SetLightBrightness command on bus {bus} address {address} value {value}")))
-                                }
-                                DaliCommand::SetGroupBrightness { bus, group, value } => {
-                                    republish_config = false;
-                                    self.dali_manager
-                                        .set_group_brightness_async(bus, group, value)
-                                        .await
-                                        .change_context_lazy(|| CommandError::Context(format!("MQTT: SetGroupBrightness command on bus {bus} group {group} value {value}")))
-                                }
-                                DaliCommand::UpdateBusStatus => self.update_bus_status(),
-                                DaliCommand::RenameBus {
-                                    bus: bus_number,
-                                    ref name,
-                                } => self.rename_bus(bus_number, name),
-                                DaliCommand::RenameLight {
-                                    bus,
-                                    address,
-                                    ref name,
-                                } => self.rename_light(bus, address, name),
-                                DaliCommand::RenameGroup {
-                                    bus,
-                                    group,
-                                    ref name,
-                                } => self.rename_group(bus, group, name),
-                                DaliCommand::NewGroup { bus } => self.new_group(bus),
-                                DaliCommand::MatchGroup {
-                                    bus,
-                                    group,
-                                    ref pattern,
-                                } => self.match_group(bus, group, pattern),
-                                DaliCommand::RemoveGroup { bus, group } => {
-                                    self.remove_group(bus, group)
-                                }
-                                DaliCommand::AddToGroup {
-                                    bus,
-                                    group,
-                                    address,
-                                } => self.add_to_group(bus, group, address),
-                                DaliCommand::RemoveFromGroup {
-                                    bus,
-                                    group,
-                                    address,
-                                } => self.remove_from_group(bus, group, address),
-                                DaliCommand::FindAllLights { bus } => {
-                                    self.find_lights(
-                                        &mqtt_client,
-                                        config_topic,
-                                        bus,
-                                        DaliDeviceSelection::All,
-                                    )
-                                    .await
-                                }
-                                DaliCommand::FindNewLights { bus } => {
-                                    self.find_lights(
-                                        &mqtt_client,
-                                        config_topic,
-                                        bus,
-                                        DaliDeviceSelection::WithoutShortAddress,
-                                    )
-                                    .await
-                                }
-                                DaliCommand::QueryLightStatus { bus, address } => {
-                                    republish_config = false;
-                                    self.query_light_status(&mqtt_client, bus, address).await
-                                }
-                                DaliCommand::RemoveShortAddress { bus, address } => {
-                                    self.remove_short_address(bus, address).await
-                                }
-                                DaliCommand::SetLightFadeTime {
-                                    bus,
-                                    address,
-                                    fade_time,
-                                } => {
-                                    republish_config = false;
-                                    self.dali_manager
-                                        .set_light_fade_time(bus, address, fade_time)
-                                        .change_context_lazy(|| CommandError::Context(format!("MQTT: SetLightFadeTime command on bus {bus} address {address} fade_time {fade_time}")))
-                                }
-                                DaliCommand::SetGroupFadeTime {
-                                    bus,
-                                    group,
-                                    fade_time,
-                                } => {
-                                    republish_config = false;
-                                    self.dali_manager
-                                        .set_group_fade_time(bus, group, fade_time)
-                                        .change_context_lazy(|| CommandError::Context(format!("MQTT: SetGroupFadeTime command on bus {bus} group {group} fade_time {fade_time}")))
-                                }
-                            };
-
-                            if let Err(e) = command_result {
-                                let error_message = serde_json::to_string(&format!(
-                                    "Command {:?} completed with error {}",
-                                    command, e
-                                ))
-                                .change_context_lazy(into_context)?;
-
-                                error!("{}", error_message);
-                                mqtt_client
-                                    .publish(
-                                        status_topic,
-                                        QoS::AtMostOnce,
-                                        false,
-                                        error_message.as_bytes(),
-                                    )
-                                    .await
-                                    .change_context_lazy(into_context)?;
-
-                                status_ok = false;
-                            } else {
-                                if !status_ok {
-                                    mqtt_client
-                                        .publish(
-                                            status_topic,
-                                            QoS::AtLeastOnce,
-                                            false,
-                                            "\"OK\"".as_bytes(),
-                                        )
-                                        .await
-                                        .change_context_lazy(into_context)?;
-                                    status_ok = true;
-                                }
-
-                                if republish_config {
-                                    MqttDali::publish_config(
-                                        &mqtt_client,
-                                        config_topic,
-                                        self.dali_config,
-                                    )
-                                    .await
-                                    .change_context_lazy(into_context)?;
-
-                                    config.save(self.dali_config).expect("Saving config file");
-                                }
+                        Ok(request) => {
+                            if let Some(superseded) = self.scheduler.schedule(request) {
+                                let command_reply = CommandReply::new_failure(
+                                    &self.dali_config.name,
+                                    superseded.request_id.as_deref(),
+                                    "Superseded by a newer command for the same target",
+                                );
+                                self.publish_command_reply(&mqtt_client, &command_reply)
+                                    .await?;
                             }
+
+                            self.publish_queue_depth(&mqtt_client).await?;
+                        }
+                        Err(e) => {
+                            error!("Invalid payload received on {}: {}", command_topic, e);
+
+                            // Reply with the request id (if the payload is a JSON object that has one)
+                            let request_id =
+                                serde_json::from_slice::<serde_json::Value>(payload.as_ref())
+                                    .ok()
+                                    .and_then(|payload| {
+                                        payload
+                                            .get("request_id")
+                                            .and_then(|id| id.as_str())
+                                            .map(|id| id.to_owned())
+                                    });
+                            let command_reply = CommandReply::new_failure(
+                                &self.dali_config.name,
+                                request_id.as_deref(),
+                                &format!("Invalid command: {e}"),
+                            );
+                            self.publish_command_reply(&mqtt_client, &command_reply)
+                                .await?;
                         }
-                        Err(e) => error!("Invalid payload received on {}: {}", command_topic, e),
                     }
                 } else {
                     error!("Got publish on unexpected topic {}", topic);
@@ -726,16 +3135,49 @@ impl<'a> MqttDali<'a> {
         }
     }
 
-    pub fn new(
+    fn new(
+        config: &Config,
         dali_manager: &'a mut DaliManager<'a>,
         dali_config: &'a mut DaliConfig,
+        bus_job_sender: mpsc::UnboundedSender<BusJobEvent>,
     ) -> MqttDali<'a> {
         MqttDali {
             dali_config,
             dali_manager,
+            light_poller: LightPoller::new(config.poll_interval),
+            home_assistant: config.home_assistant,
+            discovery_topics: HashSet::new(),
+            scheduler: BusScheduler::new(),
+            bus_job_sender,
+            status_ok: false,
+            reported_queue_depth: None,
+            bus_workers: HashMap::new(),
+            config_dirty: false,
         }
     }
 
+    // DALI bus operations are synchronous and may take a long time (e.g. FindAllLights), so the MQTT event loop is polled
+    // in its own task and the session receives the events over a channel. This way keep alive and acknowledgements are
+    // handled by the event loop task while the session is busy. The channel is unbounded, so the event loop task never
+    // waits for the session.
+    fn spawn_event_loop(mut mqtt_events: EventLoop) -> (JoinHandle<()>, MqttEvents) {
+        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+
+        let event_loop_task = tokio::spawn(async move {
+            loop {
+                let event = mqtt_events.poll().await;
+                let failed = event.is_err();
+
+                // Stop if the session has ended or if the connection failed (the session will reconnect)
+                if event_sender.send(event).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        (event_loop_task, event_receiver)
+    }
+
     pub async fn run(
         config: &Config,
         dali_manager: &'a mut DaliManager<'a>,
@@ -743,13 +3185,16 @@ impl<'a> MqttDali<'a> {
         mqtt_broker: &str,
     ) -> Result<()> {
         let name = dali_config.name.clone();
-        let mut mqtt = MqttDali::new(dali_manager, dali_config);
+        // Bus jobs may outlive a session, so their events are received by whatever session is running when they are sent
+        let (bus_job_sender, mut bus_job_events) = mpsc::unbounded_channel();
+        let mut mqtt = MqttDali::new(config, dali_manager, dali_config, bus_job_sender);
+
+        let connection_options = config.mqtt.get_mqtt_options(mqtt_broker, &name)?;
 
         loop {
             info!("Connecting to MQTT broker");
 
-            let client_id = format!("DALI-{}", name);
-            let mut mqtt_options = MqttOptions::new(client_id, mqtt_broker, 1883);
+            let mut mqtt_options = connection_options.clone();
             let last_will = LastWill::new(
                 MqttDali::get_is_active_topic(&name),
                 "false".as_bytes(),
@@ -763,8 +3208,14 @@ impl<'a> MqttDali<'a> {
                 .set_request_channel_capacity(200);
 
             let (mqtt_client, mqtt_events) = AsyncClient::new(mqtt_options, 200);
+            let (event_loop_task, mqtt_events) = MqttDali::spawn_event_loop(mqtt_events);
+            let session_result = mqtt
+                .run_session(config, mqtt_client, mqtt_events, &mut bus_job_events)
+                .await;
 
-            match mqtt.run_session(config, mqtt_client, mqtt_events).await {
+            event_loop_task.abort();
+
+            match session_result {
                 Ok(_) => break Ok(()),
                 Err(e) => {
                     info!("MQTT session terminated due to error: {e}, wait 10 seconds and try to reconnect");
@@ -775,3 +3226,182 @@ impl<'a> MqttDali<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::mqtt::{CommandError, MqttConnectionConfig};
+    use rumqttc::tokio_rustls::TlsAcceptor;
+    use rumqttc::{AsyncClient, Event, Packet};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{RootCertStore, ServerConfig};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // Test certificates (EC P-256, valid for 100 years) were created with openssl: a CA, a broker certificate for
+    // localhost and a client certificate, both signed by the CA
+    const TLS_TEST_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_data/mqtt_tls");
+
+    fn test_file(name: &str) -> String {
+        format!("{TLS_TEST_DATA}/{name}")
+    }
+
+    fn connection_config(port: u16) -> MqttConnectionConfig {
+        MqttConnectionConfig {
+            port: Some(port),
+            client_id: Some("dali-test".to_owned()),
+            username: Some("dali-user".to_owned()),
+            password: Some("dali-password".to_owned()),
+            tls: false,
+            ca_cert: None,
+            client_cert: None,
+            client_key: None,
+        }
+    }
+
+    // Broker stand-in: receive the CONNECT packet, accept the connection and return the packet
+    async fn accept_connect<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> Vec<u8> {
+        let mut packet = vec![stream.read_u8().await.unwrap()];
+        let mut remaining_length = 0usize;
+
+        for shift in (0..28).step_by(7) {
+            let length_byte = stream.read_u8().await.unwrap();
+
+            packet.push(length_byte);
+            remaining_length |= ((length_byte & 0x7f) as usize) << shift;
+
+            if length_byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        let header_length = packet.len();
+
+        packet.resize(header_length + remaining_length, 0);
+        stream
+            .read_exact(&mut packet[header_length..])
+            .await
+            .unwrap();
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap(); // CONNACK, connection accepted
+        stream.flush().await.unwrap();
+
+        packet
+    }
+
+    // Connect to the broker stand-in, and return the CONNECT packet it received
+    async fn connect(
+        config: &MqttConnectionConfig,
+        broker: tokio::task::JoinHandle<Vec<u8>>,
+    ) -> Vec<u8> {
+        let mqtt_options = config.get_mqtt_options("localhost", "Test").unwrap();
+        let (_client, mut event_loop) = AsyncClient::new(mqtt_options, 10);
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Event::Incoming(Packet::ConnAck(_)) = event_loop.poll().await.unwrap() {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("No CONNACK from broker stand-in");
+
+        broker.await.unwrap()
+    }
+
+    fn contains(packet: &[u8], value: &str) -> bool {
+        packet
+            .windows(value.len())
+            .any(|window| window == value.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_credentials() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = connection_config(listener.local_addr().unwrap().port());
+        let broker = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+
+            accept_connect(stream).await
+        });
+
+        let packet = connect(&config, broker).await;
+
+        assert_eq!(packet[0], 0x10); // CONNECT
+        assert!(contains(&packet, "dali-test"));
+        assert!(contains(&packet, "dali-user"));
+        assert!(contains(&packet, "dali-password"));
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_tls_client_certificate() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = MqttConnectionConfig {
+            ca_cert: Some(test_file("ca.crt")),
+            client_cert: Some(test_file("client.crt")),
+            client_key: Some(test_file("client.key")),
+            ..connection_config(listener.local_addr().unwrap().port())
+        };
+
+        // Getting the options installs the crypto provider used by the broker stand-in
+        config.get_mqtt_options("localhost", "Test").unwrap();
+
+        let mut client_roots = RootCertStore::empty();
+
+        client_roots
+            .add(CertificateDer::from_pem_file(test_file("ca.crt")).unwrap())
+            .unwrap();
+
+        let server_config = ServerConfig::builder()
+            .with_client_cert_verifier(
+                WebPkiClientVerifier::builder(Arc::new(client_roots))
+                    .build()
+                    .unwrap(),
+            )
+            .with_single_cert(
+                vec![CertificateDer::from_pem_file(test_file("broker.crt")).unwrap()],
+                PrivateKeyDer::from_pem_file(test_file("broker.key")).unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let broker = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.expect("TLS handshake failed");
+
+            assert!(stream.get_ref().1.peer_certificates().is_some());
+            accept_connect(stream).await
+        });
+
+        let packet = connect(&config, broker).await;
+
+        assert!(contains(&packet, "dali-user"));
+    }
+
+    #[test]
+    fn test_invalid_tls_configuration() {
+        let config = MqttConnectionConfig {
+            tls: true,
+            client_cert: Some(test_file("client.crt")),
+            ..connection_config(8883)
+        };
+        let e = config.get_mqtt_options("localhost", "Test").unwrap_err();
+
+        assert!(matches!(
+            e.current_context(),
+            CommandError::TlsConfiguration(_)
+        ));
+
+        let config = MqttConnectionConfig {
+            tls: true,
+            client_cert: Some(test_file("client.crt")),
+            client_key: Some(test_file("client.key")),
+            ..connection_config(8883)
+        };
+
+        assert!(config.get_mqtt_options("localhost", "Test").is_err());
+    }
+}
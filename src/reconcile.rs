@@ -0,0 +1,538 @@
+use crate::command_payload::{
+    DaliTarget, GroupMismatch, LightParameter, ParameterMismatch, ReconcilePolicy, ReconcileReport,
+};
+use crate::config_payload::{BusConfig, Channel, Group, OperatingLevels};
+use crate::dali_commands;
+use crate::dali_manager::{DaliBusResult, DaliManager, DaliManagerError, Result};
+use error_stack::ResultExt;
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub type ReconcileProgress = Box<dyn Fn(u8)>;
+
+/// Settings read from a light's gear
+#[derive(Debug, Clone)]
+pub struct GearState {
+    pub short_address: u8,
+    pub group_mask: u16,
+    pub levels: OperatingLevels,
+    pub fade_duration: u32, // Milliseconds
+    pub fade_rate: u8,
+    pub extended_fade_time: bool, // DALI-2 gear that supports extended fade time
+}
+
+/// Lights that answered when all the short addresses of a bus were scanned
+#[derive(Debug, Default)]
+pub struct BusScan {
+    pub lights: Vec<GearState>,
+    pub conflicts: Vec<u8>, // Addresses that were answered by more than one light
+}
+
+// Number of QUERY STATUS retries before an address is considered unused (a light may miss a command, or its reply may
+// be lost)
+const QUERY_STATUS_RETRIES: u8 = 2;
+
+// Query the status of the light at a short address, the query is repeated if no light answers
+pub fn query_status(
+    dali_manager: &mut DaliManager,
+    bus: usize,
+    short_address: u8,
+) -> Result<DaliBusResult> {
+    let mut retries = 0;
+
+    loop {
+        let result = dali_manager.send_command_to_address(
+            bus,
+            dali_commands::DALI_QUERY_STATUS,
+            short_address,
+            false,
+        )?;
+
+        if matches!(
+            result,
+            DaliBusResult::Value8(_) | DaliBusResult::ReceiveCollision
+        ) || retries == QUERY_STATUS_RETRIES
+        {
+            return Ok(result);
+        }
+
+        retries += 1;
+    }
+}
+
+// Scan all short addresses and read the settings of each light that answers. Returns None if cancelled.
+pub fn scan_bus(
+    dali_manager: &mut DaliManager,
+    bus: usize,
+    progress: Option<&ReconcileProgress>,
+    cancel: Option<&AtomicBool>,
+) -> Result<Option<BusScan>> {
+    let mut scan = BusScan::default();
+
+    for short_address in 0..64u8 {
+        let into_context = || {
+            DaliManagerError::Context(format!(
+                "Reconcile: Read light {short_address} on bus {bus}"
+            ))
+        };
+
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Ok(None);
+        }
+
+        if let Some(progress) = progress {
+            progress(short_address);
+        }
+
+        match query_status(dali_manager, bus, short_address).change_context_lazy(into_context)? {
+            DaliBusResult::Value8(_) => {
+                let group_mask = dali_manager
+                    .query_group_membership(bus, short_address)
+                    .change_context_lazy(into_context)?;
+                let levels = dali_manager
+                    .query_operating_levels(bus, short_address)
+                    .change_context_lazy(into_context)?;
+                let fade_settings = dali_manager
+                    .query_fade_settings(bus, short_address)
+                    .change_context_lazy(into_context)?;
+
+                scan.lights.push(GearState {
+                    short_address,
+                    group_mask,
+                    levels,
+                    fade_duration: fade_settings.fade_duration,
+                    fade_rate: fade_settings.fade_rate,
+                    extended_fade_time: fade_settings.extended_fade_time.is_some(),
+                });
+            }
+            DaliBusResult::ReceiveCollision => scan.conflicts.push(short_address),
+            _ => {}
+        }
+    }
+
+    Ok(Some(scan))
+}
+
+fn group_addresses(group_mask: u16) -> Vec<u8> {
+    (0..16u8)
+        .filter(|group_address| group_mask & (1 << group_address) != 0)
+        .collect()
+}
+
+fn config_group_mask(bus_config: &BusConfig, short_address: u8) -> u16 {
+    bus_config
+        .groups
+        .iter()
+        .filter(|group| group.members.contains(&short_address))
+        .fold(0, |mask, group| mask | (1 << group.group_address))
+}
+
+// Fade duration that the gear has after the configured fade duration is programmed into it (gear without extended
+// fade time fades instantly if the duration is not close to a standard fade time)
+fn programmed_fade_duration(fade_duration: u32, gear: &GearState) -> u32 {
+    let (fade_time, extended_fade_time) = DaliManager::encode_fade_duration(fade_duration);
+
+    DaliManager::decode_fade_duration(
+        fade_time,
+        gear.extended_fade_time.then_some(extended_fade_time),
+    )
+}
+
+// Compare parameters that are specified in the configuration with the gear's parameters
+fn parameter_mismatches(channel: &Channel, gear: &GearState) -> Vec<ParameterMismatch> {
+    let levels = &channel.levels;
+
+    [
+        (
+            LightParameter::MaxLevel,
+            levels.max_level,
+            gear.levels.max_level,
+        ),
+        (
+            LightParameter::MinLevel,
+            levels.min_level,
+            gear.levels.min_level,
+        ),
+        (
+            LightParameter::PowerOnLevel,
+            levels.power_on_level,
+            gear.levels.power_on_level,
+        ),
+        (
+            LightParameter::SystemFailureLevel,
+            levels.system_failure_level,
+            gear.levels.system_failure_level,
+        ),
+    ]
+    .into_iter()
+    .map(|(parameter, config, gear_value)| {
+        (parameter, config.map(u32::from), gear_value.map(u32::from))
+    })
+    .chain([
+        (
+            LightParameter::FadeDuration,
+            channel
+                .fade_duration
+                .map(|fade_duration| programmed_fade_duration(fade_duration, gear)),
+            Some(gear.fade_duration),
+        ),
+        (
+            LightParameter::FadeRate,
+            channel.fade_rate.map(u32::from),
+            Some(gear.fade_rate as u32),
+        ),
+    ])
+    .filter_map(
+        |(parameter, config, gear_value)| match (config, gear_value) {
+            (Some(config), Some(gear_value)) if config != gear_value => Some(ParameterMismatch {
+                short_address: gear.short_address,
+                parameter,
+                config,
+                gear: gear_value,
+            }),
+            _ => None,
+        },
+    )
+    .collect()
+}
+
+/// Compare the bus configuration with the lights that were found by scanning the bus
+pub fn diff(bus_config: &BusConfig, scan: &BusScan) -> ReconcileReport {
+    let mut report = ReconcileReport {
+        address_conflicts: scan.conflicts.clone(),
+        ..ReconcileReport::default()
+    };
+
+    for channel in bus_config.channels.iter() {
+        let short_address = channel.short_address;
+
+        if scan.conflicts.contains(&short_address) {
+            continue;
+        }
+
+        match scan
+            .lights
+            .iter()
+            .find(|gear| gear.short_address == short_address)
+        {
+            None => report.missing_lights.push(short_address),
+            Some(gear) => {
+                let config_mask = config_group_mask(bus_config, short_address);
+
+                if config_mask != gear.group_mask {
+                    report.group_mismatches.push(GroupMismatch {
+                        short_address,
+                        config_groups: group_addresses(config_mask),
+                        gear_groups: group_addresses(gear.group_mask),
+                    });
+                }
+
+                report
+                    .parameter_mismatches
+                    .extend(parameter_mismatches(channel, gear));
+            }
+        }
+    }
+
+    report.unknown_lights = scan
+        .lights
+        .iter()
+        .map(|gear| gear.short_address)
+        .filter(|short_address| bus_config.get_channel_index(*short_address).is_none())
+        .collect();
+
+    report
+}
+
+// Program the gear according to the configuration. Lights that are not configured are only reported (a light that
+// missed the scan of the previous reconcile should not lose its address), they can be removed by RemoveShortAddress.
+// Nothing can be done about configured lights that do not answer.
+fn apply_config(
+    dali_manager: &mut DaliManager,
+    bus_config: &BusConfig,
+    report: &ReconcileReport,
+) -> Result<()> {
+    let bus = bus_config.bus;
+
+    for short_address in report.unknown_lights.iter() {
+        info!("Reconcile: Light {short_address} on bus {bus} is not in the configuration");
+    }
+
+    for mismatch in report.group_mismatches.iter() {
+        for group_address in mismatch.gear_groups.iter().copied() {
+            if !mismatch.config_groups.contains(&group_address) {
+                dali_manager.remove_from_group_and_verify(
+                    bus,
+                    group_address,
+                    mismatch.short_address,
+                )?;
+            }
+        }
+
+        for group_address in mismatch.config_groups.iter().copied() {
+            if !mismatch.gear_groups.contains(&group_address) {
+                dali_manager.add_to_group_and_verify(bus, group_address, mismatch.short_address)?;
+            }
+        }
+    }
+
+    for mismatch in report.parameter_mismatches.iter() {
+        let target = DaliTarget::Light(mismatch.short_address);
+        let level = Some(mismatch.config as u8);
+        let levels = match mismatch.parameter {
+            LightParameter::MaxLevel => OperatingLevels {
+                max_level: level,
+                ..OperatingLevels::default()
+            },
+            LightParameter::MinLevel => OperatingLevels {
+                min_level: level,
+                ..OperatingLevels::default()
+            },
+            LightParameter::PowerOnLevel => OperatingLevels {
+                power_on_level: level,
+                ..OperatingLevels::default()
+            },
+            LightParameter::SystemFailureLevel => OperatingLevels {
+                system_failure_level: level,
+                ..OperatingLevels::default()
+            },
+            LightParameter::FadeDuration => {
+                dali_manager.set_fade_duration(bus, target, mismatch.config)?;
+                continue;
+            }
+            LightParameter::FadeRate => {
+                dali_manager.set_fade_rate(bus, target, mismatch.config as u8)?;
+                continue;
+            }
+        };
+
+        dali_manager.set_operating_levels(bus, target, &levels)?;
+    }
+
+    Ok(())
+}
+
+// Set the groups a light is member of in the configuration (groups that are not configured are added)
+fn set_config_groups(bus_config: &mut BusConfig, short_address: u8, group_mask: u16) {
+    for group_address in 0..16u8 {
+        let is_member = group_mask & (1 << group_address) != 0;
+
+        match bus_config
+            .groups
+            .iter_mut()
+            .find(|group| group.group_address == group_address)
+        {
+            Some(group) => {
+                group.members.retain(|member| *member != short_address);
+
+                if is_member {
+                    group.members.push(short_address);
+                }
+            }
+            None if is_member => bus_config.groups.push(Group {
+                group_address,
+                description: format!("Group {}", group_address),
+                members: vec![short_address],
+            }),
+            None => {}
+        }
+    }
+
+    bus_config.groups.sort_by_key(|group| group.group_address);
+}
+
+/// Update the configuration according to the gear: lights that do not answer are removed, lights that are not
+/// configured are added and group membership and parameters are taken from the gear
+pub fn apply_gear(bus_config: &mut BusConfig, report: &ReconcileReport, scan: &BusScan) {
+    for short_address in report.missing_lights.iter().copied() {
+        bus_config.remove_channel(short_address);
+
+        for group in bus_config.groups.iter_mut() {
+            group.members.retain(|member| *member != short_address);
+        }
+
+        for scene in 0..16u8 {
+            bus_config.remove_from_scene(scene, short_address);
+        }
+    }
+
+    for short_address in report.unknown_lights.iter().copied() {
+        bus_config.channels.push(Channel {
+            short_address,
+            description: format!("Light {}", short_address),
+            identification: None,
+            random_address: None,
+            levels: OperatingLevels::default(),
+            fade_duration: None,
+            fade_rate: None,
+        });
+    }
+
+    bus_config
+        .channels
+        .sort_by_key(|channel| channel.short_address);
+
+    let group_updates = report
+        .group_mismatches
+        .iter()
+        .map(|mismatch| mismatch.short_address)
+        .chain(report.unknown_lights.iter().copied());
+
+    for short_address in group_updates.collect::<Vec<_>>() {
+        if let Some(gear) = scan
+            .lights
+            .iter()
+            .find(|gear| gear.short_address == short_address)
+        {
+            set_config_groups(bus_config, short_address, gear.group_mask);
+        }
+    }
+
+    for mismatch in report.parameter_mismatches.iter() {
+        let Some(index) = bus_config.get_channel_index(mismatch.short_address) else {
+            continue;
+        };
+        let channel = &mut bus_config.channels[index];
+        let level = Some(mismatch.gear as u8);
+
+        match mismatch.parameter {
+            LightParameter::MaxLevel => channel.levels.max_level = level,
+            LightParameter::MinLevel => channel.levels.min_level = level,
+            LightParameter::PowerOnLevel => channel.levels.power_on_level = level,
+            LightParameter::SystemFailureLevel => channel.levels.system_failure_level = level,
+            LightParameter::FadeDuration => channel.fade_duration = Some(mismatch.gear),
+            LightParameter::FadeRate => channel.fade_rate = level,
+        }
+    }
+}
+
+/// Compare the bus configuration with the gear on the bus, and resolve the differences according to the policy.
+/// Returns the differences that were found (None if cancelled while scanning the bus).
+pub fn reconcile(
+    dali_manager: &mut DaliManager,
+    bus_config: &mut BusConfig,
+    policy: ReconcilePolicy,
+    progress: Option<ReconcileProgress>,
+    cancel: Option<&AtomicBool>,
+) -> Result<Option<ReconcileReport>> {
+    let bus = bus_config.bus;
+    let into_context = || DaliManagerError::Context(format!("Reconcile bus {bus} ({policy:?})"));
+
+    let Some(scan) =
+        scan_bus(dali_manager, bus, progress.as_ref(), cancel).change_context_lazy(into_context)?
+    else {
+        info!("Reconcile of bus {bus} was cancelled");
+        return Ok(None);
+    };
+
+    let report = ReconcileReport {
+        policy,
+        ..diff(bus_config, &scan)
+    };
+
+    match policy {
+        ReconcilePolicy::ReportOnly => {}
+        ReconcilePolicy::ConfigWins => {
+            apply_config(dali_manager, bus_config, &report).change_context_lazy(into_context)?
+        }
+        ReconcilePolicy::GearWins => apply_gear(bus_config, &report, &scan),
+    }
+
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command_payload::{LightParameter, ParameterMismatch};
+    use crate::config_payload::BusConfig;
+    use crate::reconcile::{apply_gear, diff, parameter_mismatches, BusScan};
+    use crate::test_builders::{bus_config, channel, gear, group, scene};
+
+    fn kitchen() -> BusConfig {
+        let mut bus_config = BusConfig {
+            groups: vec![group(0, &[1, 2])],
+            scenes: vec![scene(0, &[(2, 100)])],
+            ..bus_config(&[1, 2, 3])
+        };
+
+        bus_config.channels[0].levels.max_level = Some(200);
+        bus_config
+    }
+
+    fn bus_scan() -> BusScan {
+        BusScan {
+            lights: vec![
+                gear(1, 0x0001, 254),
+                gear(3, 0x0002, 254),
+                gear(5, 0x0001, 254),
+            ],
+            conflicts: vec![7],
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let report = diff(&kitchen(), &bus_scan());
+
+        assert_eq!(report.missing_lights, vec![2]);
+        assert_eq!(report.unknown_lights, vec![5]);
+        assert_eq!(report.address_conflicts, vec![7]);
+        assert_eq!(report.group_mismatches.len(), 1);
+        assert_eq!(report.group_mismatches[0].short_address, 3);
+        assert_eq!(report.group_mismatches[0].gear_groups, vec![1]);
+        assert_eq!(
+            report.parameter_mismatches,
+            vec![ParameterMismatch {
+                short_address: 1,
+                parameter: LightParameter::MaxLevel,
+                config: 200,
+                gear: 254,
+            }]
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["parameter_mismatches"][0]["parameter"], "MaxLevel");
+    }
+
+    #[test]
+    fn test_fade_duration_of_dali1_gear() {
+        let mut light = channel(1);
+        let mut gear = gear(1, 0, 254);
+
+        // Standard fade time (4 = 2000ms) is programmed
+        light.fade_duration = Some(2000);
+        gear.fade_duration = 2000;
+        assert!(parameter_mismatches(&light, &gear).is_empty());
+
+        // Gear without extended fade time fades instantly
+        light.fade_duration = Some(300);
+        gear.fade_duration = 0;
+        assert!(parameter_mismatches(&light, &gear).is_empty());
+
+        gear.extended_fade_time = true;
+        assert_eq!(parameter_mismatches(&light, &gear)[0].config, 300);
+    }
+
+    #[test]
+    fn test_gear_wins() {
+        let mut bus_config = kitchen();
+        let scan = bus_scan();
+        let report = diff(&bus_config, &scan);
+
+        apply_gear(&mut bus_config, &report, &scan);
+
+        let addresses: Vec<u8> = bus_config
+            .channels
+            .iter()
+            .map(|channel| channel.short_address)
+            .collect();
+
+        assert_eq!(addresses, vec![1, 3, 5]);
+        assert_eq!(bus_config.channels[0].levels.max_level, Some(254));
+        assert_eq!(bus_config.groups.len(), 2);
+        assert_eq!(bus_config.groups[0].members, vec![1, 5]);
+        assert_eq!(bus_config.groups[1].members, vec![3]);
+        assert!(bus_config.scenes.is_empty());
+        assert!(diff(&bus_config, &scan).group_mismatches.is_empty());
+    }
+}
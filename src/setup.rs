@@ -1,7 +1,10 @@
-use crate::dali_manager::{DaliBusResult, MatchGroupAction};
+use crate::command_payload::DaliTarget;
+use crate::dali_manager::{AuditBusAction, AuditBusProgress, DaliBusResult, MatchGroupAction};
 use crate::Config;
 use crate::{
-    config_payload::{BusConfig, BusStatus, Channel, DaliConfig, Group},
+    config_payload::{
+        BusConfig, BusStatus, Channel, DaliConfig, Group, OperatingLevels, Scene, SceneLevel,
+    },
     dali_manager::{DaliBusIterator, DaliDeviceSelection, DaliManager},
 };
 use log::{log_enabled, Level::Trace};
@@ -39,6 +42,20 @@ impl std::fmt::Display for SetupError {
 
 impl std::error::Error for SetupError {}
 
+impl OperatingLevels {
+    pub fn is_empty(&self) -> bool {
+        *self == OperatingLevels::default()
+    }
+
+    // Update the levels that are specified in levels
+    pub fn update(&mut self, levels: &OperatingLevels) {
+        self.max_level = levels.max_level.or(self.max_level);
+        self.min_level = levels.min_level.or(self.min_level);
+        self.power_on_level = levels.power_on_level.or(self.power_on_level);
+        self.system_failure_level = levels.system_failure_level.or(self.system_failure_level);
+    }
+}
+
 impl fmt::Display for Channel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:2} - {}", self.short_address, self.description)
@@ -74,6 +91,7 @@ impl BusConfig {
             bus: bus_number,
             channels: Vec::new(),
             groups: Vec::new(),
+            scenes: Vec::new(),
         }
     }
 
@@ -81,7 +99,7 @@ impl BusConfig {
         self.channels.iter().find(|c| c.short_address == channel)
     }
 
-    fn get_channel_index(&self, short_address: u8) -> Option<usize> {
+    pub fn get_channel_index(&self, short_address: u8) -> Option<usize> {
         self.channels
             .iter()
             .position(|channel| channel.short_address == short_address)
@@ -126,6 +144,120 @@ impl BusConfig {
         }
     }
 
+    // Get the short addresses of the lights addressed by a target
+    pub fn get_target_members(&self, target: DaliTarget) -> Vec<u8> {
+        match target {
+            DaliTarget::Light(short_address) => vec![short_address],
+            DaliTarget::Group(group_address) => self
+                .groups
+                .iter()
+                .find(|g| g.group_address == group_address)
+                .map_or(Vec::new(), |g| g.members.clone()),
+            DaliTarget::Bus => self.channels.iter().map(|c| c.short_address).collect(),
+        }
+    }
+
+    // Update the configuration of the lights addressed by a target
+    pub fn update_target_channels(
+        &mut self,
+        target: DaliTarget,
+        mut update: impl FnMut(&mut Channel),
+    ) {
+        for short_address in self.get_target_members(target) {
+            if let Some(index) = self.get_channel_index(short_address) {
+                update(&mut self.channels[index]);
+            }
+        }
+    }
+
+    pub fn set_operating_levels(&mut self, target: DaliTarget, levels: &OperatingLevels) {
+        self.update_target_channels(target, |channel| channel.levels.update(levels));
+    }
+
+    // Update the configuration after the lights addressed by target were reset: reset lights are no longer members
+    // of any group or scene, and have their default levels and fade settings
+    pub fn reset_lights(&mut self, target: DaliTarget) {
+        let members = self.get_target_members(target);
+
+        for short_address in members.iter() {
+            for group in self.groups.iter_mut() {
+                group.members.retain(|member| member != short_address);
+            }
+
+            for scene in self.scenes.iter_mut() {
+                scene
+                    .levels
+                    .retain(|level| level.short_address != *short_address);
+            }
+        }
+
+        self.scenes.retain(|scene| !scene.levels.is_empty());
+
+        // Group members were already removed, so use the members that were found before
+        for short_address in members {
+            if let Some(index) = self.get_channel_index(short_address) {
+                let channel = &mut self.channels[index];
+
+                channel.levels = OperatingLevels::default();
+                channel.fade_duration = None;
+                channel.fade_rate = None;
+            }
+        }
+    }
+
+    pub fn get_scene_level(&self, scene: u8, short_address: u8) -> Option<u8> {
+        self.scenes
+            .iter()
+            .find(|s| s.scene == scene)
+            .and_then(|s| s.levels.iter().find(|l| l.short_address == short_address))
+            .map(|l| l.level)
+    }
+
+    pub fn set_scene_level(&mut self, scene: u8, short_address: u8, level: u8) {
+        let scene_index = match self.scenes.iter().position(|s| s.scene == scene) {
+            Some(index) => index,
+            None => {
+                self.scenes.push(Scene {
+                    scene,
+                    description: format!("Scene {}", scene),
+                    levels: Vec::new(),
+                });
+                self.scenes.len() - 1
+            }
+        };
+
+        let levels = &mut self.scenes[scene_index].levels;
+
+        if let Some(scene_level) = levels.iter_mut().find(|l| l.short_address == short_address) {
+            scene_level.level = level;
+        } else {
+            levels.push(SceneLevel {
+                short_address,
+                level,
+            });
+        }
+    }
+
+    pub fn remove_from_scene(&mut self, scene: u8, short_address: u8) -> bool {
+        if let Some(scene_index) = self.scenes.iter().position(|s| s.scene == scene) {
+            let levels = &mut self.scenes[scene_index].levels;
+
+            if let Some(index) = levels.iter().position(|l| l.short_address == short_address) {
+                levels.remove(index);
+
+                // Remove scene that has no lights
+                if levels.is_empty() {
+                    self.scenes.remove(scene_index);
+                }
+                true
+            } else {
+                false
+            }
+        } else {
+            false
+        }
+    }
+
     fn display_channels(&self) {
         let max_channel_name_length = self
             .channels
@@ -389,6 +521,11 @@ impl Setup {
                         dali_config.buses[bus_number].channels.push(Channel {
                             description,
                             short_address,
+                            identification: None,
+                            random_address: None,
+                            levels: OperatingLevels::default(),
+                            fade_duration: None,
+                            fade_rate: None,
                         });
                         config.save(&dali_config)?;
                     }
@@ -508,6 +645,11 @@ impl Setup {
                             dali_config.buses[bus_number].channels.push(Channel {
                                 description,
                                 short_address,
+                                identification: None,
+                                random_address: dali_bus_iterator.random_address(),
+                                levels: OperatingLevels::default(),
+                                fade_duration: None,
+                                fade_rate: None,
                             });
 
                             count += 1;
@@ -566,6 +708,11 @@ impl Setup {
                             dali_config.buses[bus_number].channels.push(Channel {
                                 description,
                                 short_address,
+                                identification: None,
+                                random_address: dali_bus_iterator.random_address(),
+                                levels: OperatingLevels::default(),
+                                fade_duration: None,
+                                fade_rate: None,
                             });
                             config.save(&dali_config)?;
 
@@ -774,6 +921,7 @@ impl Setup {
                                         }
                                     }
                                 })),
+                                None,
                             )?;
 
                             config.save(&dali_config)?;
@@ -1063,6 +1211,35 @@ impl Setup {
         Ok(dali_config)
     }
 
+    fn audit_bus(
+        config: &Config,
+        mut dali_config: DaliConfig,
+        dali_manager: &mut DaliManager,
+        bus_number: usize,
+    ) -> Result<DaliConfig, Box<dyn std::error::Error>> {
+        let progress: AuditBusProgress = Box::new(|action| match action {
+            AuditBusAction::CheckAddress(short_address) => {
+                println!("Checking short address {short_address}")
+            }
+            AuditBusAction::ConflictResolved(conflict) => println!(
+                "  Short address {} was used by more than one light, other lights moved to: {:?}",
+                conflict.short_address, conflict.moved_to
+            ),
+        });
+
+        let conflicts =
+            dali_manager.audit_bus(&mut dali_config.buses[bus_number], Some(progress), None)?;
+
+        if conflicts.is_empty() {
+            println!("No short address is used by more than one light");
+        } else {
+            println!("Resolved {} short address conflicts", conflicts.len());
+            config.save(&dali_config)?;
+        }
+
+        Ok(dali_config)
+    }
+
     fn interactive_setup_lights(
         config: &Config,
         mut dali_config: DaliConfig,
@@ -1077,7 +1254,7 @@ impl Setup {
 
         loop {
             let command = Setup::prompt_for_string(
-                "Lights - r:rename, s:set-level, q:query, g:group-membership, t:fade-Time, b:back",
+                "Lights - r:rename, s:set-level, q:query, i:device-info, d:iDentify, g:group-membership, t:fade-Time, R:Replace failed light, b:back",
                 Some("b"),
             )?;
 
@@ -1138,6 +1315,30 @@ impl Setup {
                             );
                         }
                     }
+                    'i' => {
+                        if let Some(short_address) = Setup::prompt_for_existing_short_address(
+                            &dali_config.buses[bus_number],
+                            "Address",
+                            last_short_address,
+                        )? {
+                            let device_info =
+                                dali_manager.query_device_info(bus_number, short_address)?;
+                            println!("Light {bus_number}/{short_address}:\n{device_info}");
+                            last_short_address = Some(short_address);
+                        }
+                    }
+                    'd' => {
+                        if let Some(short_address) = Setup::prompt_for_existing_short_address(
+                            &dali_config.buses[bus_number],
+                            "Identify",
+                            last_short_address,
+                        )? {
+                            let seconds = Setup::prompt_for_number("Seconds", Some(10u32))?;
+
+                            dali_manager.identify(bus_number, short_address, seconds, None)?;
+                            last_short_address = Some(short_address);
+                        }
+                    }
                     'g' => {
                         if let Some(short_address) = Setup::prompt_for_existing_short_address(
                             &dali_config.buses[bus_number],
@@ -1166,6 +1367,21 @@ impl Setup {
                             }
                         }
                     }
+                    'R' => {
+                        if let Some(short_address) = Setup::prompt_for_existing_short_address(
+                            &dali_config.buses[bus_number],
+                            "Replace",
+                            last_short_address,
+                        )? {
+                            println!(
+                                "Install the new light (without short address) before replacing"
+                            );
+                            dali_manager
+                                .replace_light(&mut dali_config.buses[bus_number], short_address)?;
+                            println!("Light {short_address} was replaced");
+                            config.save(&dali_config)?;
+                        }
+                    }
                     '?' => dali_config.buses[bus_number].display(),
                     _ => println!("Invalid command"),
                 }
@@ -1211,7 +1427,7 @@ impl Setup {
         } else {
             loop {
                 dali_config.buses[bus_number].display();
-                let command = Setup::prompt_for_string("Bus - r:rename, a:assign addresses, l:lights, g:groups, q:query, Q=Scan bus, f:fix, b:back", Some("b"))?;
+                let command = Setup::prompt_for_string("Bus - r:rename, a:assign addresses, l:lights, g:groups, q:query, Q=Scan bus, f:fix, A:audit addresses, b:back", Some("b"))?;
 
                 if let Some(command) = command.chars().next() {
                     match command {
@@ -1253,6 +1469,10 @@ impl Setup {
                             dali_config =
                                 Setup::fix_config(config, dali_config, dali_manager, bus_number)?
                         }
+                        'A' => {
+                            dali_config =
+                                Setup::audit_bus(config, dali_config, dali_manager, bus_number)?
+                        }
                         _ => println!("Invalid command"),
                     }
                 }
@@ -1451,3 +1671,88 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::command_payload::DaliTarget;
+    use crate::config_payload::{BusConfig, OperatingLevels};
+    use crate::test_builders::{bus_config, group, scene};
+
+    // Lights 1, 2 and 3 with programmed levels and fade settings, in groups 0 (1, 2) and 1 (2, 3), scene 0 has all
+    // lights and scene 1 has only light 1
+    fn programmed_bus_config() -> BusConfig {
+        let mut bus_config = BusConfig {
+            groups: vec![group(0, &[1, 2]), group(1, &[2, 3])],
+            scenes: vec![
+                scene(0, &[(1, 100), (2, 100), (3, 100)]),
+                scene(1, &[(1, 50)]),
+            ],
+            ..bus_config(&[1, 2, 3])
+        };
+
+        for channel in bus_config.channels.iter_mut() {
+            channel.levels = OperatingLevels {
+                max_level: Some(200),
+                min_level: Some(10),
+                ..OperatingLevels::default()
+            };
+            channel.fade_duration = Some(2000);
+            channel.fade_rate = Some(7);
+        }
+
+        bus_config
+    }
+
+    fn is_reset(bus_config: &BusConfig, short_address: u8) -> bool {
+        let channel = bus_config.find_member(short_address).unwrap();
+
+        channel.levels.is_empty()
+            && channel.fade_duration.is_none()
+            && channel.fade_rate.is_none()
+            && bus_config
+                .groups
+                .iter()
+                .all(|group| !group.members.contains(&short_address))
+            && (0..16).all(|scene| bus_config.get_scene_level(scene, short_address).is_none())
+    }
+
+    #[test]
+    fn test_reset_light() {
+        let mut bus_config = programmed_bus_config();
+
+        bus_config.reset_lights(DaliTarget::Light(1));
+
+        assert!(is_reset(&bus_config, 1));
+        assert!(!is_reset(&bus_config, 2) && !is_reset(&bus_config, 3));
+        assert_eq!(bus_config.groups[0].members, vec![2]);
+
+        // Scene 1 has no lights left
+        assert_eq!(bus_config.scenes.len(), 1);
+        assert_eq!(bus_config.get_scene_level(0, 2), Some(100));
+    }
+
+    #[test]
+    fn test_reset_group() {
+        let mut bus_config = programmed_bus_config();
+
+        bus_config.reset_lights(DaliTarget::Group(1));
+
+        assert!(is_reset(&bus_config, 2) && is_reset(&bus_config, 3));
+        assert!(!is_reset(&bus_config, 1));
+        assert_eq!(bus_config.groups[0].members, vec![1]);
+        assert!(bus_config.groups[1].members.is_empty());
+        assert_eq!(bus_config.get_scene_level(0, 1), Some(100));
+        assert_eq!(bus_config.get_scene_level(1, 1), Some(50));
+    }
+
+    #[test]
+    fn test_reset_bus() {
+        let mut bus_config = programmed_bus_config();
+
+        bus_config.reset_lights(DaliTarget::Bus);
+
+        assert!((1..=3).all(|short_address| is_reset(&bus_config, short_address)));
+        assert!(bus_config.scenes.is_empty());
+        assert_eq!(bus_config.channels[0].levels, OperatingLevels::default());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::config_payload::{
+    BusConfig, BusStatus, Channel, GearIdentification, Group, OperatingLevels, Scene, SceneLevel,
+};
+use crate::reconcile::GearState;
+
+// Builders of the configurations (and gear state) used by the unit tests
+
+// Light with default settings that was not identified
+pub fn channel(short_address: u8) -> Channel {
+    Channel {
+        short_address,
+        description: format!("Light {}", short_address),
+        identification: None,
+        random_address: None,
+        levels: OperatingLevels::default(),
+        fade_duration: None,
+        fade_rate: None,
+    }
+}
+
+// Identification (memory bank 0) of gear with the given serial number
+pub fn identification(serial: u64) -> GearIdentification {
+    GearIdentification {
+        gtin: 0x12345678,
+        firmware_version: "1.0".to_owned(),
+        identification_number: serial,
+        hardware_version: None,
+        version_101: None,
+        version_102: None,
+    }
+}
+
+pub fn group(group_address: u8, members: &[u8]) -> Group {
+    Group {
+        group_address,
+        description: format!("Group {}", group_address),
+        members: members.to_vec(),
+    }
+}
+
+// Scene with the (short address, level) of each light in it
+pub fn scene(scene: u8, levels: &[(u8, u8)]) -> Scene {
+    Scene {
+        scene,
+        description: format!("Scene {}", scene),
+        levels: levels
+            .iter()
+            .map(|&(short_address, level)| SceneLevel {
+                short_address,
+                level,
+            })
+            .collect(),
+    }
+}
+
+// Active bus 0 with default lights at the given short addresses (no groups or scenes)
+pub fn bus_config(short_addresses: &[u8]) -> BusConfig {
+    let mut bus_config = BusConfig::new(0, BusStatus::Active);
+
+    bus_config.channels = short_addresses.iter().copied().map(channel).collect();
+    bus_config
+}
+
+// Gear with default settings (apart from its max level)
+pub fn gear(short_address: u8, group_mask: u16, max_level: u8) -> GearState {
+    GearState {
+        short_address,
+        group_mask,
+        levels: OperatingLevels {
+            max_level: Some(max_level),
+            min_level: Some(1),
+            power_on_level: Some(254),
+            system_failure_level: Some(254),
+        },
+        fade_duration: 0,
+        fade_rate: 7,
+        extended_fade_time: false,
+    }
+}